@@ -0,0 +1,53 @@
+//! Batches of inserts and deletes, written atomically.
+
+use crate::{ByteStr, ByteString};
+
+/// Batch of inserts and deletes, written with `RiaKV::write_batch` so that either all or none
+/// of them are read back, even if the write is torn by a crash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    /// key value pairs in the order they are written, with tombstones for deletes, along with
+    /// the timestamp to write them at, if not the time of the write
+    pairs: Vec<(ByteString, ByteString, Option<u64>)>,
+}
+
+impl WriteBatch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        WriteBatch::default()
+    }
+
+    /// Adds an insert of the given key value pair to this batch.
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) {
+        self.pairs.push((key.to_vec(), value.to_vec(), None));
+    }
+
+    /// Adds an insert of the given key value pair, written at the given timestamp in
+    /// microseconds since the unix epoch, to this batch.
+    pub(crate) fn insert_at(&mut self, key: &ByteStr, value: &ByteStr, timestamp: u64) {
+        self.pairs
+            .push((key.to_vec(), value.to_vec(), Some(timestamp)));
+    }
+
+    /// Adds a delete of the given key to this batch.
+    #[inline]
+    pub fn delete(&mut self, key: &ByteStr) {
+        self.insert(key, b"")
+    }
+
+    /// Returns the number of inserts and deletes in this batch.
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Returns whether this batch holds no inserts or deletes.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Returns the key value pairs of this batch, in order, along with the timestamp to write
+    /// them at, if not the time of the write.
+    pub(crate) fn pairs(&self) -> &[(ByteString, ByteString, Option<u64>)] {
+        &self.pairs
+    }
+}
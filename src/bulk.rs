@@ -0,0 +1,197 @@
+//! Bulk loading of key value pairs into a fresh storage file.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+use crate::crypto::Cipher;
+use crate::header::Header;
+use crate::keyspace::DEFAULT_KEYSPACE_ID;
+use crate::record;
+use crate::{ByteStr, ByteString};
+
+/// Default capacity of the buffer records are written through while bulk loading.
+pub const DEFAULT_BULK_BUFFER_LEN: usize = 8 << 20;
+
+/// Handling of pairs with duplicate keys while bulk loading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dedup {
+    /// every pair is written, as with `RiaKV::insert`; later pairs shadow earlier ones
+    #[default]
+    None,
+
+    /// pairs with the same key are adjacent, e.g. because the input is sorted by key: only the
+    /// last pair of every run is written, without buffering the input
+    Adjacent,
+
+    /// pairs with the same key may be anywhere in the input: the whole input is buffered in
+    /// memory, and only the last pair for every key is written, in input order
+    All,
+}
+
+/// Options for `RiaKV::bulk_load`.
+#[derive(Debug, Clone, Copy)]
+pub struct BulkLoadOptions {
+    /// handling of pairs with duplicate keys
+    pub dedup: Dedup,
+
+    /// capacity of the buffer records are written through
+    pub buffer_len: usize,
+}
+
+impl Default for BulkLoadOptions {
+    fn default() -> Self {
+        BulkLoadOptions {
+            dedup: Dedup::default(),
+            buffer_len: DEFAULT_BULK_BUFFER_LEN,
+        }
+    }
+}
+
+/// Writer appending records through a single buffer, tracking their positions itself instead
+/// of seeking, and building the index as it goes.
+pub(crate) struct BulkWriter<'a, W: Write> {
+    w: BufWriter<W>,
+    header: &'a Header,
+    cipher: Option<&'a Cipher>,
+
+    /// minimum number of bytes between sync markers, if sync markers are written
+    sync_marker_interval: Option<u64>,
+
+    /// position of the last sync marker, or of the first record written
+    last_sync_marker: u64,
+
+    /// position the next record is written at
+    position: u64,
+
+    index: HashMap<ByteString, u64>,
+}
+
+impl<'a, W: Write> BulkWriter<'a, W> {
+    /// Creates a writer appending records to the given storage, positioned at `position`.
+    pub(crate) fn new(
+        w: W,
+        buffer_len: usize,
+        header: &'a Header,
+        cipher: Option<&'a Cipher>,
+        sync_marker_interval: Option<u64>,
+        position: u64,
+    ) -> Self {
+        BulkWriter {
+            w: BufWriter::with_capacity(buffer_len, w),
+            header,
+            cipher,
+            sync_marker_interval,
+            last_sync_marker: position,
+            position,
+            index: HashMap::new(),
+        }
+    }
+
+    /// Writes every given pair, deduplicated as requested.
+    pub(crate) fn write_pairs<I, K, V>(&mut self, pairs: I, dedup: Dedup) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<ByteStr>,
+        V: AsRef<ByteStr>,
+    {
+        match dedup {
+            Dedup::None => {
+                for (key, value) in pairs {
+                    self.write(key.as_ref(), value.as_ref())?;
+                }
+            }
+            Dedup::Adjacent => {
+                let mut pending: Option<(K, V)> = None;
+
+                for (key, value) in pairs {
+                    if let Some((pending_key, pending_value)) = pending.take() {
+                        if pending_key.as_ref() != key.as_ref() {
+                            self.write_last(pending_key.as_ref(), pending_value.as_ref())?;
+                        }
+                    }
+
+                    pending = Some((key, value));
+                }
+
+                if let Some((key, value)) = pending {
+                    self.write_last(key.as_ref(), value.as_ref())?;
+                }
+            }
+            Dedup::All => {
+                let pairs: Vec<(K, V)> = pairs.into_iter().collect();
+
+                let mut last: HashMap<&ByteStr, usize> = HashMap::with_capacity(pairs.len());
+                for (i, (key, _)) in pairs.iter().enumerate() {
+                    last.insert(key.as_ref(), i);
+                }
+
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if last[key.as_ref()] == i {
+                        self.write_last(key.as_ref(), value.as_ref())?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last pair for a key. As no earlier record for the key has been written, a
+    /// tombstone is skipped entirely.
+    fn write_last(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        if value.is_empty() {
+            return Ok(());
+        }
+
+        self.write(key, value)
+    }
+
+    /// Appends a record for the given pair, preceded by a sync marker if one is due, and
+    /// updates the index.
+    fn write(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        if let Some(interval) = self.sync_marker_interval {
+            if self.position - self.last_sync_marker >= interval {
+                let (record, data) = record::sync_marker(self.header);
+
+                record.write_to(&mut self.w, self.header)?;
+                self.w.write_all(data)?;
+
+                self.last_sync_marker = self.position;
+                self.position += self.header.record_header_len() + record.data_len();
+            }
+        }
+
+        let (record, data) = record::encode_record(
+            self.header,
+            self.cipher,
+            DEFAULT_KEYSPACE_ID,
+            key,
+            value,
+            record::now(),
+        )?;
+
+        record.write_to(&mut self.w, self.header)?;
+        for part in &data {
+            self.w.write_all(part)?;
+        }
+
+        if !value.is_empty() {
+            self.index.insert(key.to_vec(), self.position);
+        } else {
+            self.index.remove(key);
+        }
+
+        self.position += self.header.record_header_len() + record.data_len();
+        Ok(())
+    }
+
+    /// Flushes the buffered records, returning the index built along with the position of the
+    /// last sync marker.
+    pub(crate) fn finish(mut self) -> io::Result<(HashMap<ByteString, u64>, u64)> {
+        self.w.flush()?;
+
+        Ok((self.index, self.last_sync_marker))
+    }
+}
@@ -0,0 +1,105 @@
+//! Checksum algorithms used for validating the records stored in a `RiaKV` store.
+
+use std::io;
+use std::io::prelude::*;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc::crc32;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::ByteStr;
+
+/// Checksum algorithm used for all the records in a storage file. The algorithm is
+/// identified in the file header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumAlgorithm {
+    /// `crc32` with the IEEE polynomial. Used by storage files without a header.
+    #[default]
+    Crc32,
+
+    /// `crc32` with the Castagnoli polynomial, hardware accelerated where supported.
+    Crc32c,
+
+    /// 64 bit `xxh3` hash.
+    Xxh3,
+}
+
+impl ChecksumAlgorithm {
+    /// Returns the identifier used for this algorithm in the file header.
+    pub fn id(self) -> u8 {
+        match self {
+            ChecksumAlgorithm::Crc32 => 0,
+            ChecksumAlgorithm::Crc32c => 1,
+            ChecksumAlgorithm::Xxh3 => 2,
+        }
+    }
+
+    /// Returns the algorithm with the given file header identifier, if any.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(ChecksumAlgorithm::Crc32),
+            1 => Some(ChecksumAlgorithm::Crc32c),
+            2 => Some(ChecksumAlgorithm::Xxh3),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bytes occupied by a checksum in a record.
+    pub fn width(self) -> u64 {
+        match self {
+            ChecksumAlgorithm::Crc32 | ChecksumAlgorithm::Crc32c => 4,
+            ChecksumAlgorithm::Xxh3 => 8,
+        }
+    }
+
+    /// Returns a new hasher for incrementally computing a checksum.
+    pub fn hasher(self) -> ChecksumHasher {
+        match self {
+            ChecksumAlgorithm::Crc32 => ChecksumHasher::Crc32(0),
+            ChecksumAlgorithm::Crc32c => ChecksumHasher::Crc32c(0),
+            ChecksumAlgorithm::Xxh3 => ChecksumHasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+
+    /// Reads a checksum of this algorithm's width in little endian format.
+    pub fn read_checksum<R: Read>(self, f: &mut R) -> io::Result<u64> {
+        match self.width() {
+            4 => Ok(f.read_u32::<LittleEndian>()? as u64),
+            _ => f.read_u64::<LittleEndian>(),
+        }
+    }
+
+    /// Writes a checksum of this algorithm's width in little endian format.
+    pub fn write_checksum<W: Write>(self, f: &mut W, checksum: u64) -> io::Result<()> {
+        match self.width() {
+            4 => f.write_u32::<LittleEndian>(checksum as u32),
+            _ => f.write_u64::<LittleEndian>(checksum),
+        }
+    }
+}
+
+/// Incremental checksum computation for one of the supported `ChecksumAlgorithm`s.
+pub enum ChecksumHasher {
+    Crc32(u32),
+    Crc32c(u32),
+    Xxh3(Box<Xxh3>),
+}
+
+impl ChecksumHasher {
+    /// Feeds the given bytes into the checksum.
+    pub fn write(&mut self, bytes: &ByteStr) {
+        match self {
+            ChecksumHasher::Crc32(crc) => *crc = crc32::update(*crc, &crc32::IEEE_TABLE, bytes),
+            ChecksumHasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, bytes),
+            ChecksumHasher::Xxh3(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// Returns the checksum of all the bytes written so far.
+    pub fn finish(&self) -> u64 {
+        match self {
+            ChecksumHasher::Crc32(crc) | ChecksumHasher::Crc32c(crc) => *crc as u64,
+            ChecksumHasher::Xxh3(hasher) => hasher.digest(),
+        }
+    }
+}
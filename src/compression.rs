@@ -0,0 +1,130 @@
+//! Compression algorithms for the values stored in a `RiaKV` store.
+
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+
+use crate::{ByteStr, ByteString};
+
+/// Compression algorithm applied to a value before it is written to the storage file. Values
+/// are compressed as self contained `lz4` or `zstd` frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// values are stored as is
+    #[default]
+    None,
+
+    /// `lz4` frame compression, favouring speed
+    Lz4,
+
+    /// `zstd` compression at the default level, favouring compression ratio
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the identifier used for this algorithm in the file and record headers.
+    pub fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    /// Returns the algorithm with the given identifier, if any.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Compresses the given value.
+    pub fn compress(self, value: &ByteStr) -> io::Result<ByteString> {
+        let mut encoder = self.encoder(Vec::with_capacity(value.len()))?;
+        encoder.write_all(value)?;
+        encoder.finish()
+    }
+
+    /// Decompresses the given stored value.
+    pub fn decompress(self, stored: &ByteStr) -> io::Result<ByteString> {
+        let mut value = ByteString::with_capacity(stored.len());
+        self.decoder(stored)?.read_to_end(&mut value)?;
+        Ok(value)
+    }
+
+    /// Returns an encoder compressing everything written to it into the given writer.
+    pub fn encoder<W: Write>(self, w: W) -> io::Result<Encoder<W>> {
+        Ok(match self {
+            Compression::None => Encoder::None(w),
+            Compression::Lz4 => Encoder::Lz4(FrameEncoder::new(w)),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(w, 0)?),
+        })
+    }
+
+    /// Returns a decoder decompressing the contents of the given reader.
+    pub fn decoder<R: Read>(self, r: R) -> io::Result<Decoder<R>> {
+        Ok(match self {
+            Compression::None => Decoder::None(r),
+            Compression::Lz4 => Decoder::Lz4(FrameDecoder::new(r)),
+            Compression::Zstd => Decoder::Zstd(zstd::Decoder::new(r)?),
+        })
+    }
+}
+
+/// Streaming compression into a writer for one of the supported `Compression` algorithms.
+pub enum Encoder<W: Write> {
+    None(W),
+    Lz4(FrameEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Completes the compressed stream and returns the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(w) => Ok(w),
+            Encoder::Lz4(encoder) => encoder.finish().map_err(io::Error::other),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(w) => w.write(buf),
+            Encoder::Lz4(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(w) => w.flush(),
+            Encoder::Lz4(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Streaming decompression from a reader for one of the supported `Compression` algorithms.
+pub enum Decoder<R: Read> {
+    None(R),
+    Lz4(FrameDecoder<R>),
+    Zstd(zstd::Decoder<'static, BufReader<R>>),
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decoder::None(r) => r.read(buf),
+            Decoder::Lz4(decoder) => decoder.read(buf),
+            Decoder::Zstd(decoder) => decoder.read(buf),
+        }
+    }
+}
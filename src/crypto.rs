@@ -0,0 +1,140 @@
+//! Authenticated encryption of the records and the persisted index of a `RiaKV` store.
+
+use std::fmt;
+use std::io;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::{ByteStr, ByteString};
+
+/// Length of the random nonce stored with every encrypted payload.
+pub const NONCE_LEN: usize = 12;
+
+/// Length of the authentication tag appended to every encrypted payload.
+pub const TAG_LEN: usize = 16;
+
+/// Identifier of `ChaCha20-Poly1305` in the file header, the only supported algorithm.
+pub const CHACHA20_POLY1305_ID: u8 = 1;
+
+/// 256 bit key used for encrypting a storage file, along with an identifier which is
+/// recorded in the file header. The identifier allows telling which key a storage file
+/// was encrypted with when keys are rotated.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct EncryptionKey {
+    /// identifier of the key
+    pub id: u32,
+
+    key: [u8; 32],
+}
+
+impl EncryptionKey {
+    /// Creates a new encryption key with the given identifier and key material.
+    pub fn new(id: u32, key: [u8; 32]) -> Self {
+        EncryptionKey { id, key }
+    }
+
+    /// Generates a new random encryption key with the given identifier.
+    pub fn generate(id: u32) -> Self {
+        EncryptionKey {
+            id,
+            key: ChaCha20Poly1305::generate_key(&mut OsRng).into(),
+        }
+    }
+
+    /// Returns the key material.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.key
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `ChaCha20-Poly1305` cipher for sealing payloads with a fresh random nonce each.
+#[derive(Clone)]
+pub struct Cipher {
+    key_id: u32,
+    aead: ChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Creates a new cipher with the given key.
+    pub fn new(key: &EncryptionKey) -> Self {
+        Cipher {
+            key_id: key.id,
+            aead: ChaCha20Poly1305::new(Key::from_slice(&key.key)),
+        }
+    }
+
+    /// Returns the identifier of the key used by this cipher.
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    /// Encrypts and authenticates the given plaintext, along with the given associated data,
+    /// which is authenticated but not encrypted. Returns the nonce followed by the ciphertext
+    /// and the authentication tag.
+    pub fn seal(&self, plaintext: &ByteStr, aad: &ByteStr) -> io::Result<ByteString> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let ciphertext = self
+            .aead
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| io::Error::other("encryption failed"))?;
+
+        let mut sealed = ByteString::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        Ok(sealed)
+    }
+
+    /// Decrypts a payload sealed with `Cipher::seal`, verifying its authenticity along with
+    /// the given associated data. Fails with an `InvalidData` error if the payload was sealed
+    /// with a different key or has been tampered with.
+    pub fn open(&self, sealed: &ByteStr, aad: &ByteStr) -> io::Result<ByteString> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "encrypted payload too short",
+            ));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+        self.aead
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "decryption failed: wrong key or tampered data",
+                )
+            })
+    }
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,55 @@
+//! Differences between the live key value pairs of two stores, and merging of stores.
+
+use crate::ByteString;
+
+/// Differences between the live key value pairs of two stores, returned by `RiaKV::diff`. Keys
+/// are ordered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreDiff {
+    /// live keys only found in the left store
+    pub only_left: Vec<ByteString>,
+
+    /// live keys only found in the right store
+    pub only_right: Vec<ByteString>,
+
+    /// live keys found in both stores, with different values
+    pub changed: Vec<ByteString>,
+
+    /// number of live keys found in both stores, with the same value
+    pub unchanged: u64,
+}
+
+impl StoreDiff {
+    /// Returns whether both stores hold the same live key value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.only_left.is_empty() && self.only_right.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Policy resolving keys with different values in both stores, for `RiaKV::merge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// the value of the left store, the store merged into, is kept
+    LeftWins,
+
+    /// the value of the right store replaces the value of the left store
+    RightWins,
+
+    /// the value written last, according to the timestamps of the records, is kept; the value
+    /// of the left store is kept for equal timestamps. Records migrated from format versions
+    /// without timestamps have a timestamp of `0`, so they lose against any newer record.
+    LatestTimestamp,
+}
+
+/// Summary of a merge, returned by `RiaKV::merge`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    /// number of keys only found in the right store, inserted into the left store
+    pub inserted: u64,
+
+    /// number of keys with different values, overwritten with the value of the right store
+    pub overwritten: u64,
+
+    /// number of keys with different values, keeping the value of the left store
+    pub kept: u64,
+}
@@ -0,0 +1,305 @@
+//! Portable dumps of the key value pairs of a `RiaKV` store.
+//!
+//! Unlike the storage file, a dump does not depend on the options of the store it was written
+//! from: keys and values are neither compressed nor encrypted, and records are not positioned.
+//! A dump starts with a preamble:
+//! ```text
+//! ┌───────┬─────────┬───────┐
+//! │ magic │ version │ flags │
+//! └───────┴─────────┴───────┘
+//!     8        2        2
+//! ```
+//!
+//! followed by a sequence of entries, each starting with its kind:
+//! ```text
+//! keyspace: ┌──────┬────┬─────────────┬──────┐
+//!           │ kind │ id │ name length │ name │
+//!           └──────┴────┴─────────────┴──────┘
+//!              1     4         4
+//!
+//! pair:     ┌──────┬──────────┬────────────┬──────────────┬─────┬───────┐
+//!           │ kind │ keyspace │ key length │ value length │ key │ value │
+//!           └──────┴──────────┴────────────┴──────────────┴─────┴───────┘
+//!              1        4           4              4
+//!
+//! end:      ┌──────┬───────┬──────────┐
+//!           │ kind │ pairs │ checksum │
+//!           └──────┴───────┴──────────┘
+//!              1       8        8
+//! ```
+//!
+//! Keyspace entries declare the named keyspaces referenced by the pairs following them; pairs of
+//! the default keyspace reference keyspace `0`. Pairs with an empty value are tombstones, which
+//! are only found in dumps with history. The end entry holds the number of pairs in the dump,
+//! and the `xxh3` checksum of every byte of the dump preceding the checksum.
+
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::checksum::{ChecksumAlgorithm, ChecksumHasher};
+use crate::{ByteStr, ByteString};
+
+/// Magic bytes identifying a dump.
+pub const DUMP_MAGIC: &[u8; 8] = b"RIAKVDMP";
+
+/// Latest dump format version.
+pub const DUMP_VERSION: u16 = 1;
+
+/// Dump flag set for dumps holding every record in the order written, instead of only the
+/// live pairs.
+const FLAG_HISTORY: u16 = 0b1;
+
+const KEYSPACE: u8 = 1;
+const PAIR: u8 = 2;
+const END: u8 = 0xff;
+
+/// Options for `RiaKV::dump`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DumpOptions {
+    /// whether to dump every record in the order written, including overwritten values and
+    /// tombstones, instead of only the latest value of every live key
+    pub history: bool,
+}
+
+/// Summary of a dump written by `RiaKV::dump` or read by `RiaKV::restore`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DumpSummary {
+    /// whether the dump holds every record in the order written
+    pub history: bool,
+
+    /// number of named keyspaces
+    pub keyspaces: u64,
+
+    /// number of key value pairs, excluding tombstones
+    pub pairs: u64,
+
+    /// number of tombstones
+    pub tombstones: u64,
+}
+
+/// Entry of a dump, as read by `DumpReader`.
+pub(crate) enum DumpEntry {
+    Keyspace(u32, String),
+    Pair(u32, ByteString, ByteString),
+}
+
+/// Writer of a dump, hashing everything written.
+pub(crate) struct DumpWriter<W: Write> {
+    w: BufWriter<W>,
+    hasher: ChecksumHasher,
+    summary: DumpSummary,
+}
+
+impl<W: Write> DumpWriter<W> {
+    /// Creates a writer, writing the preamble.
+    pub(crate) fn new(w: W, history: bool) -> io::Result<Self> {
+        let mut writer = DumpWriter {
+            w: BufWriter::new(w),
+            hasher: ChecksumAlgorithm::Xxh3.hasher(),
+            summary: DumpSummary {
+                history,
+                ..DumpSummary::default()
+            },
+        };
+
+        let flags = if history { FLAG_HISTORY } else { 0 };
+
+        let mut preamble = DUMP_MAGIC.to_vec();
+        preamble.write_u16::<LittleEndian>(DUMP_VERSION)?;
+        preamble.write_u16::<LittleEndian>(flags)?;
+        writer.write(&preamble)?;
+
+        Ok(writer)
+    }
+
+    /// Writes an entry declaring the named keyspace with the given identifier.
+    pub(crate) fn write_keyspace(&mut self, id: u32, name: &str) -> io::Result<()> {
+        let mut entry = vec![KEYSPACE];
+        entry.write_u32::<LittleEndian>(id)?;
+        entry.write_u32::<LittleEndian>(name.len() as u32)?;
+        entry.extend_from_slice(name.as_bytes());
+        self.write(&entry)?;
+
+        self.summary.keyspaces += 1;
+        Ok(())
+    }
+
+    /// Writes an entry for the given key value pair of the given keyspace.
+    pub(crate) fn write_pair(
+        &mut self,
+        keyspace: u32,
+        key: &ByteStr,
+        value: &ByteStr,
+    ) -> io::Result<()> {
+        let mut entry = vec![PAIR];
+        entry.write_u32::<LittleEndian>(keyspace)?;
+        entry.write_u32::<LittleEndian>(key.len() as u32)?;
+        entry.write_u32::<LittleEndian>(value.len() as u32)?;
+        self.write(&entry)?;
+        self.write(key)?;
+        self.write(value)?;
+
+        if value.is_empty() {
+            self.summary.tombstones += 1;
+        } else {
+            self.summary.pairs += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the end entry and flushes the dump, returning its summary.
+    pub(crate) fn finish(mut self) -> io::Result<DumpSummary> {
+        let mut entry = vec![END];
+        entry.write_u64::<LittleEndian>(self.summary.pairs + self.summary.tombstones)?;
+        self.write(&entry)?;
+
+        let checksum = self.hasher.finish();
+        self.w.write_u64::<LittleEndian>(checksum)?;
+        self.w.flush()?;
+
+        Ok(self.summary)
+    }
+
+    fn write(&mut self, bytes: &ByteStr) -> io::Result<()> {
+        self.hasher.write(bytes);
+        self.w.write_all(bytes)
+    }
+}
+
+/// Reader of a dump, hashing everything read and verifying the checksum at the end entry.
+pub(crate) struct DumpReader<R: Read> {
+    r: BufReader<R>,
+    hasher: ChecksumHasher,
+    summary: DumpSummary,
+    ended: bool,
+}
+
+impl<R: Read> DumpReader<R> {
+    /// Creates a reader, reading and checking the preamble.
+    pub(crate) fn new(r: R) -> io::Result<Self> {
+        let mut reader = DumpReader {
+            r: BufReader::new(r),
+            hasher: ChecksumAlgorithm::Xxh3.hasher(),
+            summary: DumpSummary::default(),
+            ended: false,
+        };
+
+        let preamble = reader.read(DUMP_MAGIC.len() as u64 + 4)?;
+        if !preamble.starts_with(DUMP_MAGIC) {
+            return Err(invalid_dump("missing dump magic bytes"));
+        }
+
+        let mut fields = &preamble[DUMP_MAGIC.len()..];
+        let version = fields.read_u16::<LittleEndian>()?;
+        let flags = fields.read_u16::<LittleEndian>()?;
+
+        if version != DUMP_VERSION {
+            return Err(invalid_dump(format!(
+                "unsupported dump version {} (supported version is {})",
+                version, DUMP_VERSION
+            )));
+        }
+
+        if flags & !FLAG_HISTORY != 0 {
+            return Err(invalid_dump(format!("unknown dump flags {:016b}", flags)));
+        }
+
+        reader.summary.history = flags & FLAG_HISTORY != 0;
+
+        Ok(reader)
+    }
+
+    /// Reads the next entry, or `None` once the end entry has been read and the checksum
+    /// verified.
+    pub(crate) fn next_entry(&mut self) -> io::Result<Option<DumpEntry>> {
+        if self.ended {
+            return Ok(None);
+        }
+
+        match self.read(1)?[0] {
+            KEYSPACE => {
+                let mut fields = &self.read(8)?[..];
+                let id = fields.read_u32::<LittleEndian>()?;
+                let name_len = fields.read_u32::<LittleEndian>()?;
+
+                let name = String::from_utf8(self.read(name_len as u64)?).map_err(invalid_dump)?;
+
+                self.summary.keyspaces += 1;
+                Ok(Some(DumpEntry::Keyspace(id, name)))
+            }
+            PAIR => {
+                let mut fields = &self.read(12)?[..];
+                let keyspace = fields.read_u32::<LittleEndian>()?;
+                let key_len = fields.read_u32::<LittleEndian>()?;
+                let value_len = fields.read_u32::<LittleEndian>()?;
+
+                let key = self.read(key_len as u64)?;
+                let value = self.read(value_len as u64)?;
+
+                if value.is_empty() {
+                    self.summary.tombstones += 1;
+                } else {
+                    self.summary.pairs += 1;
+                }
+
+                Ok(Some(DumpEntry::Pair(keyspace, key, value)))
+            }
+            END => {
+                let pairs = (&self.read(8)?[..]).read_u64::<LittleEndian>()?;
+                let expected = self.hasher.finish();
+                let checksum = self.r.read_u64::<LittleEndian>().map_err(truncated)?;
+
+                if checksum != expected {
+                    return Err(invalid_dump("dump checksum mismatch"));
+                }
+
+                if pairs != self.summary.pairs + self.summary.tombstones {
+                    return Err(invalid_dump("number of pairs does not match the dump end"));
+                }
+
+                self.ended = true;
+                Ok(None)
+            }
+            kind => Err(invalid_dump(format!("unknown dump entry kind {}", kind))),
+        }
+    }
+
+    /// Returns the summary of the entries read so far.
+    pub(crate) fn summary(&self) -> DumpSummary {
+        self.summary
+    }
+
+    /// Reads and hashes exactly `len` bytes.
+    fn read(&mut self, len: u64) -> io::Result<ByteString> {
+        let mut bytes = ByteString::new();
+        (&mut self.r).take(len).read_to_end(&mut bytes)?;
+
+        if bytes.len() as u64 != len {
+            return Err(truncated(io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        self.hasher.write(&bytes);
+        Ok(bytes)
+    }
+}
+
+fn truncated(err: io::Error) -> io::Error {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "dump truncated before its end",
+        ),
+        _ => err,
+    }
+}
+
+fn invalid_dump<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -0,0 +1,153 @@
+//! Human readable export and import of the key value pairs stored in a `RiaKV` store.
+
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{ByteStr, ByteString};
+
+/// Encoding of binary keys and values as text in exported documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// keys and values are written as is, failing for bytes which are not valid UTF-8
+    #[default]
+    Utf8,
+
+    /// lowercase hexadecimal digits
+    Hex,
+
+    /// standard base64 with padding
+    Base64,
+}
+
+impl Encoding {
+    /// Returns the name of this encoding, as used in exported JSON documents.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf8",
+            Encoding::Hex => "hex",
+            Encoding::Base64 => "base64",
+        }
+    }
+
+    /// Encodes the given bytes as text. Fails with an `InvalidData` error if the bytes cannot
+    /// be represented with this encoding.
+    pub fn encode(self, bytes: &ByteStr) -> io::Result<String> {
+        match self {
+            Encoding::Utf8 => String::from_utf8(bytes.to_vec())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Encoding::Hex => Ok(hex::encode(bytes)),
+            Encoding::Base64 => Ok(BASE64.encode(bytes)),
+        }
+    }
+
+    /// Decodes bytes from the given text. Fails with an `InvalidData` error if the text is
+    /// not valid for this encoding.
+    pub fn decode(self, text: &str) -> io::Result<ByteString> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Hex => {
+                hex::decode(text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            }
+            Encoding::Base64 => BASE64
+                .decode(text)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8" | "utf-8" => Ok(Encoding::Utf8),
+            "hex" => Ok(Encoding::Hex),
+            "base64" => Ok(Encoding::Base64),
+            _ => Err(format!(
+                "unknown encoding {}, expected utf8, hex or base64",
+                s
+            )),
+        }
+    }
+}
+
+/// Exported key value pair, with the key and value encoded as text.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ExportedPair {
+    pub(crate) key: String,
+    pub(crate) value: String,
+}
+
+/// Start of an exported JSON document, holding the encoding used for its pairs.
+#[derive(Debug, Serialize)]
+struct JsonPrologue {
+    encoding: Encoding,
+}
+
+/// Exported JSON document, as read back on import.
+#[derive(Debug, Deserialize)]
+pub(crate) struct JsonDocument {
+    pub(crate) encoding: Encoding,
+    pub(crate) pairs: Vec<ExportedPair>,
+}
+
+/// Writer for an exported JSON document, which writes the pairs one at a time:
+/// ```text
+/// {"encoding":"hex","pairs":[
+/// {"key":"6b6579","value":"76616c7565"}
+/// ]}
+/// ```
+pub(crate) struct JsonWriter<W: Write> {
+    w: W,
+    encoding: Encoding,
+    written: u64,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub(crate) fn new(mut w: W, encoding: Encoding) -> io::Result<Self> {
+        let prologue = serde_json::to_string(&JsonPrologue { encoding })?;
+        write!(w, "{},\"pairs\":[", prologue.trim_end_matches('}'))?;
+
+        Ok(JsonWriter {
+            w,
+            encoding,
+            written: 0,
+        })
+    }
+
+    pub(crate) fn write_pair(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        let pair = ExportedPair {
+            key: self.encoding.encode(key)?,
+            value: self.encoding.encode(value)?,
+        };
+
+        if self.written > 0 {
+            self.w.write_all(b",")?;
+        }
+        self.w.write_all(b"\n")?;
+        serde_json::to_writer(&mut self.w, &pair)?;
+
+        self.written += 1;
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<u64> {
+        self.w.write_all(b"\n]}\n")?;
+        self.w.flush()?;
+
+        Ok(self.written)
+    }
+}
@@ -0,0 +1,264 @@
+//! Header stored at the start of every storage file.
+
+use std::io;
+use std::io::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::checksum::ChecksumAlgorithm;
+use crate::compression::Compression;
+use crate::crypto::CHACHA20_POLY1305_ID;
+use crate::StoreOptions;
+
+/// Magic bytes identifying a `RiaKV` storage file.
+pub const MAGIC: [u8; 8] = *b"RIAKVLOG";
+
+/// Latest storage format version. Version `0` denotes storage files without a header, version
+/// `1` storage files with a compact header. Version `2` introduced the current header layout,
+/// version `3` record flags and version `4` record timestamps.
+pub const FORMAT_VERSION: u16 = 4;
+
+/// Number of bytes occupied by the header in a storage file.
+pub const HEADER_LEN: u16 = 32;
+
+/// Number of bytes occupied by the compact header of storage files of format version 1,
+/// holding only the magic bytes, the version and the checksum algorithm.
+const COMPACT_HEADER_LEN: u16 = 11;
+
+/// Number of bytes in the header which are in use, the rest is reserved.
+const HEADER_USED_LEN: u16 = 31;
+
+/// Header identifying a storage file and describing the format of its records. It is stored
+/// with the following layout at the start of the file:
+/// ```text
+/// ┌───────┬─────────┬────────────┬────────────┬──────────┬─────────────┬───────────┐
+/// │ magic │ version │ header len │ created at │ checksum │ compression │ threshold │
+/// └───────┴─────────┴────────────┴────────────┴──────────┴─────────────┴───────────┘
+///     8        2          2            8           1            1             4
+/// ┌────────────┬────────┬──────────┐
+/// │ encryption │ key id │ reserved │
+/// └────────────┴────────┴──────────┘
+///       1           4         1
+/// ```
+///
+/// The creation time is stored as seconds since the unix epoch. The checksum, compression and
+/// encryption algorithms are stored as their identifiers, with `0` denoting no encryption.
+/// The reserved bytes are zeroed. Headers of format version 2 have the compression, threshold
+/// and encryption fields zeroed.
+///
+/// Storage files of format version 1 have a compact header instead:
+/// ```text
+/// ┌───────┬─────────┬──────────────┐
+/// │ magic │ version │ checksum alg │
+/// └───────┴─────────┴──────────────┘
+///     8        2            1
+/// ```
+///
+/// Storage files with a compact header are only read to be upgraded, with
+/// `RiaKV::upgrade_file_at_path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// storage format version
+    pub version: u16,
+
+    /// creation time of the storage file in seconds since the unix epoch
+    pub created_at: u64,
+
+    /// checksum algorithm used for every record in the file
+    pub checksum: ChecksumAlgorithm,
+
+    /// compression applied to values written to the file
+    pub compression: Compression,
+
+    /// minimum length of a value to be compressed
+    pub compression_threshold: u32,
+
+    /// identifier of the key the records are encrypted with, if encrypted
+    pub encryption_key_id: Option<u32>,
+}
+
+impl Header {
+    /// Creates a header of the latest format version for a storage file created now
+    /// with the given options.
+    pub fn new(options: StoreOptions) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        Header {
+            version: FORMAT_VERSION,
+            created_at,
+            checksum: options.checksum,
+            compression: options.compression,
+            compression_threshold: options.compression_threshold,
+            encryption_key_id: options.encryption.map(|key| key.id),
+        }
+    }
+
+    /// Returns the header describing storage files without a header, where records
+    /// are stored from the start of the file with `crc32` checksums.
+    pub fn legacy() -> Self {
+        Header {
+            version: 0,
+            created_at: 0,
+            checksum: ChecksumAlgorithm::Crc32,
+            compression: Compression::None,
+            compression_threshold: 0,
+            encryption_key_id: None,
+        }
+    }
+
+    /// Returns the options recorded in this header. The encryption key and the sync marker
+    /// interval are not recorded in the header, so the returned options enable neither.
+    pub fn options(&self) -> StoreOptions {
+        StoreOptions {
+            checksum: self.checksum,
+            compression: self.compression,
+            compression_threshold: self.compression_threshold,
+            encryption: None,
+            sync_marker_interval: None,
+        }
+    }
+
+    /// Returns the number of bytes occupied by this header in the storage file.
+    pub fn len(&self) -> u64 {
+        match self.version {
+            0 => 0,
+            1 => COMPACT_HEADER_LEN as u64,
+            _ => HEADER_LEN as u64,
+        }
+    }
+
+    /// Returns whether this header occupies no space, i.e. it describes a legacy storage file.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether this header is of the latest format version.
+    pub fn is_current(&self) -> bool {
+        self.version == FORMAT_VERSION
+    }
+
+    /// Returns the number of bytes preceding the key and value in every record, i.e.
+    /// the checksum, flags, timestamp, key length and value length fields.
+    pub fn record_header_len(&self) -> u64 {
+        match self.version {
+            0..=2 => self.checksum.width() + 8,
+            3 => self.checksum.width() + 9,
+            _ => self.checksum.width() + 17,
+        }
+    }
+
+    /// Reads the header from the start of a storage file. Storage files not starting with the
+    /// magic bytes are treated as legacy storage files without a header. Headers of a newer
+    /// format version than `FORMAT_VERSION` are refused with an `InvalidData` error.
+    pub fn read_from<R: Read>(f: &mut R) -> io::Result<Self> {
+        let mut magic = Vec::with_capacity(MAGIC.len());
+        f.take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+
+        if magic != MAGIC {
+            return Ok(Header::legacy());
+        }
+
+        let version = f.read_u16::<LittleEndian>()?;
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(invalid_header(format!(
+                "unsupported storage format version {} (latest supported version is {})",
+                version, FORMAT_VERSION
+            )));
+        }
+
+        if version == 1 {
+            let checksum_id = f.read_u8()?;
+            let checksum = ChecksumAlgorithm::from_id(checksum_id).ok_or_else(|| {
+                invalid_header(format!("unknown checksum algorithm {}", checksum_id))
+            })?;
+
+            return Ok(Header {
+                version,
+                checksum,
+                ..Header::legacy()
+            });
+        }
+
+        let header_len = f.read_u16::<LittleEndian>()?;
+        if header_len != HEADER_LEN {
+            return Err(invalid_header(format!(
+                "invalid storage file header length {}",
+                header_len
+            )));
+        }
+
+        let created_at = f.read_u64::<LittleEndian>()?;
+
+        let checksum_id = f.read_u8()?;
+        let checksum = ChecksumAlgorithm::from_id(checksum_id)
+            .ok_or_else(|| invalid_header(format!("unknown checksum algorithm {}", checksum_id)))?;
+
+        let compression_id = f.read_u8()?;
+        let compression = Compression::from_id(compression_id).ok_or_else(|| {
+            invalid_header(format!("unknown compression algorithm {}", compression_id))
+        })?;
+
+        let compression_threshold = f.read_u32::<LittleEndian>()?;
+
+        let encryption_id = f.read_u8()?;
+        let key_id = f.read_u32::<LittleEndian>()?;
+        let encryption_key_id = match encryption_id {
+            0 => None,
+            CHACHA20_POLY1305_ID => Some(key_id),
+            _ => {
+                return Err(invalid_header(format!(
+                    "unknown encryption algorithm {}",
+                    encryption_id
+                )))
+            }
+        };
+
+        let mut reserved = [0; (HEADER_LEN - HEADER_USED_LEN) as usize];
+        f.read_exact(&mut reserved)?;
+
+        Ok(Header {
+            version,
+            created_at,
+            checksum,
+            compression,
+            compression_threshold,
+            encryption_key_id,
+        })
+    }
+
+    /// Writes this header to the given writer. Nothing is written for legacy headers, and the
+    /// compact layout for headers of format version 1.
+    pub fn write_to<W: Write>(&self, f: &mut W) -> io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        f.write_all(&MAGIC)?;
+        f.write_u16::<LittleEndian>(self.version)?;
+
+        if self.version == 1 {
+            return f.write_u8(self.checksum.id());
+        }
+
+        f.write_u16::<LittleEndian>(HEADER_LEN)?;
+        f.write_u64::<LittleEndian>(self.created_at)?;
+        f.write_u8(self.checksum.id())?;
+        f.write_u8(self.compression.id())?;
+        f.write_u32::<LittleEndian>(self.compression_threshold)?;
+        match self.encryption_key_id {
+            None => f.write_all(&[0; 5])?,
+            Some(key_id) => {
+                f.write_u8(CHACHA20_POLY1305_ID)?;
+                f.write_u32::<LittleEndian>(key_id)?;
+            }
+        }
+        f.write_all(&[0; (HEADER_LEN - HEADER_USED_LEN) as usize])
+    }
+}
+
+fn invalid_header(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
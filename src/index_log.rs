@@ -0,0 +1,277 @@
+//! Incremental persistence of the index of a `RiaKV` store.
+//!
+//! An index log starts with magic bytes, followed by a sequence of frames with the following
+//! layout:
+//! ```text
+//! ┌──────┬────────────────┬──────────┬─────────┐
+//! │ kind │ payload length │ checksum │ payload │
+//! └──────┴────────────────┴──────────┴─────────┘
+//!    1           4              4
+//! ```
+//!
+//! The first frame is a checkpoint holding the whole index, and every following frame is a
+//! delta holding the entries changed since the previous frame. Every payload also records the
+//! identity of the storage file it was written for: the creation time in its header, its
+//! length, and the `xxh3` checksum of the bytes preceding that length, so that index logs left
+//! over from another storage file, e.g. one since compacted or migrated, are told apart. The
+//! checksum is the `crc32c` of the payload, which is encrypted with the cipher of the store, if
+//! any.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::crypto::Cipher;
+use crate::{ByteStr, ByteString};
+
+/// Magic bytes identifying an index log.
+pub const INDEX_LOG_MAGIC: &[u8; 8] = b"RIAKVIDX";
+
+/// Minimum number of delta entries appended before a new checkpoint is written.
+pub const MIN_CHECKPOINT_INTERVAL: u64 = 1024;
+
+const CHECKPOINT: u8 = 0;
+const DELTA: u8 = 1;
+
+const FRAME_HEADER_LEN: usize = 9;
+
+/// Number of bytes preceding the recorded length of a storage file covered by the checksum in
+/// its identity.
+pub(crate) const STORAGE_TAIL_LEN: u64 = 4096;
+
+/// Identity of the storage file an index log frame was written for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StorageIdentity {
+    /// creation time recorded in the header of the storage file
+    pub(crate) created_at: u64,
+
+    /// length of the storage file
+    pub(crate) end: u64,
+
+    /// checksum of the `STORAGE_TAIL_LEN` bytes preceding the length, or of every byte if fewer
+    pub(crate) tail_checksum: u64,
+}
+
+/// State of the index log a store was loaded from or last persisted to, along with the index
+/// entries changed since.
+#[derive(Debug)]
+pub(crate) struct IndexLogState {
+    /// changed entries, mapping keys to their new position, or `None` if removed
+    changes: HashMap<ByteString, Option<u64>>,
+
+    /// number of entries in the checkpoint
+    checkpoint_entries: u64,
+
+    /// number of entries in the deltas following the checkpoint
+    delta_entries: u64,
+
+    /// identity of the storage file the last frame was written for
+    storage: StorageIdentity,
+
+    /// length of the valid frames in the index log
+    len: u64,
+}
+
+impl IndexLogState {
+    /// Records a changed index entry.
+    pub(crate) fn record(&mut self, key: &ByteStr, position: Option<u64>) {
+        self.changes.insert(key.to_vec(), position);
+    }
+
+    /// Returns the identity of the storage file the index log is up to date with.
+    pub(crate) fn storage(&self) -> StorageIdentity {
+        self.storage
+    }
+
+    /// Returns whether enough deltas have been appended for a new checkpoint to be written.
+    pub(crate) fn needs_checkpoint(&self) -> bool {
+        self.delta_entries >= self.checkpoint_entries.max(MIN_CHECKPOINT_INTERVAL)
+    }
+}
+
+/// Writes a new index log at the given path holding a checkpoint of the given index, replacing
+/// any existing index log atomically.
+pub(crate) fn write_checkpoint(
+    path: &Path,
+    index: &HashMap<ByteString, u64>,
+    storage: StorageIdentity,
+    cipher: Option<&Cipher>,
+) -> io::Result<IndexLogState> {
+    let payload = bincode::serialize(&(storage, index)).map_err(invalid_index_log)?;
+    let frame = encode_frame(CHECKPOINT, &payload, cipher)?;
+
+    crate::write_file_atomically(path, |index_file| {
+        index_file.write_all(INDEX_LOG_MAGIC)?;
+        index_file.write_all(&frame)
+    })?;
+
+    Ok(IndexLogState {
+        changes: HashMap::new(),
+        checkpoint_entries: index.len() as u64,
+        delta_entries: 0,
+        storage,
+        len: (INDEX_LOG_MAGIC.len() + frame.len()) as u64,
+    })
+}
+
+/// Appends a delta holding the changed entries to the index log at the given path. Anything
+/// following the valid frames, e.g. a torn delta, is truncated first.
+pub(crate) fn append_delta(
+    path: &Path,
+    state: &mut IndexLogState,
+    storage: StorageIdentity,
+    cipher: Option<&Cipher>,
+) -> io::Result<()> {
+    if state.changes.is_empty() && state.storage == storage {
+        return Ok(());
+    }
+
+    let changes: Vec<(&ByteString, &Option<u64>)> = state.changes.iter().collect();
+    let payload = bincode::serialize(&(storage, changes)).map_err(invalid_index_log)?;
+    let frame = encode_frame(DELTA, &payload, cipher)?;
+
+    let mut index_file = OpenOptions::new().write(true).open(path)?;
+    index_file.set_len(state.len)?;
+    index_file.seek(SeekFrom::Start(state.len))?;
+    index_file.write_all(&frame)?;
+    index_file.sync_data()?;
+
+    state.delta_entries += state.changes.len() as u64;
+    state.changes.clear();
+    state.storage = storage;
+    state.len += frame.len() as u64;
+
+    Ok(())
+}
+
+/// Reads the index log at the given path, applying every delta to the checkpoint. A frame cut
+/// short by the end of the index log, as left by a torn append, is ignored. Returns the index,
+/// along with the state of the index log, which records the identity of the storage file the
+/// index is up to date with.
+pub(crate) fn read_index_log(
+    path: &Path,
+    cipher: Option<&Cipher>,
+) -> io::Result<(HashMap<ByteString, u64>, IndexLogState)> {
+    let mut bytes = ByteString::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    if !bytes.starts_with(INDEX_LOG_MAGIC) {
+        return Err(invalid_index_log("missing index log magic bytes"));
+    }
+
+    let mut offset = INDEX_LOG_MAGIC.len();
+    let mut index: Option<HashMap<ByteString, u64>> = None;
+    let mut state = IndexLogState {
+        changes: HashMap::new(),
+        checkpoint_entries: 0,
+        delta_entries: 0,
+        storage: StorageIdentity::default(),
+        len: 0,
+    };
+
+    while let Some((kind, payload, frame_len)) = decode_frame(&bytes[offset..], cipher)? {
+        match (kind, &mut index) {
+            (CHECKPOINT, None) => {
+                let (storage, checkpoint): (StorageIdentity, HashMap<ByteString, u64>) =
+                    bincode::deserialize(&payload).map_err(invalid_index_log)?;
+
+                state.checkpoint_entries = checkpoint.len() as u64;
+                state.storage = storage;
+                index = Some(checkpoint);
+            }
+            (DELTA, Some(index)) => {
+                let (storage, changes): (StorageIdentity, Vec<(ByteString, Option<u64>)>) =
+                    bincode::deserialize(&payload).map_err(invalid_index_log)?;
+
+                state.delta_entries += changes.len() as u64;
+                state.storage = storage;
+
+                for (key, position) in changes {
+                    match position {
+                        Some(position) => index.insert(key, position),
+                        None => index.remove(&key),
+                    };
+                }
+            }
+            _ => return Err(invalid_index_log("unexpected index log frame")),
+        }
+
+        offset += frame_len;
+    }
+
+    state.len = offset as u64;
+
+    match index {
+        Some(index) => Ok((index, state)),
+        None => Err(invalid_index_log("index log without checkpoint")),
+    }
+}
+
+/// Encodes a frame of the given kind holding the given payload, encrypted if a cipher is given.
+fn encode_frame(kind: u8, payload: &ByteStr, cipher: Option<&Cipher>) -> io::Result<ByteString> {
+    let payload = match cipher {
+        None => payload.to_vec(),
+        Some(cipher) => cipher.seal(payload, &[kind])?,
+    };
+
+    if payload.len() > u32::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "index too large for a single index log frame",
+        ));
+    }
+
+    let mut frame = ByteString::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.write_u8(kind)?;
+    frame.write_u32::<LittleEndian>(payload.len() as u32)?;
+    frame.write_u32::<LittleEndian>(crc32c::crc32c(&payload))?;
+    frame.extend_from_slice(&payload);
+
+    Ok(frame)
+}
+
+/// Decodes the frame at the start of the given bytes, returning its kind, decrypted payload and
+/// length. Returns `None` if the bytes end before the frame does.
+fn decode_frame(
+    bytes: &ByteStr,
+    cipher: Option<&Cipher>,
+) -> io::Result<Option<(u8, ByteString, usize)>> {
+    if bytes.len() < FRAME_HEADER_LEN {
+        return Ok(None);
+    }
+
+    let kind = bytes[0];
+    let payload_len = LittleEndian::read_u32(&bytes[1..5]) as usize;
+    let checksum = LittleEndian::read_u32(&bytes[5..9]);
+
+    let frame_len = FRAME_HEADER_LEN + payload_len;
+    if bytes.len() < frame_len {
+        return Ok(None);
+    }
+
+    let payload = &bytes[FRAME_HEADER_LEN..frame_len];
+    if crc32c::crc32c(payload) != checksum {
+        return Err(invalid_index_log("index log frame checksum mismatch"));
+    }
+
+    let payload = match cipher {
+        None => payload.to_vec(),
+        Some(cipher) => cipher.open(payload, &[kind])?,
+    };
+
+    Ok(Some((kind, payload, frame_len)))
+}
+
+/// Returns an `InvalidData` error for a malformed index log.
+fn invalid_index_log<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -0,0 +1,227 @@
+//! Named keyspaces, holding separate sets of keys in a single store.
+//!
+//! Every record of a named keyspace is tagged with the identifier of its keyspace. The names
+//! of the keyspaces are recorded in the storage as well, by records of a reserved catalog
+//! keyspace mapping every name to its identifier. Dropping a keyspace writes a tombstone for
+//! its name into the catalog; the records of the dropped keyspace are left in place until
+//! the store is compacted.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::record::KEYSPACE_ID_LEN;
+use crate::{ByteStr, ByteString, KeyValuePair, RiaKV};
+
+/// Identifier of the default keyspace, whose records are not tagged.
+pub const DEFAULT_KEYSPACE_ID: u32 = 0;
+
+/// Identifier of the reserved keyspace whose records map keyspace names to their identifiers.
+pub const KEYSPACE_CATALOG_ID: u32 = u32::MAX;
+
+/// Catalog and indexes of the named keyspaces of a store.
+#[derive(Debug)]
+pub(crate) struct Keyspaces {
+    /// whether the keyspaces have been loaded from the storage
+    pub(crate) loaded: bool,
+
+    /// mapping from the names of the keyspaces to their identifiers
+    ids: HashMap<String, u32>,
+
+    /// index of every keyspace, mapping keys to the position of their latest record
+    indexes: HashMap<u32, HashMap<ByteString, u64>>,
+
+    /// identifier assigned to the next keyspace created
+    next_id: u32,
+}
+
+impl Keyspaces {
+    /// Creates an empty catalog, which is either known to be loaded, e.g. for empty storage,
+    /// or has yet to be loaded from the storage.
+    pub(crate) fn new(loaded: bool) -> Self {
+        Keyspaces {
+            loaded,
+            ids: HashMap::new(),
+            indexes: HashMap::new(),
+            next_id: DEFAULT_KEYSPACE_ID + 1,
+        }
+    }
+
+    /// Returns the identifier of the keyspace with the given name, if any.
+    pub(crate) fn id(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    /// Returns the name and identifier of every keyspace, ordered by name.
+    pub(crate) fn catalog(&self) -> Vec<(String, u32)> {
+        let mut catalog: Vec<(String, u32)> = self
+            .ids
+            .iter()
+            .map(|(name, &id)| (name.clone(), id))
+            .collect();
+        catalog.sort_unstable();
+        catalog
+    }
+
+    /// Returns the identifier for a new keyspace.
+    pub(crate) fn next_id(&self) -> io::Result<u32> {
+        if self.next_id == KEYSPACE_CATALOG_ID {
+            return Err(io::Error::other(
+                "keyspace identifiers exhausted, the store needs to be compacted",
+            ));
+        }
+
+        Ok(self.next_id)
+    }
+
+    /// Returns the index of the keyspace with the given identifier, if it exists.
+    pub(crate) fn index(&self, id: u32) -> Option<&HashMap<ByteString, u64>> {
+        self.indexes.get(&id)
+    }
+
+    /// Returns every index, along with the identifier of its keyspace.
+    pub(crate) fn indexes(&self) -> impl Iterator<Item = (u32, &HashMap<ByteString, u64>)> {
+        self.indexes.iter().map(|(&id, index)| (id, index))
+    }
+
+    /// Applies the record of the given keyspace at the given position, which is a tombstone
+    /// unless `live`. Records of keyspaces which do not exist, i.e. which have been dropped,
+    /// are ignored.
+    pub(crate) fn apply(
+        &mut self,
+        keyspace: u32,
+        kv: KeyValuePair,
+        position: u64,
+        live: bool,
+    ) -> io::Result<()> {
+        if keyspace != KEYSPACE_CATALOG_ID {
+            if let Some(index) = self.indexes.get_mut(&keyspace) {
+                if live {
+                    index.insert(kv.key, position);
+                } else {
+                    index.remove(&kv.key);
+                }
+            }
+
+            return Ok(());
+        }
+
+        let name = String::from_utf8(kv.key)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        if !live {
+            if let Some(id) = self.ids.remove(&name) {
+                self.indexes.remove(&id);
+            }
+
+            return Ok(());
+        }
+
+        if kv.value.len() != KEYSPACE_ID_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid keyspace identifier in catalog record",
+            ));
+        }
+
+        let id = LittleEndian::read_u32(&kv.value);
+
+        if id == DEFAULT_KEYSPACE_ID || id == KEYSPACE_CATALOG_ID {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("reserved keyspace identifier {} in catalog record", id),
+            ));
+        }
+
+        if let Some(previous) = self.ids.insert(name, id) {
+            self.indexes.remove(&previous);
+        }
+        self.indexes.entry(id).or_default();
+        self.next_id = self.next_id.max(id + 1);
+
+        Ok(())
+    }
+}
+
+/// Handle to a named keyspace of a store, returned by `RiaKV::keyspace`. Keys of a keyspace
+/// are independent from the keys of the default keyspace and of every other keyspace.
+#[derive(Debug)]
+pub struct Keyspace<'a, F>
+where
+    F: Read + Write + Seek,
+{
+    pub(crate) store: &'a mut RiaKV<F>,
+    pub(crate) id: u32,
+}
+
+impl<F> Keyspace<'_, F>
+where
+    F: Read + Write + Seek,
+{
+    /// Returns the identifier of this keyspace, as recorded in its records.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Gets the value for the given key.
+    pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        let position = match self.index().get(key) {
+            None => return Ok(None),
+            Some(position) => *position,
+        };
+
+        let kv = self.store.get_at(position)?;
+
+        if !kv.value.is_empty() {
+            Ok(Some(kv.value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Inserts the given key value pair into this keyspace.
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        let position = self.store.append_record(self.id, key, value)?;
+
+        let kv = KeyValuePair {
+            key: key.to_vec(),
+            value: ByteString::new(),
+        };
+
+        self.store
+            .keyspaces
+            .apply(self.id, kv, position, !value.is_empty())
+    }
+
+    /// Deletes the value for the given key by inserting a _tombstone_ entry.
+    #[inline]
+    pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
+        self.insert(key, b"")
+    }
+
+    /// Returns all the live keys of this keyspace, in order.
+    pub fn keys(&self) -> Vec<ByteString> {
+        let mut keys: Vec<ByteString> = self.index().keys().cloned().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Returns the number of live keys in this keyspace.
+    pub fn len(&self) -> usize {
+        self.index().len()
+    }
+
+    /// Returns whether this keyspace holds no live keys.
+    pub fn is_empty(&self) -> bool {
+        self.index().is_empty()
+    }
+
+    fn index(&self) -> &HashMap<ByteString, u64> {
+        self.store
+            .keyspaces
+            .index(self.id)
+            .expect("keyspace dropped while borrowed")
+    }
+}
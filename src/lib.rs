@@ -7,10 +7,24 @@
 //!## Features
 //!
 //!- Persistent key value store with a hash table index
-//!- `crc32` checksum validation for every key value pair stored.
+//!- `crc32`, `crc32c` or `xxh3` checksum validation for every key value pair stored.
+//!- Optionally, `lz4` or `zstd` compression of values
+//!- Optionally, `ChaCha20-Poly1305` encryption at rest of records and the persistent index
 //!- Optionally, persistent index for fast loading
+//!- JSON and CSV export and import, with UTF-8, hex or base64 encoded keys and values
+//!- Bulk loading of fresh stores from an iterator of key value pairs
+//!- Typed keys and values encoded with serde, as `bincode` or JSON
+//!- Named keyspaces with separate indexes, and compaction
+//!- Store statistics, with key and value size histograms
+//!- Atomic batches of inserts and deletes, surviving torn writes
+//!- Versioned, checksummed dumps of stores, with or without history, and restores
+//!- Hot backups copying a consistent prefix of the storage, with a matching index
+//!- Migration of storage files from older format versions, with an optional dry run
+//!- Diffs of the live key value pairs of two stores, and merges with a conflict policy
+//!- Optionally, memory mapped zero-copy reads for file backed stores
 //!- Exhaustive, comprehensive tests
 
+use std::borrow::Cow;
 use std::io;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter, SeekFrom};
@@ -18,13 +32,66 @@ use std::io::{BufReader, BufWriter, SeekFrom};
 use std::result;
 
 use std::fs::{File, OpenOptions};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 use serde_derive::{Deserialize, Serialize};
 
+mod batch;
+mod bulk;
+mod checksum;
+mod compression;
+mod crypto;
+mod diff;
+mod dump;
+mod export;
+mod header;
+mod index_log;
+mod keyspace;
+mod migrate;
+mod mmap;
+mod record;
+mod stats;
+mod stream;
+mod typed;
+mod verify;
+
+pub use batch::WriteBatch;
+pub use bulk::{BulkLoadOptions, Dedup, DEFAULT_BULK_BUFFER_LEN};
+pub use checksum::{ChecksumAlgorithm, ChecksumHasher};
+pub use compression::Compression;
+pub use crypto::{Cipher, EncryptionKey, NONCE_LEN, TAG_LEN};
+pub use diff::{ConflictPolicy, MergeSummary, StoreDiff};
+pub use dump::{DumpOptions, DumpSummary, DUMP_MAGIC, DUMP_VERSION};
+pub use export::Encoding;
+pub use header::{Header, FORMAT_VERSION, HEADER_LEN, MAGIC};
+pub use index_log::{INDEX_LOG_MAGIC, MIN_CHECKPOINT_INTERVAL};
+pub use keyspace::{Keyspace, DEFAULT_KEYSPACE_ID, KEYSPACE_CATALOG_ID};
+pub use migrate::{MigrateOptions, MigrationReport};
+pub use mmap::MappedFile;
+pub use record::{
+    RecordHeader, BATCH_MARKER_KEY, FLAG_BATCH_MARKER, FLAG_COMPRESSION_MASK, FLAG_ENCRYPTED,
+    FLAG_KEYSPACE, FLAG_SYNC_MARKER, KEYSPACE_ID_LEN, SYNC_MARKER_KEY,
+};
+pub use stats::{SizeHistogram, StoreStats};
+pub use stream::ValueReader;
+pub use typed::{Codec, TypedIter, TypedRiaKV};
+pub use verify::{Damage, DamagedRange, IndexMismatch, VerifyReport};
+
+use bulk::BulkWriter;
+use dump::{DumpEntry, DumpReader, DumpWriter};
+use export::{ExportedPair, JsonDocument, JsonWriter};
+use index_log::{IndexLogState, StorageIdentity};
+use keyspace::Keyspaces;
+use migrate::NullStorage;
+use stream::HashingWriter;
+use verify::{ScanItem, ScannedRecord};
+
+/// Default minimum length of a value to be compressed.
+pub const DEFAULT_COMPRESSION_THRESHOLD: u32 = 64;
+
 /// Type to represent binary content
 pub type ByteString = Vec<u8>;
 
@@ -52,6 +119,60 @@ where
 
     /// index - storing a mapping from keys to the position where the key value entry is stored
     pub index: HashMap<ByteString, u64>,
+
+    /// header describing the format of the records in the underlying storage
+    header: Header,
+
+    /// cipher for encrypting and decrypting records, if the underlying storage is encrypted
+    cipher: Option<Cipher>,
+
+    /// minimum number of bytes between sync markers, if sync markers are written
+    sync_marker_interval: Option<u64>,
+
+    /// position of the last sync marker, or of the end of the storage when first written to
+    last_sync_marker: Option<u64>,
+
+    /// state of the index log, tracking index changes since it was last loaded or persisted
+    index_log: Option<IndexLogState>,
+
+    /// catalog and indexes of the named keyspaces
+    keyspaces: Keyspaces,
+}
+
+/// Options used when creating a new storage file. They are recorded in the file header, so
+/// opening an existing storage file uses the options it was created with.
+///
+/// The encryption key is the exception: only its identifier is recorded in the file header,
+/// and the key itself has to be supplied every time an encrypted storage file is opened. The
+/// sync marker interval is not recorded at all and only applies to the opened instance.
+#[derive(Debug, Clone, Copy)]
+pub struct StoreOptions {
+    /// checksum algorithm used for every record
+    pub checksum: ChecksumAlgorithm,
+
+    /// compression applied to values at least `compression_threshold` bytes long
+    pub compression: Compression,
+
+    /// minimum length of a value to be compressed
+    pub compression_threshold: u32,
+
+    /// key used for encrypting records and the persisted index, if any
+    pub encryption: Option<EncryptionKey>,
+
+    /// minimum number of bytes written between sync markers, if sync markers are to be written
+    pub sync_marker_interval: Option<u64>,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        StoreOptions {
+            checksum: ChecksumAlgorithm::default(),
+            compression: Compression::default(),
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            encryption: None,
+            sync_marker_interval: None,
+        }
+    }
 }
 
 /// Represent the kind of index operation to use for a given `(KeyValuePair, u64)`
@@ -63,6 +184,18 @@ pub enum IndexOp {
     Nop,
 }
 
+impl IndexOp {
+    /// Returns the operation loading the given record into the index: `IndexOp::Insert` for a
+    /// key value pair, or `IndexOp::Delete` for a _tombstone_.
+    fn for_record(kv: KeyValuePair, position: u64) -> Self {
+        if !kv.value.is_empty() {
+            IndexOp::Insert(kv, position)
+        } else {
+            IndexOp::Delete(kv, position)
+        }
+    }
+}
+
 impl RiaKV<File> {
     /// Creates a new `RiaKV` instance from a file stored at the given path as th
     /// backing store.
@@ -79,528 +212,4533 @@ impl RiaKV<File> {
     /// };
     /// ```
     pub fn open_from_file_at_path(path: &Path) -> io::Result<Self> {
-        let f = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .append(true)
-            .open(path)?;
-
-        Ok(RiaKV {
-            f: f,
-            index: HashMap::new(),
-        })
+        Self::open_from_file_at_path_with_options(path, StoreOptions::default())
     }
-}
 
-impl RiaKV<io::Cursor<Vec<u8>>> {
-    /// Creates a new `RiakV` instance from an in memory buffer [`Vec<u8>`] with the given
-    /// capacity.
+    /// Creates a new `RiaKV` instance from a file stored at the given path as the backing
+    /// store. The given options are used if a new storage file is created.
     ///
     /// # Example
     /// ```
-    /// use libriakv::RiaKV;
+    /// use libriakv::{ChecksumAlgorithm, RiaKV, StoreOptions};
     ///
-    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    /// let options = StoreOptions {
+    ///     checksum: ChecksumAlgorithm::Crc32c,
+    ///     ..StoreOptions::default()
+    /// };
+    ///
+    /// match RiaKV::open_from_file_at_path_with_options(storage_path, options) {
+    ///     Ok(opened_store) => {}, // use the opened store
+    ///     _ => {} // handle failure
+    /// };
     /// ```
-    pub fn open_from_in_memory_buffer(capacity: usize) -> Self {
-        RiaKV {
-            f: io::Cursor::new(vec![0; capacity]),
-            index: HashMap::new(),
-        }
+    pub fn open_from_file_at_path_with_options(
+        path: &Path,
+        options: StoreOptions,
+    ) -> io::Result<Self> {
+        RiaKV::open_from_storage(open_storage_file(path)?, options)
     }
-}
 
-impl<F> RiaKV<F>
-where
-    F: Read + Write + Seek,
-{
-    /// Processes a record from the current position in the underlying storage file.
-    /// Every record (key value pair) is stored with the following layout:
-    /// ```text
-    /// ┌────────────────┬────────────┬──────────────┬────────────────┐
-    /// │ crc32 checksum │ key length │ value length │ KeyValuePair{} │
-    /// └────────────────┴────────────┴──────────────┴────────────────┘
-    /// ```
-    ///
-    /// Reading a record from the underlying storage occurs in the following steps:
-    /// - Read the checksum, key length and value length as 32 bit integers with little endian
-    /// format
-    /// - Read the next (key length + value length) bytes into a bytestring
-    /// - Verify that the crc32 checksum of the data Bytestring read matches with the crc32
-    /// checksum read
-    /// - Split off the bytestring at key length from the start to obtain the key and the value
-    /// - Return `KeyValuePair { key, value }`
+    /// Upgrades the storage file at the given path to the latest format version. The upgraded
+    /// copy is written next to the original file and atomically renamed over it once complete.
+    /// Returns `Ok(false)` without modifying the file if no upgrade is required.
     ///
     /// # Example
-    /// ```
-    /// use std::io;
+    /// ```no_run
     /// use libriakv::RiaKV;
     ///
-    /// let mut cursor = io::Cursor::new(vec![0; 5000]);
-    ///
-    /// // .. enter some data into the cursor
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
     ///
-    /// let maybe_kv = RiaKV::<io::Cursor<Vec<u8>>>::process_record(&mut cursor);
+    /// RiaKV::upgrade_file_at_path(storage_path).expect("upgrade");
+    /// let mut store = RiaKV::open_from_file_at_path(storage_path).expect("open");
     /// ```
-    pub fn process_record<R: Read>(f: &mut R) -> io::Result<KeyValuePair> {
-        let saved_checksum = f.read_u32::<LittleEndian>()?;
-        let key_len = f.read_u32::<LittleEndian>()?;
-        let val_len = f.read_u32::<LittleEndian>()?;
+    pub fn upgrade_file_at_path(path: &Path) -> io::Result<bool> {
+        let mut src = File::open(path)?;
 
-        let data_len = key_len + val_len;
+        if src.metadata()?.len() == 0 || Header::read_from(&mut src)?.is_current() {
+            return Ok(false);
+        }
 
-        let mut data = ByteString::with_capacity(data_len as usize);
+        write_file_atomically(path, |f| RiaKV::upgrade_from(&mut src, f).map(|_| ()))?;
 
-        {
-            f.by_ref().take(data_len as u64).read_to_end(&mut data)?;
-        }
+        Ok(true)
+    }
 
-        debug_assert_eq!(data.len(), data_len as usize);
+    /// Migrates the storage file at the given path, of any supported format version, to the
+    /// latest format version, keeping its checksum and compression options. As with
+    /// `RiaKV::upgrade_file_at_path`, the migrated copy is written next to the original file
+    /// and atomically renamed over it once complete.
+    ///
+    /// Unless `MigrateOptions::history`, only the latest record of every live key is migrated,
+    /// along with the names of the live keyspaces, compacting the store. With
+    /// `MigrateOptions::dry_run`, the migration is encoded without writing anything, and only
+    /// reported. Encrypted storage files are refused with an `InvalidInput` error, as their
+    /// key is required to read them; use `RiaKV::copy_into` instead.
+    ///
+    /// Positions change with migration, so any index persisted for the storage file has to be
+    /// written again. Returns a report of what the migration changed, or would change.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use libriakv::{MigrateOptions, RiaKV};
+    ///
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    ///
+    /// let options = MigrateOptions { history: true, dry_run: true };
+    /// let report = RiaKV::migrate_file_at_path(storage_path, options).expect("migrate");
+    /// println!("{} bytes would be migrated to {} bytes", report.source_bytes, report.migrated_bytes);
+    /// ```
+    pub fn migrate_file_at_path(
+        path: &Path,
+        options: MigrateOptions,
+    ) -> io::Result<MigrationReport> {
+        let mut src = File::open(path)?;
 
-        let checksum = crc::crc32::checksum_ieee(&data);
-        if checksum != saved_checksum {
-            panic!(
-                "data corruption encountered: ({:08x}) != {:08x}",
-                checksum, saved_checksum
-            );
+        if src.metadata()?.len() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "storage file to migrate is empty",
+            ));
         }
 
-        let value = data.split_off(key_len as usize);
-        let key = data;
+        let src_header = Header::read_from(&mut src)?;
+        if src_header.encryption_key_id.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "encrypted storage files cannot be migrated without their key",
+            ));
+        }
 
-        Ok(KeyValuePair { key, value })
-    }
+        if options.dry_run {
+            let mut dst = RiaKV::open_from_storage(NullStorage::default(), src_header.options())?;
+            return dst.migrate_records_from(&mut src, &src_header, options.history);
+        }
 
-    /// Seeks to the end of the underlying storage file. Any subsequent read should end in `EOF`.
-    pub fn seek_to_end(&mut self) -> io::Result<u64> {
-        self.f.seek(SeekFrom::End(0))
+        let mut report = None;
+        write_file_atomically(path, |f| {
+            let mut dst = RiaKV::open_from_storage(f, src_header.options())?;
+            report = Some(dst.migrate_records_from(&mut src, &src_header, options.history)?);
+            Ok(())
+        })?;
+
+        Ok(report.expect("migrated storage file"))
     }
 
-    /// For each function for processing all `KeyValuePair{}` instances stored in the underlying
-    /// storage.
-    ///
-    /// The key value entries are processed in the following way:
-    /// - First we backup the current position of the underlying storage since it would otherwise
-    /// be lost during scanning the entire storage file
-    /// - Next we seek to the start of the storage file
-    /// - Now in an infinite loop, during every iteration
-    ///     - We seek to the current position
-    ///     - We read a record using `RiaKV::process_record`
-    ///     - If the record read is not an error, we operate on it using the callback
-    ///     - In the case of an error
-    ///         - For simple EOF we break out of the loop
-    ///         - In the case of any other error, we return `Err(err)`
-    /// - Now if the callback is executed, the return value is used as follows:
-    ///     - For `IndexOp::Insert` the key value pair is inserted into the index
-    ///     - For `IndexOp::Delete` the key value pair is deleted from the index if it existed in the
-    ///     index before
-    ///     - For `Index::Nop` we do nothing a continue to the next iteration
-    ///     - For `Index::End` we break out of the loop
-    /// - When we exit from the loop, we seek back to the position we saved before entering into
-    /// the loop
-    /// - We return `Ok(())`
+    /// Creates a new storage file at the given path, which must not exist, holding a record for
+    /// every given key value pair, with `RiaKV::bulk_load`. Once the storage file is synced to
+    /// disk, a checkpoint of the index is written to the index log at the given index path.
     ///
     /// # Example
-    ///
     /// ```
-    /// use libriakv::{RiaKV, IndexOp, ByteString, ByteStr};
-    /// use std::io;
-    /// use std::io::prelude::*;
-    ///
-    /// // As used in the impl{} of RiaKV itself
-    ///
-    /// fn load<F>(store: &mut RiaKV<F>) -> io::Result<()> where F: Read + Write + Seek {
-    ///     store.for_each_kv_entry_in_storage(|kv, position| {
-    ///         if kv.value.len() > 0 {
-    ///             IndexOp::Insert(kv, position)
-    ///         } else {
-    ///             IndexOp::Delete(kv, position)
-    ///         }
-    ///     })
-    /// }
-    ///
-    /// // ...
+    /// use libriakv::{BulkLoadOptions, RiaKV, StoreOptions};
     ///
-    /// fn find<F>(store: &mut RiaKV<F>, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>>
-    ///     where F: Read + Write + Seek, {
-    ///     
-    ///     let mut found: Option<(u64, ByteString)> = None;
+    /// let dir = tempfile::tempdir().expect("tempdir");
+    /// let storage_path = &dir.path().join("file.db");
+    /// let index_path = &dir.path().join("file.idx");
     ///
-    ///     store.for_each_kv_entry_in_storage(|kv, position| {
-    ///         if kv.key == target.to_vec() {
-    ///             found = Some((position, kv.value));
-    ///             IndexOp::End
-    ///         } else {
-    ///             IndexOp::Nop
-    ///         }
-    ///     })?;
+    /// let pairs = (0..1000u32).map(|i| (i.to_be_bytes(), b"value"));
+    /// RiaKV::bulk_load_file_at_path(
+    ///     storage_path,
+    ///     index_path,
+    ///     StoreOptions::default(),
+    ///     BulkLoadOptions::default(),
+    ///     pairs,
+    /// )
+    /// .expect("bulk_load_file_at_path");
     ///
-    ///    Ok(found)
-    /// }
+    /// let mut store = RiaKV::open_from_file_at_path(storage_path).expect("open");
+    /// store.load_index_log(index_path).expect("load_index_log");
+    /// assert_eq!(store.index.len(), 1000);
     /// ```
-    pub fn for_each_kv_entry_in_storage<Func>(&mut self, mut callback: Func) -> io::Result<()>
+    pub fn bulk_load_file_at_path<I, K, V>(
+        path: &Path,
+        index_path: &Path,
+        options: StoreOptions,
+        bulk: BulkLoadOptions,
+        pairs: I,
+    ) -> io::Result<Self>
     where
-        Func: FnMut(KeyValuePair, u64) -> IndexOp,
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<ByteStr>,
+        V: AsRef<ByteStr>,
     {
-        let mut f = BufReader::new(&mut self.f);
-        let previous_position = f.seek(SeekFrom::Current(0))?;
-        f.seek(SeekFrom::Start(0))?;
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
 
-        loop {
-            let position = f.seek(SeekFrom::Current(0))?;
+        let mut store = RiaKV::bulk_load(f, options, bulk, pairs)?;
+        store.f.sync_all()?;
+        store.checkpoint_index_log(index_path)?;
 
-            let maybe_kv = RiaKV::<F>::process_record(&mut f);
+        Ok(store)
+    }
 
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
-                Err(err) => match err.kind() {
-                    io::ErrorKind::UnexpectedEof => {
-                        break;
-                    }
+    /// Compacts the storage file at the given path in place with `RiaKV::compact_into`. The
+    /// compacted copy is written next to the original file and atomically renamed over it once
+    /// complete. Returns the compacted store, with its index and keyspaces loaded.
+    ///
+    /// Positions change with compaction, so any index persisted for the storage file has to
+    /// be written again, e.g. with `RiaKV::checkpoint_index_log`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use libriakv::{RiaKV, StoreOptions};
+    ///
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    /// let index_path = std::path::Path::new("/path/to/some/file.idx");
+    ///
+    /// let mut store =
+    ///     RiaKV::compact_file_at_path(storage_path, StoreOptions::default()).expect("compact");
+    /// store.checkpoint_index_log(index_path).expect("checkpoint_index_log");
+    /// ```
+    pub fn compact_file_at_path(path: &Path, options: StoreOptions) -> io::Result<Self> {
+        let mut store = RiaKV::open_from_file_at_path_with_options(path, options)?;
+        store.load()?;
 
-                    _ => return Err(err),
-                },
-            };
+        let mut compacted = None;
+        write_file_atomically(path, |f| {
+            let dst = store.compact_into(f)?;
+            compacted = Some((dst.index, dst.keyspaces));
+            Ok(())
+        })?;
 
-            match callback(kv, position) {
-                IndexOp::Insert(kv, position) => {
-                    self.index.insert(kv.key, position);
-                }
-                IndexOp::Delete(kv, _) => {
-                    self.index.remove(&kv.key);
-                }
-                IndexOp::Nop => {}
-                IndexOp::End => {
-                    break;
-                }
-            }
+        let mut store = RiaKV::open_from_file_at_path_with_options(path, options)?;
+        if let Some((index, keyspaces)) = compacted {
+            store.index = index;
+            store.keyspaces = keyspaces;
         }
-        f.seek(SeekFrom::Start(previous_position))?;
 
-        Ok(())
+        Ok(store)
     }
 
-    /// Loads all the key value entries from the underlying storage
-    pub fn load(&mut self) -> io::Result<()> {
-        self.for_each_kv_entry_in_storage(|kv, position| {
-            if kv.value.len() > 0 {
-                IndexOp::Insert(kv, position)
-            } else {
-                IndexOp::Delete(kv, position)
-            }
+    /// Creates a storage file at the given path, with the given options, holding every key
+    /// value pair from the given dump, as restored by `RiaKV::restore`. The file is written
+    /// next to the given path and only linked into place once the whole dump is restored and
+    /// validated, so nothing is left at the path for a damaged dump. An existing file at the
+    /// path results in an `AlreadyExists` error, even if it is created while restoring.
+    ///
+    /// Returns the restored store, with its index and keyspaces loaded, along with a summary
+    /// of the dump.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::fs::File;
+    /// use libriakv::{RiaKV, StoreOptions};
+    ///
+    /// let dump = File::open("/path/to/some/file.dump").expect("open dump");
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    ///
+    /// let (store, summary) =
+    ///     RiaKV::restore_file_at_path(storage_path, StoreOptions::default(), dump)
+    ///         .expect("restore_file_at_path");
+    /// ```
+    pub fn restore_file_at_path<R: Read>(
+        path: &Path,
+        options: StoreOptions,
+        r: R,
+    ) -> io::Result<(Self, DumpSummary)> {
+        // fails early for an existing file, creating the file refuses to replace one anyway
+        if path.exists() {
+            return Err(storage_file_exists());
+        }
+
+        let mut restored = None;
+        create_file_atomically(path, |f| {
+            let mut store = RiaKV::open_from_storage(f, options)?;
+            let summary = store.restore(r)?;
+            store.f.flush()?;
+            restored = Some((store.index, store.keyspaces, summary));
+            Ok(())
         })
-    }
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => storage_file_exists(),
+            _ => err,
+        })?;
 
-    /// Gets the `KeyValuePair{}` instance stored at the given position in the
-    /// underlying storage.
-    pub fn get_at(&mut self, position: u64) -> io::Result<KeyValuePair> {
-        let mut f = BufReader::new(&mut self.f);
-        f.seek(SeekFrom::Start(position))?;
-        let kv = RiaKV::<F>::process_record(&mut f)?;
+        let mut store = RiaKV::open_from_file_at_path_with_options(path, options)?;
+        let (index, keyspaces, summary) = restored.expect("restored store");
+        store.index = index;
+        store.keyspaces = keyspaces;
 
-        Ok(kv)
+        Ok((store, summary))
     }
+}
 
-    /// Gets the value for the given key.
+impl RiaKV<MappedFile> {
+    /// Creates a new `RiaKV` instance from a file stored at the given path as the backing
+    /// store, with a memory map over the file for zero-copy reads with `RiaKV::get_ref`.
     ///
     /// # Example
     /// ```
     /// use libriakv::RiaKV;
     ///
-    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    ///
+    /// match RiaKV::open_mapped_from_file_at_path(storage_path) {
+    ///     Ok(opened_store) => {}, // use the opened store
+    ///     _ => {} // handle failure
+    /// };
+    /// ```
+    pub fn open_mapped_from_file_at_path(path: &Path) -> io::Result<Self> {
+        Self::open_mapped_from_file_at_path_with_options(path, StoreOptions::default())
+    }
+
+    /// Creates a new memory mapped `RiaKV` instance from a file stored at the given path.
+    /// The given options are used if a new storage file is created.
+    pub fn open_mapped_from_file_at_path_with_options(
+        path: &Path,
+        options: StoreOptions,
+    ) -> io::Result<Self> {
+        RiaKV::open_from_storage(MappedFile::new(open_storage_file(path)?), options)
+    }
+
+    /// Gets the key and value stored at the given position as slices into the memory
+    /// mapped storage file. The file is remapped if the record lies beyond the mapped region.
+    /// Compressed values are decompressed and encrypted records decrypted into new buffers.
+    pub fn get_at_ref(
+        &mut self,
+        position: u64,
+    ) -> io::Result<(Cow<'_, ByteStr>, Cow<'_, ByteStr>)> {
+        let header = self.header;
+        let data_start = position + header.record_header_len();
+
+        let mut record = &self.f.mapped_bytes(data_start)?[position as usize..data_start as usize];
+        let record = RecordHeader::read_from(&mut record, &header)?;
+
+        let data_end = data_start + record.data_len();
+        let data = &self.f.mapped_bytes(data_end)?[data_start as usize..data_end as usize];
+
+        record.verify_checksum(&header, data)?;
+
+        record::decode_record(&header, &record, self.cipher.as_ref(), data)
+            .map(|(_, key, value)| (key, value))
+    }
+
+    /// Gets the value for the given key as a slice into the memory mapped storage file,
+    /// without copying it. Compressed values are decompressed into a new buffer.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use libriakv::RiaKV;
+    ///
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    /// let mut store = RiaKV::open_mapped_from_file_at_path(storage_path).expect("open");
     ///
     /// store.insert(b"key", b"value").expect("insert");
-    /// store.get(b"key").expect("get").unwrap();
+    /// assert_eq!(store.get_ref(b"key").expect("get").as_deref(), Some(&b"value"[..]));
     /// ```
-    pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+    pub fn get_ref(&mut self, key: &ByteStr) -> io::Result<Option<Cow<'_, ByteStr>>> {
         let position = match self.index.get(key) {
             None => return Ok(None),
             Some(position) => *position,
         };
 
-        let kv = self.get_at(position)?;
+        let (_, value) = self.get_at_ref(position)?;
 
-        if kv.value.len() > 0 {
-            Ok(Some(kv.value))
+        if !value.is_empty() {
+            Ok(Some(value))
         } else {
             Ok(None)
         }
     }
+}
 
-    /// Finds the first `KeyValueEntry{}` corresponding to the given `ByteStr` key.
-    ///
-    /// Note: Since this implementation is an append only, log structured store,
-    /// deleted entries will always have corresponding entries.
+impl RiaKV<io::Cursor<Vec<u8>>> {
+    /// Creates a new `RiakV` instance from an empty in memory buffer [`Vec<u8>`] with the given
+    /// capacity pre-allocated. The logical length of the buffer starts at zero and grows as
+    /// records are appended to it.
     ///
     /// # Example
     /// ```
     /// use libriakv::RiaKV;
     ///
     /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
-    ///
-    /// store.insert(b"key", b"value").expect("insert");
-    /// store.find(b"key").expect("find").unwrap();
     /// ```
-    pub fn find(&mut self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
-        let mut found: Option<(u64, ByteString)> = None;
-
-        self.for_each_kv_entry_in_storage(|kv, position| {
-            if kv.key == target.to_vec() {
-                found = Some((position, kv.value));
-                IndexOp::End
-            } else {
-                IndexOp::Nop
-            }
-        })?;
+    pub fn open_from_in_memory_buffer(capacity: usize) -> Self {
+        Self::open_from_bytes(Vec::with_capacity(capacity))
+            .expect("writing to an in memory buffer does not fail")
+    }
 
-        Ok(found)
+    /// Creates a new `RiaKV` instance backed by an in memory buffer holding the given bytes.
+    /// The bytes are expected to be the contents of a storage file. The index is not loaded.
+    pub fn open_from_bytes(bytes: Vec<u8>) -> io::Result<Self> {
+        RiaKV::open_from_storage(io::Cursor::new(bytes), StoreOptions::default())
     }
 
-    /// Inserts the given key value pair into the underlying storage and returns the position
-    /// in the underlying storage file, it was written at. The index is not updated.
+    /// Creates a new `RiaKV` instance backed by an in memory copy of the storage file
+    /// at the given path. The index is not loaded.
     ///
-    /// As mentioned before, the following layout is used for storing the key value pair:
-    /// ```text
-    /// ┌────────────────┬────────────┬──────────────┬────────────────┐
-    /// │ crc32 checksum │ key length │ value length │ KeyValuePair{} │
-    /// └────────────────┴────────────┴──────────────┴────────────────┘
-    /// ```
+    /// # Example
+    /// ```no_run
+    /// use libriakv::RiaKV;
     ///
-    /// This method is intended to be used in the actual `RiaKV::insert()` implementation.
-    pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
-        let mut f = BufWriter::new(&mut self.f);
-        let key_len = key.len();
-        let val_len = value.len();
-        let mut tmp = ByteString::with_capacity(key_len + val_len);
-
-        for byte in key {
-            tmp.push(*byte);
-        }
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    ///
+    /// let mut store = RiaKV::import_from_file(storage_path).expect("import");
+    /// store.load().expect("load");
+    /// ```
+    pub fn import_from_file(path: &Path) -> io::Result<Self> {
+        Self::open_from_bytes(std::fs::read(path)?)
+    }
 
-        for byte in value {
-            tmp.push(*byte);
-        }
+    /// Writes the contents of the in memory buffer to the file at the given path. The
+    /// written file can be opened with `RiaKV::open_from_file_at_path`.
+    pub fn export_to_file(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.as_bytes())
+    }
 
-        let checksum = crc::crc32::checksum_ieee(&tmp);
-        let current_position = f.seek(SeekFrom::Current(0))?;
+    /// Returns the bytes currently stored in the in memory buffer.
+    pub fn as_bytes(&self) -> &ByteStr {
+        self.f.get_ref()
+    }
+}
+
+impl<F> RiaKV<F>
+where
+    F: Read + Write + Seek,
+{
+    /// Creates a new `RiaKV` instance over the given storage. If the storage is empty, a header
+    /// created from the given options is written to it. Otherwise the header is read from the
+    /// start of the storage, and the options it records take precedence over the given ones.
+    ///
+    /// Storage without a header (i.e. not a storage file, or a storage file written by an older
+    /// version) and storage of an older or newer format version are refused with an
+    /// `InvalidData` error. Older storage can be converted with `RiaKV::upgrade_from`.
+    ///
+    /// Encrypted storage can only be opened with the key identified in its header, supplied
+    /// with `StoreOptions::encryption`. A missing or different key, or a key supplied for
+    /// storage which is not encrypted, results in an `InvalidData` error.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io;
+    /// use libriakv::{ChecksumAlgorithm, Compression, RiaKV, StoreOptions};
+    ///
+    /// let options = StoreOptions {
+    ///     checksum: ChecksumAlgorithm::Xxh3,
+    ///     compression: Compression::Zstd,
+    ///     ..StoreOptions::default()
+    /// };
+    ///
+    /// let mut store = RiaKV::open_from_storage(io::Cursor::new(Vec::new()), options).expect("open");
+    /// store.insert(b"key", b"value").expect("insert");
+    /// ```
+    pub fn open_from_storage(mut f: F, options: StoreOptions) -> io::Result<Self> {
+        let empty = f.seek(SeekFrom::End(0))? == 0;
+
+        let header = if empty {
+            let header = Header::new(options);
+            header.write_to(&mut f)?;
+            f.flush()?;
+            header
+        } else {
+            f.seek(SeekFrom::Start(0))?;
+            Header::read_from(&mut f)?
+        };
+
+        if header.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing storage file header: not a riakv storage file, \
+                or a storage file written by an older version which needs to be upgraded",
+            ));
+        }
+
+        if !header.is_current() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "storage format version {} needs to be upgraded to version {}",
+                    header.version, FORMAT_VERSION
+                ),
+            ));
+        }
+
+        let cipher = match (header.encryption_key_id, options.encryption) {
+            (None, None) => None,
+            (Some(key_id), Some(key)) if key_id == key.id => Some(Cipher::new(&key)),
+            (Some(key_id), Some(key)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "storage is encrypted with key {}, but key {} was supplied",
+                        key_id, key.id
+                    ),
+                ))
+            }
+            (Some(key_id), None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "storage is encrypted with key {}, but no key was supplied",
+                        key_id
+                    ),
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "storage is not encrypted, but an encryption key was supplied",
+                ))
+            }
+        };
+
+        Ok(RiaKV {
+            f,
+            index: HashMap::new(),
+            header,
+            cipher,
+            sync_marker_interval: options.sync_marker_interval,
+            last_sync_marker: None,
+            index_log: None,
+            keyspaces: Keyspaces::new(empty),
+        })
+    }
+
+    /// Creates a new `RiaKV` instance over the given empty storage, holding a copy of every
+    /// record from the given source storage. The source storage may be of any supported format
+    /// version, including legacy storage without a header. The new storage is written in the
+    /// latest format version, with the checksum algorithm used by the source storage.
+    ///
+    /// The index of the returned instance is loaded.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io;
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut legacy = io::Cursor::new(Vec::new());
+    ///
+    /// // .. legacy storage without a header
+    ///
+    /// let store = RiaKV::upgrade_from(&mut legacy, io::Cursor::new(Vec::new())).expect("upgrade");
+    /// ```
+    pub fn upgrade_from<R: Read + Seek>(src: &mut R, mut f: F) -> io::Result<Self> {
+        if f.seek(SeekFrom::End(0))? != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "storage to upgrade into is not empty",
+            ));
+        }
+
+        src.seek(SeekFrom::Start(0))?;
+        let src_header = Header::read_from(src)?;
+
+        let mut store = RiaKV::open_from_storage(f, src_header.options())?;
+        store.migrate_records_from(src, &src_header, true)?;
+
+        Ok(store)
+    }
+
+    /// Appends the records of the given source storage, with the given header, and loads them
+    /// into the index. Unless `history`, only the latest record of every live key is appended,
+    /// along with the names of the live keyspaces. Returns a report of the migration.
+    fn migrate_records_from<R: Read + Seek>(
+        &mut self,
+        src: &mut R,
+        src_header: &Header,
+        history: bool,
+    ) -> io::Result<MigrationReport> {
+        let mut src = BufReader::new(src);
+        let end = src.seek(SeekFrom::End(0))?;
+
+        let live = if history {
+            None
+        } else {
+            Some(live_record_positions(&mut src, src_header, end)?)
+        };
+
+        let mut report = MigrationReport {
+            from_version: src_header.version,
+            to_version: self.header.version,
+            source_bytes: end,
+            ..MigrationReport::default()
+        };
+
+        src.seek(SeekFrom::Start(src_header.len()))?;
+
+        loop {
+            let position = src.stream_position()?;
+
+            let (position, keyspace, timestamp, kv) =
+                match read_record_skipping_markers(&mut src, src_header, None, position, end) {
+                    Ok((skipped, keyspace, timestamp, kv)) => {
+                        (position + skipped, keyspace, timestamp, kv)
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                    Err(err) => return Err(err),
+                };
+
+            report.records += 1;
+
+            if live.as_ref().is_some_and(|live| !live.contains(&position)) {
+                report.dropped_records += 1;
+                continue;
+            }
+
+            let position = self.append_record_at(keyspace, &kv.key, &kv.value, timestamp)?;
+            let op = IndexOp::for_record(kv, position);
+            apply_index_op(&mut self.index, &mut self.keyspaces, keyspace, op)?;
+        }
+
+        self.f.flush()?;
+        report.migrated_bytes = self.seek_to_end()?;
+
+        Ok(report)
+    }
+
+    /// Returns the header describing the format of the underlying storage.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Copies every record of this store, in order, into the given empty storage, which is
+    /// created with the given options. This can be used for re-encrypting a store with a new
+    /// key, or for changing the checksum or compression options of a store.
+    ///
+    /// The index of the returned instance is loaded.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io;
+    /// use libriakv::{EncryptionKey, RiaKV, StoreOptions};
+    ///
+    /// let old_key = EncryptionKey::generate(1);
+    /// let new_key = EncryptionKey::generate(2);
+    ///
+    /// let options = StoreOptions { encryption: Some(old_key), ..StoreOptions::default() };
+    /// let mut store = RiaKV::open_from_storage(io::Cursor::new(Vec::new()), options).expect("open");
+    /// store.insert(b"key", b"value").expect("insert");
+    ///
+    /// let options = StoreOptions { encryption: Some(new_key), ..StoreOptions::default() };
+    /// let mut rotated = store.copy_into(io::Cursor::new(Vec::new()), options).expect("copy");
+    ///
+    /// assert_eq!(rotated.header().encryption_key_id, Some(2));
+    /// assert_eq!(rotated.get(b"key").expect("get").unwrap(), b"value");
+    /// ```
+    pub fn copy_into<G>(&mut self, f: G, options: StoreOptions) -> io::Result<RiaKV<G>>
+    where
+        G: Read + Write + Seek,
+    {
+        let mut dst = RiaKV::open_from_storage(f, options)?;
+        if dst.seek_to_end()? != dst.header.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "storage to copy into is not empty",
+            ));
+        }
+
+        let mut result = Ok(());
+
+        self.for_each_timestamped_record_from(self.header.len(), |keyspace, timestamp, kv, _| {
+            let copied = dst
+                .append_record_at(keyspace, &kv.key, &kv.value, timestamp)
+                .and_then(|position| {
+                    let op = IndexOp::for_record(kv, position);
+                    apply_index_op(&mut dst.index, &mut dst.keyspaces, keyspace, op)
+                });
+
+            if let Err(err) = copied {
+                result = Err(err);
+                return IndexOp::End;
+            }
+
+            IndexOp::Nop
+        })?;
+
+        result?;
+        dst.f.flush()?;
+
+        Ok(dst)
+    }
+
+    /// Creates a new `RiaKV` instance over the given empty storage, holding a record for every
+    /// given key value pair, in order. An empty value is written as a tombstone.
+    ///
+    /// Unlike calling `RiaKV::insert` for every pair, the records are written through a single
+    /// buffer of `BulkLoadOptions::buffer_len` bytes without seeking, and the index is built as
+    /// they are written. With `BulkLoadOptions::dedup`, only the last pair for every key is
+    /// written, and keys whose last value is empty are left out.
+    ///
+    /// The index of the returned instance is loaded.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io;
+    /// use libriakv::{BulkLoadOptions, Dedup, RiaKV, StoreOptions};
+    ///
+    /// let pairs = vec![(b"a", b"1"), (b"b", b"2"), (b"a", b"3")];
+    /// let bulk = BulkLoadOptions { dedup: Dedup::All, ..BulkLoadOptions::default() };
+    ///
+    /// let mut store =
+    ///     RiaKV::bulk_load(io::Cursor::new(Vec::new()), StoreOptions::default(), bulk, pairs)
+    ///         .expect("bulk_load");
+    /// assert_eq!(store.get(b"a").expect("get").unwrap(), b"3");
+    /// ```
+    pub fn bulk_load<I, K, V>(
+        mut f: F,
+        options: StoreOptions,
+        bulk: BulkLoadOptions,
+        pairs: I,
+    ) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<ByteStr>,
+        V: AsRef<ByteStr>,
+    {
+        if f.seek(SeekFrom::End(0))? != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "storage to bulk load into is not empty",
+            ));
+        }
+
+        let mut store = RiaKV::open_from_storage(f, options)?;
+        let position = store.seek_to_end()?;
+
+        let mut writer = BulkWriter::new(
+            &mut store.f,
+            bulk.buffer_len,
+            &store.header,
+            store.cipher.as_ref(),
+            store.sync_marker_interval,
+            position,
+        );
+        writer.write_pairs(pairs, bulk.dedup)?;
+        let (index, last_sync_marker) = writer.finish()?;
+
+        store.index = index;
+        store.last_sync_marker = Some(last_sync_marker);
+
+        Ok(store)
+    }
+
+    /// Processes a record from the current position in the underlying storage file.
+    /// Every record (key value pair) is stored with the following layout:
+    /// ```text
+    /// ┌──────────┬───────┬───────────┬────────────┬──────────────┬────────────────┐
+    /// │ checksum │ flags │ timestamp │ key length │ value length │ KeyValuePair{} │
+    /// └──────────┴───────┴───────────┴────────────┴──────────────┴────────────────┘
+    /// ```
+    ///
+    /// The width of the checksum depends on the checksum algorithm recorded in the given
+    /// header: 32 bits for `crc32` and `crc32c`, 64 bits for `xxh3`. The flags record the
+    /// compression applied to the value and whether the record is encrypted; they are absent
+    /// in storage of format versions before 3. The timestamp records the time the record was
+    /// written at; it is absent in storage of format versions before 4.
+    ///
+    /// Reading a record from the underlying storage occurs in the following steps:
+    /// - Read the checksum, flags, key length and value length as integers with little endian
+    ///   format
+    /// - Read the next key length bytes into the key and the next value length bytes into
+    ///   the stored value, or for encrypted records, the nonce, encrypted data and tag
+    /// - Verify that the checksum of the flags and the data read matches with the checksum read
+    /// - Decrypt the key and the stored value with the given cipher if the record is encrypted
+    /// - Decompress the stored value if it is compressed
+    /// - Return `KeyValuePair { key, value }`
+    ///
+    /// Sync markers and batch markers are skipped, i.e. the next record following them is
+    /// returned. Since the end of the storage is unknown here, torn batches are not detected.
+    /// Records of named keyspaces are returned as well, with their key stripped of the keyspace
+    /// identifier.
+    ///
+    /// A record truncated by the end of the storage results in an `UnexpectedEof` error, and a
+    /// checksum mismatch in an `InvalidData` error. An encrypted record which cannot be
+    /// decrypted with the given cipher, or which is read without a cipher, also results in an
+    /// `InvalidData` error.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io;
+    /// use libriakv::{Header, RiaKV};
+    ///
+    /// let mut cursor = io::Cursor::new(vec![0; 5000]);
+    ///
+    /// // .. enter some data into the cursor
+    ///
+    /// let maybe_kv =
+    ///     RiaKV::<io::Cursor<Vec<u8>>>::process_record(&mut cursor, &Header::legacy(), None);
+    /// ```
+    pub fn process_record<R: Read>(
+        f: &mut R,
+        header: &Header,
+        cipher: Option<&Cipher>,
+    ) -> io::Result<KeyValuePair> {
+        read_record_skipping_markers(f, header, cipher, 0, u64::MAX).map(|(_, _, _, kv)| kv)
+    }
+
+    /// Seeks to the end of the underlying storage file. Any subsequent read should end in `EOF`.
+    pub fn seek_to_end(&mut self) -> io::Result<u64> {
+        self.f.seek(SeekFrom::End(0))
+    }
+
+    /// For each function for processing all `KeyValuePair{}` instances stored in the underlying
+    /// storage.
+    ///
+    /// The key value entries are processed in the following way:
+    /// - First we backup the current position of the underlying storage since it would otherwise
+    ///   be lost during scanning the entire storage file
+    /// - Next we seek to the start of the first record, right after the file header
+    /// - Now in an infinite loop, during every iteration
+    ///     - We seek to the current position
+    ///     - We read a record using `RiaKV::process_record`
+    ///     - If the record read is not an error, we operate on it using the callback
+    ///     - In the case of an error
+    ///         - For simple EOF we break out of the loop
+    ///         - In the case of any other error, we return `Err(err)`
+    /// - Now if the callback is executed, the return value is used as follows:
+    ///     - For `IndexOp::Insert` the key value pair is inserted into the index
+    ///     - For `IndexOp::Delete` the key value pair is deleted from the index if it existed in the
+    ///       index before
+    ///     - For `Index::Nop` we do nothing a continue to the next iteration
+    ///     - For `Index::End` we break out of the loop
+    /// - When we exit from the loop, we seek back to the position we saved before entering into
+    ///   the loop
+    /// - We return `Ok(())`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use libriakv::{RiaKV, IndexOp, ByteString, ByteStr};
+    /// use std::io;
+    /// use std::io::prelude::*;
+    ///
+    /// // As used in the impl{} of RiaKV itself
+    ///
+    /// fn load<F>(store: &mut RiaKV<F>) -> io::Result<()> where F: Read + Write + Seek {
+    ///     store.for_each_kv_entry_in_storage(|kv, position| {
+    ///         if !kv.value.is_empty() {
+    ///             IndexOp::Insert(kv, position)
+    ///         } else {
+    ///             IndexOp::Delete(kv, position)
+    ///         }
+    ///     })
+    /// }
+    ///
+    /// // ...
+    ///
+    /// fn find<F>(store: &mut RiaKV<F>, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>>
+    ///     where F: Read + Write + Seek, {
+    ///     
+    ///     let mut found: Option<(u64, ByteString)> = None;
+    ///
+    ///     store.for_each_kv_entry_in_storage(|kv, position| {
+    ///         if kv.key == target.to_vec() {
+    ///             found = Some((position, kv.value));
+    ///             IndexOp::End
+    ///         } else {
+    ///             IndexOp::Nop
+    ///         }
+    ///     })?;
+    ///
+    ///    Ok(found)
+    /// }
+    /// ```
+    pub fn for_each_kv_entry_in_storage<Func>(&mut self, mut callback: Func) -> io::Result<()>
+    where
+        Func: FnMut(KeyValuePair, u64) -> IndexOp,
+    {
+        self.for_each_record_from(self.header.len(), |keyspace, kv, position| {
+            if keyspace == DEFAULT_KEYSPACE_ID {
+                callback(kv, position)
+            } else {
+                IndexOp::Nop
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Processes all `KeyValuePair{}` instances stored from the given position onwards, of
+    /// every keyspace, as described for `RiaKV::for_each_kv_entry_in_storage`. Index operations
+    /// apply to the index of the keyspace of the record. Records of named keyspaces are
+    /// ignored while the keyspaces are not loaded.
+    ///
+    /// Returns the end of the last record processed, which precedes any truncated record at
+    /// the end of the underlying storage.
+    fn for_each_record_from<Func>(&mut self, start: u64, mut callback: Func) -> io::Result<u64>
+    where
+        Func: FnMut(u32, KeyValuePair, u64) -> IndexOp,
+    {
+        self.for_each_timestamped_record_from(start, |keyspace, _, kv, position| {
+            callback(keyspace, kv, position)
+        })
+    }
+
+    /// Processes all records stored from the given position onwards as with
+    /// `RiaKV::for_each_record_from`, passing the timestamp of every record to the callback
+    /// along with its keyspace.
+    fn for_each_timestamped_record_from<Func>(
+        &mut self,
+        start: u64,
+        mut callback: Func,
+    ) -> io::Result<u64>
+    where
+        Func: FnMut(u32, u64, KeyValuePair, u64) -> IndexOp,
+    {
+        let header = self.header;
+
+        let mut f = BufReader::new(&mut self.f);
+        let previous_position = f.stream_position()?;
+        let end = f.seek(SeekFrom::End(0))?;
+        f.seek(SeekFrom::Start(start))?;
+
+        let mut processed_end = start;
+
+        loop {
+            let position = f.stream_position()?;
+
+            let maybe_kv =
+                read_record_skipping_markers(&mut f, &header, self.cipher.as_ref(), position, end);
+
+            let (position, keyspace, timestamp, kv) = match maybe_kv {
+                Ok((skipped, keyspace, timestamp, kv)) => {
+                    (position + skipped, keyspace, timestamp, kv)
+                }
+                Err(err) => match err.kind() {
+                    io::ErrorKind::UnexpectedEof => {
+                        break;
+                    }
+
+                    _ => return Err(err),
+                },
+            };
+
+            let op = callback(keyspace, timestamp, kv, position);
+
+            if let (DEFAULT_KEYSPACE_ID, Some(index_log)) = (keyspace, &mut self.index_log) {
+                match &op {
+                    IndexOp::Insert(kv, position) => index_log.record(&kv.key, Some(*position)),
+                    IndexOp::Delete(kv, _) => index_log.record(&kv.key, None),
+                    _ => {}
+                }
+            }
+
+            match op {
+                IndexOp::End => {
+                    break;
+                }
+                op => apply_index_op(&mut self.index, &mut self.keyspaces, keyspace, op)?,
+            }
+
+            processed_end = f.stream_position()?;
+        }
+        f.seek(SeekFrom::Start(previous_position))?;
+
+        Ok(processed_end)
+    }
+
+    /// Scans every record in the underlying storage and reports every damaged range, i.e.
+    /// corrupt or truncated records, along with the offsets they span. Unlike `RiaKV::load`,
+    /// the scan does not stop at the first damaged record: it resumes at the next position
+    /// holding a record with a valid checksum.
+    ///
+    /// The returned report also holds the index built from the valid records, which can be
+    /// checked against a persisted index with `VerifyReport::check_index`. The index of this
+    /// instance is not modified.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"value").expect("insert");
+    ///
+    /// let report = store.verify().expect("verify");
+    /// assert!(report.is_clean());
+    /// assert!(report.check_index(&store.index).is_empty());
+    /// ```
+    pub fn verify(&mut self) -> io::Result<VerifyReport> {
+        self.scan_records(|_| Ok(()))
+    }
+
+    /// Scans every record in the underlying storage and returns statistics about them: how
+    /// many records and live keys there are, the sizes of the live keys and values, and how
+    /// many bytes are taken by stale records which compaction would reclaim. Damaged ranges
+    /// are skipped as with `RiaKV::verify`.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"value_1").expect("insert");
+    /// store.update(b"key", b"value_2").expect("update");
+    ///
+    /// let stats = store.stats().expect("stats");
+    /// assert_eq!(stats.records, 2);
+    /// assert_eq!(stats.live_keys, 1);
+    /// assert_eq!(stats.stale_bytes, stats.live_bytes);
+    /// ```
+    pub fn stats(&mut self) -> io::Result<StoreStats> {
+        let header_len = self.header.len();
+        let record_header_len = self.header.record_header_len();
+
+        let mut stats = StoreStats::default();
+        let mut record_bytes = 0;
+
+        // latest record of every live key in every keyspace: its size, and the sizes of the
+        // key and value
+        let mut latest: HashMap<(u32, ByteString), (u64, usize, usize)> = HashMap::new();
+        let mut catalog: HashMap<ByteString, u32> = HashMap::new();
+
+        let report = self.scan_records(|scanned| {
+            let size = record_header_len + scanned.record.data_len();
+            record_bytes += size;
+
+            let key = (scanned.keyspace, scanned.kv.key.clone());
+            let value = &scanned.kv.value;
+
+            if scanned.keyspace == KEYSPACE_CATALOG_ID {
+                if value.len() == KEYSPACE_ID_LEN {
+                    catalog.insert(key.1.clone(), LittleEndian::read_u32(value));
+                } else {
+                    catalog.remove(&key.1);
+                }
+            }
+
+            if value.is_empty() {
+                latest.remove(&key);
+                return Ok(());
+            }
+
+            latest.insert(key, (size, scanned.kv.key.len(), value.len()));
+            Ok(())
+        })?;
+
+        let keyspaces: HashSet<u32> = catalog.into_values().collect();
+
+        for ((keyspace, _), (size, key_len, value_len)) in latest {
+            let live = keyspace == DEFAULT_KEYSPACE_ID
+                || keyspace == KEYSPACE_CATALOG_ID
+                || keyspaces.contains(&keyspace);
+
+            if !live {
+                continue;
+            }
+
+            stats.live_bytes += size;
+
+            if keyspace != KEYSPACE_CATALOG_ID {
+                stats.live_keys += 1;
+                stats.key_sizes.add(key_len as u64);
+                stats.value_sizes.add(value_len as u64);
+            }
+        }
+
+        stats.file_size = report.end;
+        stats.records = report.records;
+        stats.tombstones = report.tombstones;
+        stats.stale_bytes = record_bytes - stats.live_bytes;
+        stats.damaged_bytes = report.damaged.iter().map(DamagedRange::len).sum();
+        stats.reclaimable_bytes = report
+            .end
+            .saturating_sub(header_len)
+            .saturating_sub(stats.live_bytes);
+
+        Ok(stats)
+    }
+
+    /// Writes a repaired copy of the underlying storage into the given writer: the header,
+    /// followed by every valid record as stored, skipping damaged ranges as found by
+    /// `RiaKV::verify`. Records are copied without being re-encoded, so the copy can be opened
+    /// with the same options as this instance.
+    ///
+    /// Returns the report of the scan over the underlying storage.
+    pub fn repair_into<W: Write>(&mut self, w: &mut W) -> io::Result<VerifyReport> {
+        let header = self.header;
+
+        let mut w = BufWriter::new(w);
+        header.write_to(&mut w)?;
+
+        let report = self.scan_records(|scanned| {
+            scanned.record.write_to(&mut w, &header)?;
+            w.write_all(&scanned.data)
+        })?;
+
+        w.flush()?;
+
+        Ok(report)
+    }
+
+    /// Scans every record in the underlying storage, resuming after damaged ranges, and calls
+    /// the given function with every valid record.
+    fn scan_records<Func>(&mut self, mut on_record: Func) -> io::Result<VerifyReport>
+    where
+        Func: FnMut(&ScannedRecord) -> io::Result<()>,
+    {
+        let header = self.header;
+        let mut report = VerifyReport::default();
+        let mut result = Ok(());
+
+        let mut f = BufReader::new(&mut self.f);
+        let previous_position = f.stream_position()?;
+
+        report.end = verify::scan_storage(&mut f, &header, self.cipher.as_ref(), |item| {
+            match item {
+                ScanItem::Record(scanned) => {
+                    if let Err(err) = on_record(&scanned) {
+                        result = Err(err);
+                        return false;
+                    }
+
+                    report.records += 1;
+
+                    if scanned.kv.value.is_empty() {
+                        report.tombstones += 1;
+                    }
+
+                    if scanned.keyspace != DEFAULT_KEYSPACE_ID {
+                        return true;
+                    }
+
+                    if !scanned.kv.value.is_empty() {
+                        report.deleted.remove(&scanned.kv.key);
+                        report.index.insert(scanned.kv.key, scanned.position);
+                    } else {
+                        report.index.remove(&scanned.kv.key);
+                        report.deleted.insert(scanned.kv.key, scanned.position);
+                    }
+                }
+                ScanItem::Damaged(damaged) => report.damaged.push(damaged),
+            }
+
+            true
+        })?;
+
+        f.seek(SeekFrom::Start(previous_position))?;
+        result?;
+
+        Ok(report)
+    }
+
+    /// Loads all the key value entries from the underlying storage, replacing the index.
+    /// Unlike `RiaKV::load`, damaged records do not fail the load: the scan resumes at the next
+    /// position holding a record with a valid checksum, as with `RiaKV::verify`. Returns the
+    /// ranges of the underlying storage which were skipped.
+    ///
+    /// Writing sync markers with `StoreOptions::sync_marker_interval` guarantees such a
+    /// position at least every interval bytes.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"value").expect("insert");
+    ///
+    /// let skipped = store.load_tolerant().expect("load_tolerant");
+    /// assert!(skipped.is_empty());
+    /// ```
+    pub fn load_tolerant(&mut self) -> io::Result<Vec<DamagedRange>> {
+        let report = self.verify()?;
+        self.index = report.index;
+        self.index_log = None;
+        self.keyspaces = Keyspaces::new(false);
+
+        Ok(report.damaged)
+    }
+
+    /// Loads all the key value entries from the underlying storage, along with the named
+    /// keyspaces and their indexes.
+    pub fn load(&mut self) -> io::Result<()> {
+        self.load_records()?;
+        Ok(())
+    }
+
+    /// Loads the index as with `RiaKV::load`, returning the end of the last record loaded.
+    fn load_records(&mut self) -> io::Result<u64> {
+        self.keyspaces = Keyspaces::new(true);
+
+        self.for_each_record_from(self.header.len(), |_, kv, position| {
+            IndexOp::for_record(kv, position)
+        })
+    }
+
+    /// Gets the `KeyValuePair{}` instance stored at the given position in the
+    /// underlying storage.
+    pub fn get_at(&mut self, position: u64) -> io::Result<KeyValuePair> {
+        let mut f = BufReader::new(&mut self.f);
+        f.seek(SeekFrom::Start(position))?;
+        let kv = RiaKV::<F>::process_record(&mut f, &self.header, self.cipher.as_ref())?;
+
+        Ok(kv)
+    }
+
+    /// Gets the value for the given key.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    ///
+    /// store.insert(b"key", b"value").expect("insert");
+    /// store.get(b"key").expect("get").unwrap();
+    /// ```
+    pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
+        let position = match self.index.get(key) {
+            None => return Ok(None),
+            Some(position) => *position,
+        };
+
+        let kv = self.get_at(position)?;
+
+        if !kv.value.is_empty() {
+            Ok(Some(kv.value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Gets a reader over the value for the given key, which streams the value from the
+    /// underlying storage instead of reading it into memory at once. Compressed values are
+    /// decompressed as they are read. The checksum of the record is verified once the whole
+    /// value has been read. Encrypted values are read and decrypted into memory at once.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io::prelude::*;
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"value").expect("insert");
+    ///
+    /// let mut value = Vec::new();
+    /// let mut reader = store.get_reader(b"key").expect("get_reader").unwrap();
+    /// reader.read_to_end(&mut value).expect("read");
+    ///
+    /// assert_eq!(value, b"value");
+    /// ```
+    pub fn get_reader(
+        &mut self,
+        key: &ByteStr,
+    ) -> io::Result<Option<ValueReader<BufReader<&mut F>>>> {
+        let position = match self.index.get(key) {
+            None => return Ok(None),
+            Some(position) => *position,
+        };
+
+        let header = self.header;
+
+        let mut f = BufReader::new(&mut self.f);
+        f.seek(SeekFrom::Start(position))?;
+
+        let record = RecordHeader::read_from(&mut f, &header)?;
+
+        if record.is_tombstone() {
+            return Ok(None);
+        }
+
+        if record.is_encrypted() {
+            let data = read_bytes(&mut f, record.data_len())?;
+
+            record.verify_checksum(&header, &data)?;
+
+            let (_, stored_value) =
+                record::decrypt_record(&header, &record, self.cipher.as_ref(), &data)?;
+
+            return Ok(Some(ValueReader::from_stored_value(
+                stored_value,
+                record.compression(),
+            )?));
+        }
+
+        let mut hasher = RecordHeader::hasher(&header, record.flags, record.timestamp);
+        hasher.write(&read_bytes(&mut f, record.key_len as u64)?);
+
+        Ok(Some(ValueReader::new(
+            f,
+            record.val_len as u64,
+            record.compression(),
+            hasher,
+            record.checksum,
+        )?))
+    }
+
+    /// Finds the first `KeyValueEntry{}` corresponding to the given `ByteStr` key.
+    ///
+    /// Note: Since this implementation is an append only, log structured store,
+    /// deleted entries will always have corresponding entries.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    ///
+    /// store.insert(b"key", b"value").expect("insert");
+    /// store.find(b"key").expect("find").unwrap();
+    /// ```
+    pub fn find(&mut self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
+        let mut found: Option<(u64, ByteString)> = None;
+
+        self.for_each_kv_entry_in_storage(|kv, position| {
+            if kv.key == target.to_vec() {
+                found = Some((position, kv.value));
+                IndexOp::End
+            } else {
+                IndexOp::Nop
+            }
+        })?;
+
+        Ok(found)
+    }
+
+    /// Returns every record for the given key, oldest first, as its position in the underlying
+    /// storage along with its value. Deletions show up as empty values, i.e. tombstones.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    ///
+    /// store.insert(b"key", b"value").expect("insert");
+    /// store.delete(b"key").expect("delete");
+    ///
+    /// let history = store.history(b"key").expect("history");
+    /// assert_eq!(history.len(), 2);
+    /// assert!(history[1].1.is_empty());
+    /// ```
+    pub fn history(&mut self, target: &ByteStr) -> io::Result<Vec<(u64, ByteString)>> {
+        let mut history = Vec::new();
+
+        self.for_each_kv_entry_in_storage(|kv, position| {
+            if kv.key == target {
+                history.push((position, kv.value));
+            }
+
+            IndexOp::Nop
+        })?;
+
+        Ok(history)
+    }
+
+    /// Appends the given key value pair to the end of the underlying storage and returns the
+    /// position in the underlying storage file, it was written at. The index is not updated.
+    ///
+    /// As mentioned before, the following layout is used for storing the key value pair:
+    /// ```text
+    /// ┌──────────┬───────┬───────────┬────────────┬──────────────┬────────────────┐
+    /// │ checksum │ flags │ timestamp │ key length │ value length │ KeyValuePair{} │
+    /// └──────────┴───────┴───────────┴────────────┴──────────────┴────────────────┘
+    /// ```
+    ///
+    /// Values at least as long as the compression threshold are compressed with the compression
+    /// algorithm recorded in the header, unless compression does not reduce their size. For
+    /// encrypted storage, the key and the stored value are then encrypted with a fresh nonce.
+    ///
+    /// This method is intended to be used in the actual `RiaKV::insert()` implementation.
+    pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
+        self.append_record(DEFAULT_KEYSPACE_ID, key, value)
+    }
+
+    /// Appends a record for the given key value pair of the given keyspace to the end of the
+    /// underlying storage, as with `RiaKV::insert_but_ignore_index`.
+    fn append_record(&mut self, keyspace: u32, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
+        self.append_record_at(keyspace, key, value, record::now())
+    }
+
+    /// Appends a record as with `RiaKV::append_record`, written at the given timestamp instead
+    /// of the current time. Used when copying records, to keep the time they were written at.
+    fn append_record_at(
+        &mut self,
+        keyspace: u32,
+        key: &ByteStr,
+        value: &ByteStr,
+        timestamp: u64,
+    ) -> io::Result<u64> {
+        self.write_sync_marker_if_due()?;
+
+        let header = self.header;
+
+        let (record, data) = record::encode_record(
+            &header,
+            self.cipher.as_ref(),
+            keyspace,
+            key,
+            value,
+            timestamp,
+        )?;
+
+        let mut f = BufWriter::new(&mut self.f);
+        let current_position = f.seek(SeekFrom::End(0))?;
+
+        record.write_to(&mut f, &header)?;
+        for part in &data {
+            f.write_all(part)?;
+        }
+
+        Ok(current_position)
+    }
+
+    /// Inserts a value of `len` bytes read from the given reader for the given key and updates
+    /// the index. The value is streamed into the underlying storage without being buffered in
+    /// memory as a whole. Values at least as long as the compression threshold are compressed
+    /// as they are written.
+    ///
+    /// Since the checksum and stored length are only known once the whole value has been
+    /// written, a placeholder record header is written first and patched afterwards. If the
+    /// reader ends before `len` bytes are read, an `UnexpectedEof` error is returned and the
+    /// index is not updated. Any other error of the reader is returned as well. The partially
+    /// written record is then overwritten with padding skipped by readers, so that records
+    /// appended afterwards are loaded. Only a crash while streaming leaves a torn record at the
+    /// end of the storage: the placeholder claims the longest possible value, reaching past
+    /// the end of the storage, so `RiaKV::load` treats it as truncated.
+    ///
+    /// Encrypted records can only be sealed as a whole, so for encrypted storage the value is
+    /// read into memory before it is inserted.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// let value = vec![7; 1 << 20];
+    ///
+    /// store
+    ///     .put_from_reader(b"key", &mut &value[..], value.len() as u64)
+    ///     .expect("put_from_reader");
+    /// assert_eq!(store.get(b"key").expect("get").unwrap(), value);
+    /// ```
+    pub fn put_from_reader<R: Read>(
+        &mut self,
+        key: &ByteStr,
+        reader: &mut R,
+        len: u64,
+    ) -> io::Result<()> {
+        if len > u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "value too large for a single record",
+            ));
+        }
+
+        if self.cipher.is_some() {
+            let mut value = ByteString::new();
+            reader.take(len).read_to_end(&mut value)?;
+
+            if value.len() as u64 != len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "reader ended before the given length",
+                ));
+            }
+
+            return self.insert(key, &value);
+        }
+
+        self.write_sync_marker_if_due()?;
+
+        let header = self.header;
+
+        let compression = if len == 0 || len < header.compression_threshold as u64 {
+            Compression::None
+        } else {
+            header.compression
+        };
+
+        let mut f = BufWriter::new(&mut self.f);
+        let position = f.seek(SeekFrom::End(0))?;
+
+        let streamed =
+            stream_record(&mut f, &header, compression, key, reader, len).and_then(|record| {
+                f.seek(SeekFrom::Start(position))?;
+                record.write_to(&mut f, &header)?;
+                f.seek(SeekFrom::End(0))?;
+                f.flush()
+            });
+
+        if let Err(err) = streamed {
+            drop(f);
+
+            // padding is best effort, the error of the write takes precedence
+            let _ = self.pad_torn_record(position);
+            return Err(err);
+        }
+
+        drop(f);
+
+        self.index_insert(key, position);
+        Ok(())
+    }
+
+    /// Overwrites everything from the given position to the end of the underlying storage, the
+    /// bytes written for a record or batch which failed to be written, with padding skipped by
+    /// readers.
+    fn pad_torn_record(&mut self, position: u64) -> io::Result<()> {
+        let header = self.header;
+        let record_header_len = header.record_header_len();
+
+        let mut f = BufWriter::new(&mut self.f);
+        let end = f.seek(SeekFrom::End(0))?;
+
+        // too short to hold a record header, as if the write was torn by a crash
+        if end - position < record_header_len {
+            return Ok(());
+        }
+
+        f.seek(SeekFrom::Start(position))?;
+
+        let mut remaining = end - position;
+        while remaining > 0 {
+            let mut len = remaining - record_header_len;
+            if len > u32::MAX as u64 {
+                len = (u32::MAX as u64).min(len - record_header_len);
+            }
+
+            record::padding(&header, len as u32).write_to(&mut f, &header)?;
+            io::copy(&mut io::repeat(0).take(len), &mut f)?;
+
+            remaining -= record_header_len + len;
+        }
+
+        f.flush()
+    }
+
+    /// Appends a sync marker to the underlying storage if sync markers are written and at
+    /// least the sync marker interval has been written since the last one.
+    fn write_sync_marker_if_due(&mut self) -> io::Result<()> {
+        let interval = match self.sync_marker_interval {
+            None => return Ok(()),
+            Some(interval) => interval,
+        };
+
+        let end = self.f.seek(SeekFrom::End(0))?;
+        let last_sync_marker = *self.last_sync_marker.get_or_insert(end);
+
+        if end - last_sync_marker < interval {
+            return Ok(());
+        }
+
+        let header = self.header;
+        let (record, data) = record::sync_marker(&header);
+
+        let mut f = BufWriter::new(&mut self.f);
+        record.write_to(&mut f, &header)?;
+        f.write_all(data)?;
+        f.flush()?;
+
+        self.last_sync_marker = Some(end);
+        Ok(())
+    }
+
+    /// Inserts the given position for the given key into the index, tracking the change for
+    /// the index log.
+    fn index_insert(&mut self, key: &ByteStr, position: u64) {
+        if let Some(index_log) = &mut self.index_log {
+            index_log.record(key, Some(position));
+        }
+
+        self.index.insert(key.to_vec(), position);
+    }
+
+    /// Inserts the given key value pair into the underlying storage and updates the index.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"value").expect("insert");
+    /// ```
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        let position = self.insert_but_ignore_index(key, value)?;
+
+        self.index_insert(key, position);
+        Ok(())
+    }
+
+    /// Updates the value for the given key by inserting a duplicate entry into the storage and
+    /// updating the index.
+    #[inline]
+    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
+        self.insert(key, value)
+    }
+
+    /// Writes every insert and delete of the given batch and updates the index. The records
+    /// of the batch are written at once, preceded by a batch marker holding their length, so
+    /// that readers skip all of them if the write is torn, e.g. by a crash. Sync markers are
+    /// not written within a batch.
+    ///
+    /// If writing fails, the index is not updated and the bytes written for the batch are
+    /// overwritten with padding skipped by readers, as for `RiaKV::put_from_reader`.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::{RiaKV, WriteBatch};
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"old", b"value").expect("insert");
+    ///
+    /// let mut batch = WriteBatch::new();
+    /// batch.insert(b"new", b"value");
+    /// batch.delete(b"old");
+    /// store.write_batch(&batch).expect("write_batch");
+    ///
+    /// assert_eq!(store.get(b"old").expect("get"), None);
+    /// ```
+    pub fn write_batch(&mut self, batch: &WriteBatch) -> io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        self.write_sync_marker_if_due()?;
+
+        let header = self.header;
+
+        let mut records = ByteString::new();
+        let mut offsets = Vec::with_capacity(batch.len());
+
+        let now = record::now();
+
+        for (key, value, timestamp) in batch.pairs() {
+            let (record, data) = record::encode_record(
+                &header,
+                self.cipher.as_ref(),
+                DEFAULT_KEYSPACE_ID,
+                key,
+                value,
+                timestamp.unwrap_or(now),
+            )?;
+
+            offsets.push(records.len() as u64);
+
+            record.write_to(&mut records, &header)?;
+            for part in &data {
+                records.extend_from_slice(part);
+            }
+        }
+
+        let (marker, marker_data) = record::batch_marker(&header, records.len() as u64);
+
+        let mut f = BufWriter::new(&mut self.f);
+        let marker_position = f.seek(SeekFrom::End(0))?;
+
+        let written = marker
+            .write_to(&mut f, &header)
+            .and_then(|_| f.write_all(&marker_data))
+            .and_then(|_| f.write_all(&records))
+            .and_then(|_| f.flush());
+
+        drop(f);
+
+        if let Err(err) = written {
+            // padding is best effort, the error of the write takes precedence
+            let _ = self.pad_torn_record(marker_position);
+            return Err(err);
+        }
+
+        let start = marker_position + header.record_header_len() + marker.data_len();
+        for ((key, _, _), offset) in batch.pairs().iter().zip(offsets) {
+            self.index_insert(key, start + offset);
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the value for the given key by inserting a _tombstone_ entry:
+    ///
+    /// # Equivalent implementation
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"").expect("delete");
+    /// ```
+    #[inline]
+    pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
+        self.insert(key, b"")
+    }
+}
+
+impl<F> RiaKV<F>
+where
+    F: Read + Write + Seek,
+{
+    /// Loads the index from the given object implementing the `Read` trait.
+    /// This done by deserializing the contents of the file using
+    /// `bincode::deserialize_from(reader)` into a `HashMap<ByteString, u64>`.
+    ///
+    /// The `Read` object is wrapped into a `io::BufReader` instance before
+    /// reading the contents.
+    ///
+    /// For encrypted storage, the index is expected to be encrypted as written by
+    /// `RiaKV::persist_index`, with the key the storage is encrypted with.
+    pub fn load_index<R: Read>(
+        &mut self,
+        index_file: &mut R,
+    ) -> result::Result<(), bincode::Error> {
+        let mut reader = BufReader::new(index_file);
+
+        let index = match &self.cipher {
+            None => bincode::deserialize_from(reader),
+            Some(cipher) => {
+                let key_id = reader.read_u32::<LittleEndian>()?;
+                if key_id != cipher.key_id() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "index is encrypted with key {}, but the storage with key {}",
+                            key_id,
+                            cipher.key_id()
+                        ),
+                    )
+                    .into());
+                }
+
+                let mut sealed = ByteString::new();
+                reader.read_to_end(&mut sealed)?;
+
+                bincode::deserialize(&cipher.open(&sealed, &key_id.to_le_bytes())?)
+            }
+        };
+
+        match index {
+            Ok(index) => {
+                self.index = index;
+                self.index_log = None;
+                Ok(())
+            }
+            Err(value) => Err(value),
+        }
+    }
+
+    /// Writes the index into the given object implementing the `Write` trait,
+    /// using `bincode::serialize_into(writer, &self.index)`
+    ///
+    /// The `Write` object is wrapped into a `io::BufWriter` instance before
+    /// writing the contents.
+    ///
+    /// For encrypted storage, the serialized index is encrypted with the key the storage is
+    /// encrypted with. The identifier of the key is written before the encrypted index.
+    pub fn persist_index<W: Write>(
+        &self,
+        index_file: &mut W,
+    ) -> result::Result<(), bincode::Error> {
+        let mut writer = BufWriter::new(index_file);
+
+        match &self.cipher {
+            None => bincode::serialize_into(&mut writer, &self.index)?,
+            Some(cipher) => {
+                let key_id = cipher.key_id().to_le_bytes();
+                let sealed = cipher.seal(&bincode::serialize(&self.index)?, &key_id)?;
+
+                writer.write_all(&key_id)?;
+                writer.write_all(&sealed)?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Loads the index from the file at the given path, as written by
+    /// `RiaKV::persist_index_to_path`. A missing index file results in a `NotFound` error, and
+    /// an index file which cannot be deserialized in an `InvalidData` error.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use libriakv::RiaKV;
+    ///
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    /// let index_path = std::path::Path::new("/path/to/some/file.idx");
+    ///
+    /// let mut store = RiaKV::open_from_file_at_path(storage_path).expect("open");
+    ///
+    /// if store.load_index_from_path(index_path).is_err() {
+    ///     store.load().expect("load");
+    /// }
+    /// ```
+    pub fn load_index_from_path(&mut self, path: &Path) -> io::Result<()> {
+        let mut index_file = File::open(path)?;
+
+        self.load_index(&mut index_file)
+            .map_err(|err| bincode_to_io_error(*err))
+    }
+
+    /// Loads the index from the index log at the given path, as written by
+    /// `RiaKV::persist_index_log`: the last checkpoint is loaded and every delta appended after
+    /// it is applied. Records appended to the underlying storage after the index log was last
+    /// written, e.g. before a crash, are then loaded from the storage.
+    ///
+    /// A missing index log results in a `NotFound` error, and a corrupt index log, or one
+    /// written for a different storage file, in an `InvalidData` error. Index logs record the
+    /// creation time of the storage file and a checksum of its bytes preceding the length they
+    /// are up to date with, so that index logs left over from before the storage file was
+    /// replaced, e.g. by a compaction or a migration, are refused. In both cases, the index can
+    /// be rebuilt from the storage with `RiaKV::load`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use libriakv::RiaKV;
+    ///
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    /// let index_path = std::path::Path::new("/path/to/some/file.idx");
+    ///
+    /// let mut store = RiaKV::open_from_file_at_path(storage_path).expect("open");
+    ///
+    /// if store.load_index_log(index_path).is_err() {
+    ///     store.load().expect("load");
+    /// }
+    ///
+    /// store.insert(b"key", b"value").expect("insert");
+    /// store.persist_index_log(index_path).expect("persist_index_log");
+    /// ```
+    pub fn load_index_log(&mut self, path: &Path) -> io::Result<()> {
+        let (index, state) = index_log::read_index_log(path, self.cipher.as_ref())?;
+        let storage = state.storage();
+        let storage_end = storage.end;
+
+        if storage_end > self.seek_to_end()?
+            || storage_end < self.header.len()
+            || self.storage_identity(storage_end)? != storage
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "index log was written for a different storage file",
+            ));
+        }
+
+        self.index = index;
+        self.index_log = Some(state);
+
+        self.for_each_record_from(storage_end, |_, kv, position| {
+            IndexOp::for_record(kv, position)
+        })?;
+
+        Ok(())
+    }
+
+    /// Persists the index to the index log at the given path. If the index was loaded from or
+    /// last persisted to this index log, only the entries changed since are appended to it as a
+    /// delta. Otherwise, or once the deltas hold as many entries as the last checkpoint (and
+    /// at least `MIN_CHECKPOINT_INTERVAL`), a new checkpoint of the whole index replaces the
+    /// index log atomically, as with `RiaKV::checkpoint_index_log`.
+    ///
+    /// Changes to the index are tracked by the methods of this type; changes made directly to
+    /// the `index` field are not, and require a checkpoint to be persisted.
+    pub fn persist_index_log(&mut self, path: &Path) -> io::Result<()> {
+        let storage_end = self.seek_to_end()?;
+        let storage = self.storage_identity(storage_end)?;
+
+        match &mut self.index_log {
+            Some(state) if !state.needs_checkpoint() => {
+                index_log::append_delta(path, state, storage, self.cipher.as_ref())
+            }
+            _ => self.checkpoint_index_log(path),
+        }
+    }
+
+    /// Writes a checkpoint of the whole index to a new index log at the given path, which
+    /// replaces any existing index log atomically.
+    pub fn checkpoint_index_log(&mut self, path: &Path) -> io::Result<()> {
+        let storage_end = self.seek_to_end()?;
+        let storage = self.storage_identity(storage_end)?;
+
+        self.index_log = Some(index_log::write_checkpoint(
+            path,
+            &self.index,
+            storage,
+            self.cipher.as_ref(),
+        )?);
+
+        Ok(())
+    }
+
+    /// Returns the identity of the underlying storage up to the given length, as recorded in
+    /// index logs.
+    fn storage_identity(&mut self, end: u64) -> io::Result<StorageIdentity> {
+        let start = end.saturating_sub(index_log::STORAGE_TAIL_LEN);
+
+        let mut tail = ByteString::new();
+        self.f.seek(SeekFrom::Start(start))?;
+        (&mut self.f).take(end - start).read_to_end(&mut tail)?;
+
+        let mut hasher = ChecksumAlgorithm::Xxh3.hasher();
+        hasher.write(&tail);
+
+        Ok(StorageIdentity {
+            created_at: self.header.created_at,
+            end,
+            tail_checksum: hasher.finish(),
+        })
+    }
+
+    /// Writes the index to the file at the given path atomically, with `RiaKV::persist_index`:
+    /// the index is written to a temporary file next to it, which is synced to disk and then
+    /// renamed over the given path. After a crash, the path holds either the previous or the
+    /// new index, never a partially written one.
+    pub fn persist_index_to_path(&self, path: &Path) -> io::Result<()> {
+        write_file_atomically(path, |index_file| {
+            self.persist_index(index_file)
+                .map_err(|err| bincode_to_io_error(*err))
+        })
+    }
+
+    /// Writes a hot backup of this store: a copy of the underlying storage up to its current
+    /// end, at the given path, along with a checkpoint of the index matching that end, in a new
+    /// index log at `index_path`. As the storage is append only, its prefix up to the recorded
+    /// end is a consistent copy of the store; records appended after the end is recorded, e.g.
+    /// by other processes writing to the same storage file while the copy is made, are left
+    /// out of the backup. Both files replace any existing ones atomically.
+    ///
+    /// The index of this store is rebuilt from the underlying storage first, as with
+    /// `RiaKV::load`, so that the checkpoint matches the copy even if the index was not loaded
+    /// or is stale. The recorded end is the end of the last complete record: a partially
+    /// written record at the end of the storage, e.g. one being appended by another process,
+    /// is never copied. The backup is opened like any other storage file, with its index loaded
+    /// by `RiaKV::load_index_log`. Returns the length of the backup.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use libriakv::RiaKV;
+    ///
+    /// let storage_path = std::path::Path::new("/path/to/some/file.db");
+    /// let backup_path = std::path::Path::new("/path/to/backups/file.db");
+    /// let backup_index_path = std::path::Path::new("/path/to/backups/file.idx");
+    ///
+    /// let mut store = RiaKV::open_from_file_at_path(storage_path).expect("open");
+    /// store.backup_to(backup_path, backup_index_path).expect("backup_to");
+    ///
+    /// let mut backup = RiaKV::open_from_file_at_path(backup_path).expect("open");
+    /// backup.load_index_log(backup_index_path).expect("load_index_log");
+    /// ```
+    pub fn backup_to(&mut self, path: &Path, index_path: &Path) -> io::Result<u64> {
+        let end = self.load_records()?;
+
+        write_file_atomically(path, |backup| {
+            let mut f = BufReader::new(&mut self.f);
+            f.seek(SeekFrom::Start(0))?;
+
+            if io::copy(&mut f.take(end), backup)? != end {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "storage truncated while copying the backup",
+                ));
+            }
+
+            Ok(())
+        })?;
+
+        let storage = self.storage_identity(end)?;
+        index_log::write_checkpoint(index_path, &self.index, storage, self.cipher.as_ref())?;
+
+        Ok(end)
+    }
+}
+
+impl<F> RiaKV<F>
+where
+    F: Read + Write + Seek,
+{
+    /// Writes every live key value pair, ordered by key, as a JSON document into the given
+    /// writer, which is buffered internally. Keys and values are encoded as text with the given
+    /// encoding, which is recorded in the document:
+    /// ```text
+    /// {"encoding":"utf8","pairs":[
+    /// {"key":"key","value":"value"}
+    /// ]}
+    /// ```
+    ///
+    /// Returns the number of pairs written. Keys or values which cannot be represented with the
+    /// given encoding, i.e. invalid UTF-8 for `Encoding::Utf8`, result in an `InvalidData`
+    /// error.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::{Encoding, RiaKV};
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"value").expect("insert");
+    ///
+    /// let mut json = Vec::new();
+    /// store.export_json(&mut json, Encoding::Hex).expect("export_json");
+    ///
+    /// let mut imported = RiaKV::open_from_in_memory_buffer(5000);
+    /// imported.import_json(&json[..]).expect("import_json");
+    /// assert_eq!(imported.get(b"key").expect("get").unwrap(), b"value");
+    /// ```
+    pub fn export_json<W: Write>(&mut self, w: W, encoding: Encoding) -> io::Result<u64> {
+        let mut writer = JsonWriter::new(BufWriter::new(w), encoding)?;
+
+        self.for_each_live_pair_sorted(|key, value| writer.write_pair(key, value))?;
+
+        writer.finish()
+    }
+
+    /// Inserts every key value pair from a JSON document written by `RiaKV::export_json`,
+    /// decoded with the encoding recorded in the document. The whole document is decoded
+    /// before anything is inserted, so a malformed document results in an `InvalidData`
+    /// error without modifying the store. Returns the number of pairs inserted.
+    pub fn import_json<R: Read>(&mut self, r: R) -> io::Result<u64> {
+        let document: JsonDocument = serde_json::from_reader(BufReader::new(r))?;
+
+        self.insert_exported_pairs(document.pairs, document.encoding)
+    }
+
+    /// Writes every live key value pair, ordered by key, as CSV with a `key,value` header row
+    /// into the given writer, which is buffered internally. Keys and values are encoded as text
+    /// with the given encoding, which is not recorded and has to be given again on import.
+    /// Returns the number of pairs written.
+    pub fn export_csv<W: Write>(&mut self, w: W, encoding: Encoding) -> io::Result<u64> {
+        let mut writer = csv::Writer::from_writer(w);
+        writer.write_record(["key", "value"])?;
+
+        let mut written = 0;
+
+        self.for_each_live_pair_sorted(|key, value| {
+            writer.write_record([encoding.encode(key)?, encoding.encode(value)?])?;
+            written += 1;
+            Ok(())
+        })?;
+
+        writer.flush()?;
+
+        Ok(written)
+    }
+
+    /// Inserts every key value pair from CSV written by `RiaKV::export_csv`, decoded with the
+    /// given encoding. As with `RiaKV::import_json`, everything is decoded before anything is
+    /// inserted. Returns the number of pairs inserted.
+    pub fn import_csv<R: Read>(&mut self, r: R, encoding: Encoding) -> io::Result<u64> {
+        let mut reader = csv::Reader::from_reader(r);
+
+        let pairs = reader
+            .deserialize()
+            .collect::<result::Result<Vec<ExportedPair>, csv::Error>>()?;
+
+        self.insert_exported_pairs(pairs, encoding)
+    }
+
+    /// Calls the given function with every live key value pair, ordered by key.
+    fn for_each_live_pair_sorted<Func>(&mut self, mut write: Func) -> io::Result<()>
+    where
+        Func: FnMut(&ByteStr, &ByteStr) -> io::Result<()>,
+    {
+        let mut keys: Vec<ByteString> = self.index.keys().cloned().collect();
+        keys.sort_unstable();
+
+        for key in keys {
+            if let Some(value) = self.get(&key)? {
+                write(&key, &value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the given exported pairs with the given encoding and inserts them.
+    fn insert_exported_pairs(
+        &mut self,
+        pairs: Vec<ExportedPair>,
+        encoding: Encoding,
+    ) -> io::Result<u64> {
+        let pairs = pairs
+            .iter()
+            .map(|pair| Ok((encoding.decode(&pair.key)?, encoding.decode(&pair.value)?)))
+            .collect::<io::Result<Vec<(ByteString, ByteString)>>>()?;
+
+        for (key, value) in &pairs {
+            self.insert(key, value)?;
+        }
+
+        Ok(pairs.len() as u64)
+    }
+}
+
+impl<F> RiaKV<F>
+where
+    F: Read + Write + Seek,
+{
+    /// Returns a handle to the keyspace with the given name, creating the keyspace if it does
+    /// not exist yet. Creating a keyspace appends a record mapping its name to a new keyspace
+    /// identifier.
+    ///
+    /// The keyspaces are loaded by `RiaKV::load`. Otherwise, e.g. after `RiaKV::load_index`,
+    /// they are loaded from the underlying storage the first time they are used.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"default").expect("insert");
+    ///
+    /// let mut users = store.keyspace("users").expect("keyspace");
+    /// users.insert(b"key", b"user").expect("insert");
+    /// assert_eq!(users.get(b"key").expect("get").unwrap(), b"user");
+    ///
+    /// assert_eq!(store.get(b"key").expect("get").unwrap(), b"default");
+    /// ```
+    pub fn keyspace(&mut self, name: &str) -> io::Result<Keyspace<'_, F>> {
+        self.load_keyspaces()?;
+
+        let id = match self.keyspaces.id(name) {
+            Some(id) => id,
+            None => {
+                let id = self.keyspaces.next_id()?;
+                let kv = KeyValuePair {
+                    key: name.as_bytes().to_vec(),
+                    value: id.to_le_bytes().to_vec(),
+                };
+
+                let position = self.append_record(KEYSPACE_CATALOG_ID, &kv.key, &kv.value)?;
+                self.keyspaces
+                    .apply(KEYSPACE_CATALOG_ID, kv, position, true)?;
+                id
+            }
+        };
+
+        Ok(Keyspace { store: self, id })
+    }
+
+    /// Returns the names of all the keyspaces, in order.
+    pub fn keyspace_names(&mut self) -> io::Result<Vec<String>> {
+        self.load_keyspaces()?;
+
+        Ok(self
+            .keyspaces
+            .catalog()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    /// Drops the keyspace with the given name, along with all its keys. Only a _tombstone_ for
+    /// the name of the keyspace is appended; the records of the dropped keyspace are ignored
+    /// from then on, and left in the underlying storage until it is compacted with
+    /// `RiaKV::compact_into`. Returns whether the keyspace existed.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.keyspace("sessions").expect("keyspace").insert(b"id", b"data").expect("insert");
+    ///
+    /// assert!(store.drop_keyspace("sessions").expect("drop_keyspace"));
+    /// assert!(store.keyspace("sessions").expect("keyspace").is_empty());
+    /// ```
+    pub fn drop_keyspace(&mut self, name: &str) -> io::Result<bool> {
+        self.load_keyspaces()?;
+
+        if self.keyspaces.id(name).is_none() {
+            return Ok(false);
+        }
+
+        let kv = KeyValuePair {
+            key: name.as_bytes().to_vec(),
+            value: ByteString::new(),
+        };
+
+        let position = self.append_record(KEYSPACE_CATALOG_ID, &kv.key, &kv.value)?;
+        self.keyspaces
+            .apply(KEYSPACE_CATALOG_ID, kv, position, false)?;
+
+        Ok(true)
+    }
+
+    /// Loads the keyspaces from the underlying storage, unless they are already loaded.
+    fn load_keyspaces(&mut self) -> io::Result<()> {
+        if self.keyspaces.loaded {
+            return Ok(());
+        }
+
+        self.keyspaces = Keyspaces::new(true);
+
+        let loaded = self.for_each_record_from(self.header.len(), |keyspace, kv, position| {
+            if keyspace != DEFAULT_KEYSPACE_ID {
+                IndexOp::for_record(kv, position)
+            } else {
+                IndexOp::Nop
+            }
+        });
+
+        if loaded.is_err() {
+            self.keyspaces = Keyspaces::new(false);
+        }
+
+        loaded.map(|_| ())
+    }
+
+    /// Writes a compacted copy of this store into the given empty storage: only the latest
+    /// record of every live key is copied, in the order they were written, along with the
+    /// names of the live keyspaces. Tombstones, overwritten records and the records of dropped
+    /// keyspaces are left out. Records are copied as stored, so they are neither decompressed
+    /// nor decrypted.
+    ///
+    /// The index of this store is expected to be loaded. The index of the returned instance,
+    /// and its keyspaces, are loaded.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io;
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"old").expect("insert");
+    /// store.update(b"key", b"new").expect("update");
+    ///
+    /// let mut compacted = store.compact_into(io::Cursor::new(Vec::new())).expect("compact");
+    /// assert!(compacted.as_bytes().len() < store.as_bytes().len());
+    /// assert_eq!(compacted.get(b"key").expect("get").unwrap(), b"new");
+    /// ```
+    pub fn compact_into<G>(&mut self, mut f: G) -> io::Result<RiaKV<G>>
+    where
+        G: Read + Write + Seek,
+    {
+        if f.seek(SeekFrom::End(0))? != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "storage to compact into is not empty",
+            ));
+        }
+
+        self.load_keyspaces()?;
+
+        self.header.write_to(&mut f)?;
+
+        let mut dst = RiaKV {
+            f,
+            index: HashMap::new(),
+            header: self.header,
+            cipher: self.cipher.clone(),
+            sync_marker_interval: self.sync_marker_interval,
+            last_sync_marker: None,
+            index_log: None,
+            keyspaces: Keyspaces::new(true),
+        };
+
+        for (name, id) in self.keyspaces.catalog() {
+            let kv = KeyValuePair {
+                key: name.into_bytes(),
+                value: id.to_le_bytes().to_vec(),
+            };
+
+            let position = dst.append_record(KEYSPACE_CATALOG_ID, &kv.key, &kv.value)?;
+            dst.keyspaces
+                .apply(KEYSPACE_CATALOG_ID, kv, position, true)?;
+        }
+
+        let mut live: Vec<(u64, u32, ByteString)> = self
+            .index
+            .iter()
+            .map(|(key, &position)| (position, DEFAULT_KEYSPACE_ID, key.clone()))
+            .collect();
+
+        for (keyspace, index) in self.keyspaces.indexes() {
+            live.extend(
+                index
+                    .iter()
+                    .map(|(key, &position)| (position, keyspace, key.clone())),
+            );
+        }
+
+        live.sort_unstable_by_key(|&(position, _, _)| position);
+
+        for (position, keyspace, key) in live {
+            let (record, data) = self.read_raw_record(position)?;
+            if record.is_tombstone() {
+                continue;
+            }
+
+            let position = dst.append_raw_record(&record, &data)?;
+            let kv = KeyValuePair {
+                key,
+                value: ByteString::new(),
+            };
+            apply_index_op(
+                &mut dst.index,
+                &mut dst.keyspaces,
+                keyspace,
+                IndexOp::Insert(kv, position),
+            )?;
+        }
+
+        dst.f.flush()?;
+
+        Ok(dst)
+    }
+
+    /// Reads the record at the given position as stored, verifying its checksum.
+    fn read_raw_record(&mut self, position: u64) -> io::Result<(RecordHeader, ByteString)> {
+        let header = self.header;
+
+        let mut f = BufReader::new(&mut self.f);
+        f.seek(SeekFrom::Start(position))?;
+
+        let record = RecordHeader::read_from(&mut f, &header)?;
+        let data = read_bytes(&mut f, record.data_len())?;
+        record.verify_checksum(&header, &data)?;
+
+        Ok((record, data))
+    }
+
+    /// Appends a record as stored to the end of the underlying storage, returning its position.
+    fn append_raw_record(&mut self, record: &RecordHeader, data: &ByteStr) -> io::Result<u64> {
+        self.write_sync_marker_if_due()?;
+
+        let header = self.header;
+
+        let mut f = BufWriter::new(&mut self.f);
+        let position = f.seek(SeekFrom::End(0))?;
+
+        record.write_to(&mut f, &header)?;
+        f.write_all(data)?;
+
+        Ok(position)
+    }
+}
+
+impl<F> RiaKV<F>
+where
+    F: Read + Write + Seek,
+{
+    /// Writes a dump of this store into the given writer, in the format described in the
+    /// `dump` module: every named keyspace, followed by its key value pairs. Keys and values
+    /// are written decoded, so the dump can be restored into a store with different options.
+    ///
+    /// By default, only the latest value of every live key is written, ordered by key within
+    /// each keyspace. With `DumpOptions::history`, every record of the default keyspace and of
+    /// the live named keyspaces is written instead, in the order written, including
+    /// overwritten values and tombstones.
+    ///
+    /// The index of this store is expected to be loaded. Returns a summary of the dump.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::{DumpOptions, RiaKV};
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"value").expect("insert");
+    /// store.keyspace("users").expect("keyspace").insert(b"key", b"user").expect("insert");
+    ///
+    /// let mut dump = Vec::new();
+    /// store.dump(&mut dump, DumpOptions::default()).expect("dump");
+    ///
+    /// let mut restored = RiaKV::open_from_in_memory_buffer(5000);
+    /// let summary = restored.restore(&dump[..]).expect("restore");
+    /// assert_eq!(summary.pairs, 2);
+    /// assert_eq!(restored.keyspace("users").expect("keyspace").get(b"key").expect("get").unwrap(), b"user");
+    /// ```
+    pub fn dump<W: Write>(&mut self, w: W, options: DumpOptions) -> io::Result<DumpSummary> {
+        self.load_keyspaces()?;
+
+        let mut writer = DumpWriter::new(w, options.history)?;
+        let catalog = self.keyspaces.catalog();
+
+        if options.history {
+            for (name, id) in &catalog {
+                writer.write_keyspace(*id, name)?;
+            }
+
+            let live: HashSet<u32> = catalog.iter().map(|&(_, id)| id).collect();
+            let mut result = Ok(());
+
+            self.for_each_record_from(self.header.len(), |keyspace, kv, _| {
+                if keyspace != DEFAULT_KEYSPACE_ID && !live.contains(&keyspace) {
+                    return IndexOp::Nop;
+                }
+
+                if let Err(err) = writer.write_pair(keyspace, &kv.key, &kv.value) {
+                    result = Err(err);
+                    return IndexOp::End;
+                }
+
+                IndexOp::Nop
+            })?;
+
+            result?;
+        } else {
+            self.for_each_live_pair_sorted(|key, value| {
+                writer.write_pair(DEFAULT_KEYSPACE_ID, key, value)
+            })?;
+
+            for (name, id) in catalog {
+                writer.write_keyspace(id, &name)?;
+
+                let mut keyspace = Keyspace { store: self, id };
+                for key in keyspace.keys() {
+                    if let Some(value) = keyspace.get(&key)? {
+                        writer.write_pair(id, &key, &value)?;
+                    }
+                }
+            }
+        }
+
+        writer.finish()
+    }
+
+    /// Inserts every key value pair from a dump written by `RiaKV::dump`, creating the named
+    /// keyspaces it holds. Tombstones in dumps with history are written as deletes, so the
+    /// history of the dumped store is replayed.
+    ///
+    /// Entries are inserted as they are read. A dump failing validation, e.g. a truncated dump
+    /// or one with a checksum mismatch, results in an error once the damage is found, with the
+    /// entries before it inserted. Use `RiaKV::restore_file_at_path` to restore into a fresh
+    /// storage file which is only created for valid dumps. Returns a summary of the dump.
+    pub fn restore<R: Read>(&mut self, r: R) -> io::Result<DumpSummary> {
+        let mut reader = DumpReader::new(r)?;
+        let mut names = HashMap::new();
+
+        while let Some(entry) = reader.next_entry()? {
+            match entry {
+                DumpEntry::Keyspace(id, name) => {
+                    self.keyspace(&name)?;
+                    names.insert(id, name);
+                }
+                DumpEntry::Pair(DEFAULT_KEYSPACE_ID, key, value) => self.insert(&key, &value)?,
+                DumpEntry::Pair(id, key, value) => {
+                    let name = names.get(&id).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("dump references undeclared keyspace {}", id),
+                        )
+                    })?;
+
+                    self.keyspace(name)?.insert(&key, &value)?;
+                }
+            }
+        }
+
+        Ok(reader.summary())
+    }
+}
+
+impl<F> RiaKV<F>
+where
+    F: Read + Write + Seek,
+{
+    /// Returns the time the live value for the given key was written at, in microseconds since
+    /// the unix epoch. Values copied by compaction, migration or merges keep the time they were
+    /// first written at, and values migrated from format versions without timestamps have a
+    /// timestamp of `0`. Returns `None` for keys which are missing or deleted.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    /// store.insert(b"key", b"old").expect("insert");
+    /// let old = store.timestamp(b"key").expect("timestamp").unwrap();
+    ///
+    /// store.update(b"key", b"new").expect("update");
+    /// assert!(store.timestamp(b"key").expect("timestamp").unwrap() >= old);
+    /// assert_eq!(store.timestamp(b"missing").expect("timestamp"), None);
+    /// ```
+    pub fn timestamp(&mut self, key: &ByteStr) -> io::Result<Option<u64>> {
+        let position = match self.index.get(key) {
+            None => return Ok(None),
+            Some(position) => *position,
+        };
+
+        let (record, _) = self.read_raw_record(position)?;
+        if record.is_tombstone() {
+            return Ok(None);
+        }
+
+        Ok(Some(record.timestamp))
+    }
+
+    /// Compares the live key value pairs of the default keyspace of this store, the left
+    /// store, with those of the given store, the right store. Keys are taken from the indexes
+    /// of both stores, which are expected to be loaded, and values are compared as decoded, so
+    /// stores with different options can be compared. Named keyspaces are ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::RiaKV;
+    ///
+    /// let mut staging = RiaKV::open_from_in_memory_buffer(5000);
+    /// staging.insert(b"key", b"new").expect("insert");
+    ///
+    /// let mut production = RiaKV::open_from_in_memory_buffer(5000);
+    /// production.insert(b"key", b"old").expect("insert");
+    ///
+    /// let diff = staging.diff(&mut production).expect("diff");
+    /// assert_eq!(diff.changed, vec![b"key".to_vec()]);
+    /// ```
+    pub fn diff<G>(&mut self, other: &mut RiaKV<G>) -> io::Result<StoreDiff>
+    where
+        G: Read + Write + Seek,
+    {
+        let mut keys: Vec<ByteString> = self
+            .index
+            .keys()
+            .chain(other.index.keys())
+            .cloned()
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut diff = StoreDiff::default();
+
+        for key in keys {
+            match (self.get(&key)?, other.get(&key)?) {
+                (Some(left), Some(right)) if left == right => diff.unchanged += 1,
+                (Some(_), Some(_)) => diff.changed.push(key),
+                (Some(_), None) => diff.only_left.push(key),
+                (None, Some(_)) => diff.only_right.push(key),
+                (None, None) => {}
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /// Merges the live key value pairs of the default keyspace of the given store, the right
+    /// store, into this store, the left store. Keys only found in the right store are inserted,
+    /// keys only found in this store are kept, and keys with different values are resolved
+    /// with the given policy. The changes are written as a single batch with
+    /// `RiaKV::write_batch`, so either all or none of them are read back after a crash; the
+    /// values written are held in memory until then. Records written into this store keep the
+    /// timestamps of the records of the right store they were copied from.
+    ///
+    /// As with `RiaKV::diff`, the indexes of both stores are expected to be loaded, and named
+    /// keyspaces are ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::{ConflictPolicy, RiaKV};
+    ///
+    /// let mut production = RiaKV::open_from_in_memory_buffer(5000);
+    /// production.insert(b"key", b"old").expect("insert");
+    ///
+    /// let mut staging = RiaKV::open_from_in_memory_buffer(5000);
+    /// staging.insert(b"key", b"new").expect("insert");
+    /// staging.insert(b"other", b"value").expect("insert");
+    ///
+    /// let summary = production.merge(&mut staging, ConflictPolicy::RightWins).expect("merge");
+    /// assert_eq!((summary.inserted, summary.overwritten), (1, 1));
+    /// assert_eq!(production.get(b"key").expect("get").unwrap(), b"new");
+    /// ```
+    pub fn merge<G>(
+        &mut self,
+        other: &mut RiaKV<G>,
+        policy: ConflictPolicy,
+    ) -> io::Result<MergeSummary>
+    where
+        G: Read + Write + Seek,
+    {
+        let diff = self.diff(other)?;
+
+        let mut summary = MergeSummary::default();
+        let mut batch = WriteBatch::new();
+
+        for key in &diff.only_right {
+            if let (Some(value), Some(timestamp)) = (other.get(key)?, other.timestamp(key)?) {
+                batch.insert_at(key, &value, timestamp);
+                summary.inserted += 1;
+            }
+        }
+
+        for key in &diff.changed {
+            let right_wins = match policy {
+                ConflictPolicy::LeftWins => false,
+                ConflictPolicy::RightWins => true,
+                ConflictPolicy::LatestTimestamp => other.timestamp(key)? > self.timestamp(key)?,
+            };
+
+            if !right_wins {
+                summary.kept += 1;
+                continue;
+            }
+
+            if let (Some(value), Some(timestamp)) = (other.get(key)?, other.timestamp(key)?) {
+                batch.insert_at(key, &value, timestamp);
+                summary.overwritten += 1;
+            }
+        }
+
+        self.write_batch(&batch)?;
+
+        Ok(summary)
+    }
+}
+
+fn storage_file_exists() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "storage file to restore into already exists",
+    )
+}
+
+/// Opens the storage file at the given path for reading and writing, creating it if required.
+fn open_storage_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+/// Writes a file at the given path atomically: the contents are written to a temporary file
+/// next to it using the given function, which is synced to disk and then renamed over the
+/// given path. Either the previous or the new contents are found at the path after a crash.
+fn write_file_atomically<Func>(path: &Path, write: Func) -> io::Result<()>
+where
+    Func: FnOnce(&mut File) -> io::Result<()>,
+{
+    let tmp_path = write_temporary_file(path, write)?;
+    std::fs::rename(&tmp_path, path)?;
+
+    sync_parent_dir(path)
+}
+
+/// Creates a file at the given path atomically, as with `write_file_atomically`, failing with
+/// an `AlreadyExists` error if a file exists at the path. The temporary file is hard linked to
+/// the path, which never replaces an existing file, even one created after the contents were
+/// written.
+fn create_file_atomically<Func>(path: &Path, write: Func) -> io::Result<()>
+where
+    Func: FnOnce(&mut File) -> io::Result<()>,
+{
+    let tmp_path = write_temporary_file(path, write)?;
+    let linked = std::fs::hard_link(&tmp_path, path);
+    let _ = std::fs::remove_file(&tmp_path);
+    linked?;
+
+    sync_parent_dir(path)
+}
+
+/// Writes a temporary file next to the given path using the given function and syncs it to
+/// disk. Returns the path of the temporary file, which is removed if writing fails.
+fn write_temporary_file<Func>(path: &Path, write: Func) -> io::Result<PathBuf>
+where
+    Func: FnOnce(&mut File) -> io::Result<()>,
+{
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut tmp = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+
+    let result = write(&mut tmp).and_then(|_| tmp.sync_all());
+
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+
+    Ok(tmp_path)
+}
+
+/// Syncs the directory holding the given path, so that a file renamed or linked to the path
+/// survives a crash. Only done on unix, where directories can be opened and synced.
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            File::open(parent)?.sync_all()?;
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+/// Returns the positions of the latest records of the live keys in the given storage, with the
+/// given header, along with those of the names of the live keyspaces.
+fn live_record_positions<R: Read + Seek>(
+    src: &mut R,
+    header: &Header,
+    end: u64,
+) -> io::Result<HashSet<u64>> {
+    let mut latest: HashMap<(u32, ByteString), (u64, bool)> = HashMap::new();
+    let mut catalog: HashMap<ByteString, u32> = HashMap::new();
+
+    src.seek(SeekFrom::Start(header.len()))?;
+
+    loop {
+        let position = src.stream_position()?;
+
+        let (position, keyspace, kv) =
+            match read_record_skipping_markers(src, header, None, position, end) {
+                Ok((skipped, keyspace, _, kv)) => (position + skipped, keyspace, kv),
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+
+        if keyspace == KEYSPACE_CATALOG_ID {
+            match kv.value.len() {
+                0 => catalog.remove(&kv.key),
+                4 => catalog.insert(kv.key.clone(), LittleEndian::read_u32(&kv.value)),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid keyspace catalog record",
+                    ))
+                }
+            };
+        }
+
+        latest.insert((keyspace, kv.key), (position, kv.value.is_empty()));
+    }
+
+    let keyspaces: HashSet<u32> = catalog.into_values().collect();
+
+    Ok(latest
+        .into_iter()
+        .filter(|((keyspace, _), (_, tombstone))| {
+            !tombstone
+                && (*keyspace == DEFAULT_KEYSPACE_ID
+                    || *keyspace == KEYSPACE_CATALOG_ID
+                    || keyspaces.contains(keyspace))
+        })
+        .map(|(_, (position, _))| position)
+        .collect())
+}
+
+/// Applies the given index operation to the index of the given keyspace: the default index, or
+/// the index of a named keyspace, if the keyspaces are loaded.
+fn apply_index_op(
+    index: &mut HashMap<ByteString, u64>,
+    keyspaces: &mut Keyspaces,
+    keyspace: u32,
+    op: IndexOp,
+) -> io::Result<()> {
+    match op {
+        IndexOp::Insert(kv, position) if keyspace == DEFAULT_KEYSPACE_ID => {
+            index.insert(kv.key, position);
+        }
+        IndexOp::Delete(kv, _) if keyspace == DEFAULT_KEYSPACE_ID => {
+            index.remove(&kv.key);
+        }
+        IndexOp::Insert(kv, position) if keyspaces.loaded => {
+            keyspaces.apply(keyspace, kv, position, true)?;
+        }
+        IndexOp::Delete(kv, position) if keyspaces.loaded => {
+            keyspaces.apply(keyspace, kv, position, false)?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Converts an error from (de)serializing with `bincode` into an `io::Error`. Errors which are not
+/// I/O errors result in an `InvalidData` error.
+fn bincode_to_io_error(err: bincode::ErrorKind) -> io::Error {
+    match err {
+        bincode::ErrorKind::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+/// Reads the next record from the given reader, at the given position of a storage of `end`
+/// bytes, skipping sync markers and batch markers. Returns the number of bytes skipped before
+/// the record along with its keyspace, timestamp and key value pair.
+///
+/// A batch extending past the end of the storage results in an `UnexpectedEof` error, as for
+/// a truncated record, so that none of the records of a torn batch are read.
+fn read_record_skipping_markers<R: Read>(
+    f: &mut R,
+    header: &Header,
+    cipher: Option<&Cipher>,
+    position: u64,
+    end: u64,
+) -> io::Result<(u64, u32, u64, KeyValuePair)> {
+    let mut skipped = 0;
+
+    loop {
+        let record = RecordHeader::read_from(f, header)?;
+
+        let data = read_bytes(f, record.data_len())?;
+
+        record.verify_checksum(header, &data)?;
+
+        if record.is_sync_marker() || record.is_batch_marker() {
+            skipped += header.record_header_len() + record.data_len();
+
+            if record.is_batch_marker()
+                && position + skipped + record::batch_len(&record, &data)? > end
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "batch truncated in storage",
+                ));
+            }
+
+            continue;
+        }
+
+        let (keyspace, key, value) = record::decode_record(header, &record, cipher, &data)?;
+
+        return Ok((
+            skipped,
+            keyspace,
+            record.timestamp,
+            KeyValuePair {
+                key: key.into_owned(),
+                value: value.into_owned(),
+            },
+        ));
+    }
+}
+
+/// Writes a placeholder record header at the current position of the given writer, followed by
+/// the given key and a value of `len` bytes streamed from the given reader with the given
+/// compression. Returns the record header to patch the placeholder with.
+///
+/// The placeholder has a value length of `u32::MAX`, so that a record left unpatched by a crash
+/// reaches past the end of the storage and is read as truncated rather than corrupt.
+fn stream_record<W, R>(
+    f: &mut W,
+    header: &Header,
+    compression: Compression,
+    key: &ByteStr,
+    reader: &mut R,
+    len: u64,
+) -> io::Result<RecordHeader>
+where
+    W: Write,
+    R: Read,
+{
+    let mut record = RecordHeader {
+        checksum: 0,
+        flags: compression.id(),
+        timestamp: record::now(),
+        key_len: key.len() as u32,
+        val_len: u32::MAX,
+    };
+
+    record.write_to(f, header)?;
+    f.write_all(key)?;
+
+    let mut hasher = RecordHeader::hasher(header, record.flags, record.timestamp);
+    hasher.write(key);
+
+    let mut encoder = compression.encoder(HashingWriter::new(f, &mut hasher))?;
+
+    let mut reader = reader.take(len);
+    let mut buf = [0; 64 * 1024];
+    let mut read = 0;
+
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        encoder.write_all(&buf[..n])?;
+        read += n as u64;
+    }
+
+    let stored_len = encoder.finish()?.written();
+
+    if read != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "reader ended before the given length",
+        ));
+    }
+
+    if stored_len > u32::MAX as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "value too large for a single record",
+        ));
+    }
+
+    record.checksum = hasher.finish();
+    record.val_len = stored_len as u32;
+
+    Ok(record)
+}
+
+/// Reads exactly `len` bytes from the given reader into a new `ByteString`. At most 64 KiB are
+/// allocated up front, so that lengths reaching past the end of the storage, as in the
+/// placeholder of a record torn while streamed, fail without allocating them.
+fn read_bytes<R: Read>(f: &mut R, len: u64) -> io::Result<ByteString> {
+    let mut bytes = ByteString::with_capacity(len.min(64 * 1024) as usize);
+    f.take(len).read_to_end(&mut bytes)?;
+
+    if bytes.len() != len as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "record truncated in storage",
+        ));
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        BulkLoadOptions, ChecksumAlgorithm, Codec, Compression, ConflictPolicy, Damage, Dedup,
+        DumpOptions, Encoding, EncryptionKey, Header, IndexMismatch, MigrateOptions, RiaKV,
+        StoreOptions, TypedRiaKV, WriteBatch, FORMAT_VERSION, MAGIC,
+    };
+
+    #[test]
+    fn insert() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        store.insert(b"key", b"value").expect("insert");
+    }
+
+    #[test]
+    fn insert_get() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+
+        let kv_pairs = [
+            (b"12345", b"1qwerrtyui"),
+            (b"asdef", b"1zxcvnnnqq"),
+            (b"1asdf", b"qwertynnii"),
+            (b"1zxcv", b"1lllllpppq"),
+            (b"qwert", b"1zxcqqqqee"),
+            (b"abjkl", b"1aassddwww"),
+            (b"nmkli", b"1qaazzssqq"),
+            (b"asdff", b"1ppppkkkkq"),
+        ];
+
+        for kv in kv_pairs {
+            store.insert(kv.0, kv.1).expect("insert");
+        }
+
+        for kv in kv_pairs {
+            let value = store.get(kv.0).expect("get").unwrap();
+            assert_eq!(value, kv.1.to_vec());
+        }
+    }
+
+    #[test]
+    fn update_get() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+
+        let kv_pairs = [
+            (b"12345", b"1qwerrtyui_1"),
+            (b"asdef", b"1zxcvnnnqq_1"),
+            (b"1asdf", b"qwertynnii_1"),
+            (b"1zxcv", b"1lllllpppq_1"),
+            (b"qwert", b"1zxcqqqqee_1"),
+            (b"abjkl", b"1aassddwww_1"),
+            (b"nmkli", b"1qaazzssqq_1"),
+            (b"asdff", b"1ppppkkkkq_1"),
+            (b"asdef", b"1zxcvnnnqq_2"),
+            (b"1asdf", b"qwertynnii_2"),
+            (b"1zxcv", b"1lllllpppq_2"),
+            (b"abjkl", b"1aassddwww_2"),
+            (b"asdef", b"1zxcvnnnqq_3"),
+            (b"1zxcv", b"1lllllpppq_3"),
+            (b"asdff", b"1ppppkkkkq_3"),
+            (b"nmkli", b"1qaazzssqq_4"),
+            (b"asdef", b"1zxcvnnnqq_4"),
+            (b"asdef", b"1zxcvnnnqq_4"),
+        ];
+
+        let final_kv_pairs = [
+            (b"12345", b"1qwerrtyui_1"),
+            (b"asdef", b"1zxcvnnnqq_4"),
+            (b"1asdf", b"qwertynnii_2"),
+            (b"1zxcv", b"1lllllpppq_3"),
+            (b"qwert", b"1zxcqqqqee_1"),
+            (b"abjkl", b"1aassddwww_2"),
+            (b"nmkli", b"1qaazzssqq_4"),
+            (b"asdff", b"1ppppkkkkq_3"),
+        ];
+
+        for kv in kv_pairs {
+            store.update(kv.0, kv.1).expect("update");
+        }
+
+        for kv in final_kv_pairs {
+            let value = store.get(kv.0).expect("get").unwrap();
+            assert_eq!(value, kv.1.to_vec());
+        }
+    }
+
+    #[test]
+    fn delete_and_find() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+
+        let kv_pairs = [
+            (b"12345", b"1qwerrtyui"),
+            (b"asdef", b"1zxcvnnnqq"),
+            (b"1asdf", b"qwertynnii"),
+            (b"1zxcv", b"1lllllpppq"),
+            (b"qwert", b"1zxcqqqqee"),
+            (b"abjkl", b"1aassddwww"),
+            (b"nmkli", b"1qaazzssqq"),
+            (b"asdff", b"1ppppkkkkq"),
+        ];
+
+        for kv in kv_pairs {
+            store.insert(kv.0, kv.1).expect("insert");
+        }
+
+        let kv_pairs_to_delete = [
+            (b"abjkl", b"1aassddwww"),
+            (b"nmkli", b"1qaazzssqq"),
+            (b"asdff", b"1ppppkkkkq"),
+        ];
+
+        let kv_pairs_untouched = [
+            (b"12345", b"1qwerrtyui"),
+            (b"asdef", b"1zxcvnnnqq"),
+            (b"1asdf", b"qwertynnii"),
+            (b"1zxcv", b"1lllllpppq"),
+            (b"qwert", b"1zxcqqqqee"),
+        ];
+
+        for kv in kv_pairs_to_delete {
+            store.delete(kv.0).expect("delete");
+        }
+
+        for kv in kv_pairs_untouched {
+            let value = store.get(kv.0).expect("get").unwrap();
+            assert_eq!(value, kv.1.to_vec());
+        }
+
+        for kv in kv_pairs_to_delete {
+            let value = store.get(kv.0).expect("get");
+            assert_eq!(value, None);
+        }
+
+        for kv in kv_pairs {
+            store.find(kv.0).expect("find").unwrap();
+        }
+    }
+
+    #[test]
+    fn history() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+
+        store.insert(b"key", b"value_1").expect("insert");
+        store.insert(b"other", b"value").expect("insert");
+        store.update(b"key", b"value_2").expect("update");
+        store.delete(b"key").expect("delete");
+        store.insert(b"key", b"value_3").expect("insert");
+
+        let history = store.history(b"key").expect("history");
+        let values: Vec<&[u8]> = history.iter().map(|(_, value)| &value[..]).collect();
+        assert_eq!(values, vec![&b"value_1"[..], b"value_2", b"", b"value_3"]);
+
+        assert!(history.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(history[3].0, store.index[&b"key".to_vec()]);
+
+        assert!(store.history(b"missing").expect("history").is_empty());
+    }
+
+    #[test]
+    fn load_empty_in_memory_buffer() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        store.load().expect("load");
+        assert!(store.index.is_empty());
+        assert_eq!(store.as_bytes().len() as u64, store.header().len());
+
+        let data_start = store.header().len();
+
+        store.insert(b"key", b"value").expect("insert");
+        store.get(b"key").expect("get").unwrap();
+        assert_eq!(store.index[&b"key".to_vec()], data_start);
+
+        store.insert(b"key", b"value_1").expect("insert");
+        assert_eq!(
+            store.get(b"key").expect("get").unwrap(),
+            b"value_1".to_vec()
+        );
+        assert_eq!(store.find(b"key").expect("find").unwrap().0, data_start);
+    }
+
+    #[test]
+    fn export_import_in_memory_buffer() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("store.db");
+
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        store.insert(b"key", b"value").expect("insert");
+        store.insert(b"deleted", b"value").expect("insert");
+        store.delete(b"deleted").expect("delete");
+        store.export_to_file(&path).expect("export");
+
+        let mut imported = RiaKV::import_from_file(&path).expect("import");
+        imported.load().expect("load");
+        assert_eq!(imported.as_bytes(), store.as_bytes());
+        assert_eq!(
+            imported.get(b"key").expect("get").unwrap(),
+            b"value".to_vec()
+        );
+        assert_eq!(imported.get(b"deleted").expect("get"), None);
+
+        let mut opened = RiaKV::open_from_file_at_path(&path).expect("open");
+        opened.load().expect("load");
+        assert_eq!(opened.get(b"key").expect("get").unwrap(), b"value".to_vec());
+    }
+
+    #[test]
+    fn mapped_get_ref() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("store.db");
+
+        let mut store = RiaKV::open_mapped_from_file_at_path(&path).expect("open");
+        assert_eq!(store.get_ref(b"key").expect("get"), None);
+
+        store.insert(b"key", b"value").expect("insert");
+        assert_eq!(
+            store.get_ref(b"key").expect("get").as_deref(),
+            Some(&b"value"[..])
+        );
+
+        // appended records lie beyond the mapped region and force a remap
+        store.insert(b"other", b"other_value").expect("insert");
+        store.delete(b"key").expect("delete");
+        assert_eq!(store.get_ref(b"key").expect("get"), None);
+        assert_eq!(
+            store.get_ref(b"other").expect("get").as_deref(),
+            Some(&b"other_value"[..])
+        );
+
+        let mut reopened = RiaKV::open_mapped_from_file_at_path(&path).expect("open");
+        reopened.load().expect("load");
+        assert_eq!(
+            reopened.get_ref(b"other").expect("get").as_deref(),
+            Some(&b"other_value"[..])
+        );
+        assert_eq!(
+            reopened.get(b"other").expect("get"),
+            Some(b"other_value".to_vec())
+        );
+    }
+
+    #[test]
+    fn mapped_file_in_place_write() {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("store.db");
+        std::fs::write(&path, b"header").expect("write");
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .expect("open");
+        let mut mapped = crate::MappedFile::new(file);
+        assert_eq!(mapped.mapped_bytes(6).expect("mapped_bytes"), b"header");
+
+        // writes within the mapped length unmap the file until the next read
+        mapped.seek(SeekFrom::Start(0)).expect("seek");
+        mapped.write_all(b"HEAD").expect("write_all");
+        assert_eq!(mapped.mapped_len(), 0);
+        assert_eq!(mapped.mapped_bytes(6).expect("mapped_bytes"), b"HEADer");
+    }
+
+    #[test]
+    fn put_from_reader_get_reader() {
+        use std::io::Read;
+
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        let value: Vec<u8> = (0..3 * 64 * 1024 + 17).map(|i| i as u8).collect();
+
+        store.insert(b"before", b"value").expect("insert");
+        store
+            .put_from_reader(b"key", &mut &value[..], value.len() as u64)
+            .expect("put_from_reader");
+        store.insert(b"after", b"value").expect("insert");
+
+        let mut reader = store.get_reader(b"key").expect("get_reader").unwrap();
+        assert_eq!(reader.stored_len(), value.len() as u64);
+
+        let mut read_value = Vec::new();
+        reader.read_to_end(&mut read_value).expect("read");
+        assert_eq!(read_value, value);
+
+        store.delete(b"key").expect("delete");
+        assert!(store.get_reader(b"key").expect("get_reader").is_none());
+        assert!(store.get_reader(b"missing").expect("get_reader").is_none());
+
+        let mut loaded = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+        loaded.load().expect("load");
+        assert_eq!(
+            loaded.get(b"after").expect("get").unwrap(),
+            b"value".to_vec()
+        );
+        assert_eq!(loaded.find(b"key").expect("find").unwrap().1, value);
+    }
+
+    #[test]
+    fn put_from_short_reader() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+
+        let err = store
+            .put_from_reader(b"key", &mut &b"short"[..], 10)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(store.get(b"key").expect("get"), None);
+
+        // records appended after the failed write are not corrupted by it
+        store.insert(b"other", b"value").expect("insert");
+        assert_eq!(store.get(b"other").expect("get"), Some(b"value".to_vec()));
+
+        let mut reopened = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+        reopened.load().expect("load");
+        assert_eq!(reopened.get(b"key").expect("get"), None);
+        assert_eq!(
+            reopened.get(b"other").expect("get"),
+            Some(b"value".to_vec())
+        );
+        assert!(reopened.verify().expect("verify").is_clean());
+    }
+
+    #[test]
+    fn put_from_reader_torn_by_crash() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("store.db");
+
+        let mut store = RiaKV::open_from_file_at_path(&path).expect("open");
+        store.insert(b"key", b"value").expect("insert");
+        let header = *store.header();
+        drop(store);
+        let stored = std::fs::read(&path).expect("read");
+
+        // the bytes a crash leaves before the placeholder of a streamed record is patched
+        let value = vec![7; 1000];
+        let mut streamed = Vec::new();
+        super::stream_record(
+            &mut streamed,
+            &header,
+            Compression::None,
+            b"streamed",
+            &mut &value[..],
+            value.len() as u64,
+        )
+        .expect("stream_record");
+
+        for torn_len in [streamed.len() / 2, streamed.len()] {
+            let mut torn = stored.clone();
+            torn.extend_from_slice(&streamed[..torn_len]);
+            std::fs::write(&path, &torn).expect("write");
+
+            let mut reopened = RiaKV::open_from_file_at_path(&path).expect("open");
+            reopened.load().expect("load");
+            assert_eq!(reopened.get(b"key").expect("get").unwrap(), b"value");
+            assert_eq!(reopened.get(b"streamed").expect("get"), None);
+        }
+    }
+
+    #[test]
+    fn checksum_algorithms() {
+        let algorithms = [
+            ChecksumAlgorithm::Crc32,
+            ChecksumAlgorithm::Crc32c,
+            ChecksumAlgorithm::Xxh3,
+        ];
+
+        for checksum in algorithms {
+            let storage = std::io::Cursor::new(Vec::new());
+            let options = StoreOptions {
+                checksum,
+                ..StoreOptions::default()
+            };
+
+            let mut store = RiaKV::open_from_storage(storage, options).expect("open");
+            assert_eq!(store.header().version, FORMAT_VERSION);
+            assert_eq!(store.header().checksum, checksum);
+
+            store.insert(b"key", b"value").expect("insert");
+            store.insert(b"other", b"other_value").expect("insert");
+            store.delete(b"other").expect("delete");
+
+            // the header of an existing store takes precedence over the given options
+            let mut reopened = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+            assert_eq!(reopened.header().checksum, checksum);
+
+            reopened.load().expect("load");
+            assert_eq!(
+                reopened.get(b"key").expect("get").unwrap(),
+                b"value".to_vec()
+            );
+            assert_eq!(reopened.get(b"other").expect("get"), None);
+        }
+    }
+
+    fn legacy_storage() -> Vec<u8> {
+        use byteorder::{LittleEndian, WriteBytesExt};
+
+        let mut bytes = Vec::new();
+
+        for (key, value) in [
+            (&b"key"[..], &b"value"[..]),
+            (b"deleted", b"value"),
+            (b"deleted", b""),
+        ] {
+            let mut data = key.to_vec();
+            data.extend_from_slice(value);
+
+            bytes
+                .write_u32::<LittleEndian>(crc::crc32::checksum_ieee(&data))
+                .unwrap();
+            bytes.write_u32::<LittleEndian>(key.len() as u32).unwrap();
+            bytes.write_u32::<LittleEndian>(value.len() as u32).unwrap();
+            bytes.extend_from_slice(&data);
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn refuse_storage_without_current_header() {
+        let err = RiaKV::open_from_bytes(legacy_storage()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let mut future_version = MAGIC.to_vec();
+        future_version.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        future_version.resize(64, 0);
+
+        let err = RiaKV::open_from_bytes(future_version).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn upgrade_legacy_storage() {
+        let mut legacy = std::io::Cursor::new(legacy_storage());
+        let storage = std::io::Cursor::new(Vec::new());
+
+        let mut store = RiaKV::upgrade_from(&mut legacy, storage).expect("upgrade");
+        assert!(store.header().is_current());
+        assert_eq!(store.header().checksum, ChecksumAlgorithm::Crc32);
+        assert_eq!(store.get(b"key").expect("get").unwrap(), b"value".to_vec());
+        assert_eq!(store.get(b"deleted").expect("get"), None);
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("store.db");
+        std::fs::write(&path, legacy_storage()).expect("write");
+
+        assert!(RiaKV::open_from_file_at_path(&path).is_err());
+        assert!(RiaKV::upgrade_file_at_path(&path).expect("upgrade"));
+        assert!(!RiaKV::upgrade_file_at_path(&path).expect("upgrade"));
+
+        let mut opened = RiaKV::open_from_file_at_path(&path).expect("open");
+        opened.load().expect("load");
+        assert_eq!(opened.get(b"key").expect("get").unwrap(), b"value".to_vec());
+        assert_eq!(opened.get(b"deleted").expect("get"), None);
+
+        // the compact header of storage files of format version 1
+        let mut compact = MAGIC.to_vec();
+        compact.extend_from_slice(&1u16.to_le_bytes());
+        compact.push(ChecksumAlgorithm::Crc32.id());
+
+        let header = Header::read_from(&mut &compact[..]).expect("read_from");
+        assert_eq!((header.version, header.len()), (1, compact.len() as u64));
+        let mut written = Vec::new();
+        header.write_to(&mut written).expect("write_to");
+        assert_eq!(written, compact);
+
+        compact.extend_from_slice(&legacy_storage());
+        std::fs::write(&path, &compact).expect("write");
+
+        let err = RiaKV::open_from_file_at_path(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(RiaKV::upgrade_file_at_path(&path).expect("upgrade"));
+
+        let mut opened = RiaKV::open_from_file_at_path(&path).expect("open");
+        opened.load().expect("load");
+        assert_eq!(opened.get(b"key").expect("get").unwrap(), b"value".to_vec());
+        assert_eq!(opened.get(b"deleted").expect("get"), None);
+    }
+
+    #[test]
+    fn compressed_values() {
+        use std::io::Read;
+
+        let small = b"small value".to_vec();
+        let large: Vec<u8> = b"{\"key\": \"value\"}".repeat(4096);
+        let incompressible: Vec<u8> = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let options = StoreOptions {
+                compression,
+                compression_threshold: 64,
+                ..StoreOptions::default()
+            };
+
+            let storage = std::io::Cursor::new(Vec::new());
+            let mut store = RiaKV::open_from_storage(storage, options).expect("open");
+
+            store.insert(b"small", &small).expect("insert");
+            store.insert(b"large", &large).expect("insert");
+            store
+                .insert(b"incompressible", &incompressible)
+                .expect("insert");
+            store
+                .put_from_reader(b"streamed", &mut &large[..], large.len() as u64)
+                .expect("put_from_reader");
+            assert!(store.as_bytes().len() < 2 * large.len());
+
+            let mut reopened = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+            assert_eq!(reopened.header().compression, compression);
+            reopened.load().expect("load");
+
+            assert_eq!(reopened.get(b"small").expect("get").unwrap(), small);
+            assert_eq!(reopened.get(b"large").expect("get").unwrap(), large);
+            assert_eq!(reopened.get(b"streamed").expect("get").unwrap(), large);
+            assert_eq!(
+                reopened.get(b"incompressible").expect("get").unwrap(),
+                incompressible
+            );
+
+            let mut reader = reopened.get_reader(b"large").expect("get_reader").unwrap();
+            assert!(reader.stored_len() < large.len() as u64);
+
+            let mut value = Vec::new();
+            reader.read_to_end(&mut value).expect("read");
+            assert_eq!(value, large);
+        }
+    }
+
+    fn encrypted_options(key: EncryptionKey) -> StoreOptions {
+        StoreOptions {
+            compression: Compression::Zstd,
+            encryption: Some(key),
+            ..StoreOptions::default()
+        }
+    }
+
+    #[test]
+    fn encrypted_storage() {
+        use std::io::{Cursor, Read};
+
+        let key = EncryptionKey::generate(7);
+        let large: Vec<u8> = b"secret value ".repeat(1024);
+
+        let mut store = RiaKV::open_from_storage(Cursor::new(Vec::new()), encrypted_options(key))
+            .expect("open");
+        assert_eq!(store.header().encryption_key_id, Some(7));
+
+        store
+            .insert(b"secret key", b"secret value")
+            .expect("insert");
+        store.insert(b"large", &large).expect("insert");
+        store
+            .put_from_reader(b"streamed", &mut &large[..], large.len() as u64)
+            .expect("put_from_reader");
+        store.delete(b"large").expect("delete");
+
+        let bytes = store.as_bytes().to_vec();
+        assert!(!bytes.windows(10).any(|window| window == b"secret key"));
+        assert!(!bytes.windows(12).any(|window| window == b"secret value"));
+
+        let mut reopened =
+            RiaKV::open_from_storage(Cursor::new(bytes.clone()), encrypted_options(key))
+                .expect("open");
+        reopened.load().expect("load");
+
+        assert_eq!(
+            reopened.get(b"secret key").expect("get").unwrap(),
+            b"secret value"
+        );
+        assert_eq!(reopened.get(b"large").expect("get"), None);
+        assert_eq!(reopened.get(b"streamed").expect("get").unwrap(), large);
+
+        let mut value = Vec::new();
+        let mut reader = reopened
+            .get_reader(b"streamed")
+            .expect("get_reader")
+            .unwrap();
+        reader.read_to_end(&mut value).expect("read");
+        assert_eq!(value, large);
+
+        let wrong_key = EncryptionKey::generate(8);
+        let err =
+            RiaKV::open_from_storage(Cursor::new(bytes.clone()), encrypted_options(wrong_key))
+                .expect_err("wrong key id");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let err = RiaKV::open_from_bytes(bytes.clone()).expect_err("missing key");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let forged_key = EncryptionKey::new(7, [0; 32]);
+        let mut forged =
+            RiaKV::open_from_storage(Cursor::new(bytes), encrypted_options(forged_key))
+                .expect("open");
+        let err = forged.load().expect_err("forged key");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn encrypted_index_and_key_rotation() {
+        use std::io::Cursor;
+
+        let old_key = EncryptionKey::generate(1);
+        let new_key = EncryptionKey::generate(2);
+
+        let mut store =
+            RiaKV::open_from_storage(Cursor::new(Vec::new()), encrypted_options(old_key))
+                .expect("open");
+        store.insert(b"key", b"value").expect("insert");
+        store.insert(b"deleted", b"value").expect("insert");
+        store.delete(b"deleted").expect("delete");
+
+        let mut index = Vec::new();
+        store.persist_index(&mut index).expect("persist_index");
+        assert!(!index.windows(3).any(|window| window == b"key"));
+
+        let mut reopened = RiaKV::open_from_storage(
+            Cursor::new(store.as_bytes().to_vec()),
+            encrypted_options(old_key),
+        )
+        .expect("open");
+        reopened.load_index(&mut &index[..]).expect("load_index");
+        assert_eq!(reopened.index, store.index);
+
+        let mut rotated = store
+            .copy_into(Cursor::new(Vec::new()), encrypted_options(new_key))
+            .expect("copy_into");
+        assert_eq!(rotated.header().encryption_key_id, Some(2));
+        assert_eq!(rotated.get(b"key").expect("get").unwrap(), b"value");
+        assert_eq!(rotated.get(b"deleted").expect("get"), None);
+
+        assert!(rotated.load_index(&mut &index[..]).is_err());
+    }
+
+    #[test]
+    fn verify_and_repair() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+
+        store.insert(b"first", b"value").expect("insert");
+        let corrupt_position = store.seek_to_end().expect("seek");
+        store.insert(b"second", b"value").expect("insert");
+        store.insert(b"third", b"value").expect("insert");
+        store.delete(b"first").expect("delete");
+        let torn_position = store.seek_to_end().expect("seek");
+        store.insert(b"torn", b"value").expect("insert");
+
+        let report = store.verify().expect("verify");
+        assert!(report.is_clean());
+        assert_eq!((report.records, report.tombstones), (5, 1));
+        assert!(report.check_index(&store.index).is_empty());
+
+        let mut bytes = store.as_bytes().to_vec();
+        bytes.truncate(bytes.len() - 3);
+        let key_position = corrupt_position + store.header().record_header_len();
+        bytes[key_position as usize + 1] ^= 0xff;
+
+        let mut damaged = RiaKV::open_from_bytes(bytes).expect("open");
+        let err = damaged.load().expect_err("load");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let report = damaged.verify().expect("verify");
+        assert_eq!(report.damaged.len(), 2);
+        assert_eq!(report.damaged[0].start, corrupt_position);
+        assert!(matches!(report.damaged[0].damage, Damage::Corrupt(_)));
+        assert_eq!(report.damaged[1].start, torn_position);
+        assert_eq!(report.damaged[1].end, report.end);
+        assert_eq!(report.damaged[1].damage, Damage::Truncated);
+        assert_eq!(report.records, 3);
+
+        assert_eq!(
+            report.check_index(&store.index),
+            vec![
+                IndexMismatch::Dangling {
+                    key: b"second".to_vec(),
+                    indexed: corrupt_position
+                },
+                IndexMismatch::Dangling {
+                    key: b"torn".to_vec(),
+                    indexed: torn_position
+                }
+            ]
+        );
+
+        let mut repaired = Vec::new();
+        damaged.repair_into(&mut repaired).expect("repair_into");
+
+        let mut repaired = RiaKV::open_from_bytes(repaired).expect("open");
+        assert!(repaired.verify().expect("verify").is_clean());
+        repaired.load().expect("load");
+
+        assert_eq!(repaired.get(b"first").expect("get"), None);
+        assert_eq!(repaired.get(b"second").expect("get"), None);
+        assert_eq!(repaired.get(b"third").expect("get").unwrap(), b"value");
+        assert_eq!(repaired.get(b"torn").expect("get"), None);
+    }
+
+    #[test]
+    fn load_tolerant_with_sync_markers() {
+        let options = StoreOptions {
+            sync_marker_interval: Some(64),
+            ..StoreOptions::default()
+        };
+
+        let mut store =
+            RiaKV::open_from_storage(std::io::Cursor::new(Vec::new()), options).expect("open");
+
+        let mut positions = Vec::new();
+        for i in 0..16u8 {
+            positions.push(store.seek_to_end().expect("seek"));
+            store.insert(&[b'k', i], &[i; 20]).expect("insert");
+        }
+
+        let bytes = store.as_bytes().to_vec();
+        assert!(bytes
+            .windows(crate::SYNC_MARKER_KEY.len())
+            .any(|window| window == crate::SYNC_MARKER_KEY));
+
+        let mut reopened = RiaKV::open_from_bytes(bytes.clone()).expect("open");
+        reopened.load().expect("load");
+        assert_eq!(reopened.index, store.index);
+        // a sync marker may precede the record written at a position
+        let found = reopened.find(b"k\x03").expect("find").unwrap().0;
+        assert!(found >= positions[3] && found < positions[4]);
+
+        let mut damaged = bytes;
+        for byte in &mut damaged[positions[4] as usize..positions[7] as usize] {
+            *byte = 0xff;
+        }
+
+        let mut damaged = RiaKV::open_from_bytes(damaged).expect("open");
+        assert!(damaged.load().is_err());
+
+        let skipped = damaged.load_tolerant().expect("load_tolerant");
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].start, positions[4]);
+        assert!(skipped[0].end >= positions[7] && skipped[0].end <= positions[8]);
+
+        for i in 0..16u8 {
+            let value = damaged.get(&[b'k', i]).expect("get");
+            if (4..7).contains(&i) {
+                assert_eq!(value, None);
+            } else {
+                assert_eq!(value.unwrap(), vec![i; 20]);
+            }
+        }
+    }
+
+    #[test]
+    fn persist_index_to_path() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let index_path = dir.path().join("store.idx");
+
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        for i in 0..64u8 {
+            store.insert(&[i; 32], b"value").expect("insert");
+        }
+        store.persist_index_to_path(&index_path).expect("persist");
+        let long_len = std::fs::metadata(&index_path).expect("metadata").len();
+
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        store.insert(b"key", b"value").expect("insert");
+        store.persist_index_to_path(&index_path).expect("persist");
+
+        assert!(std::fs::metadata(&index_path).expect("metadata").len() < long_len);
+        assert!(!dir.path().join("store.idx.tmp").exists());
+
+        let mut reopened = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+        reopened.load_index_from_path(&index_path).expect("load");
+        assert_eq!(reopened.index, store.index);
+
+        let err = reopened
+            .load_index_from_path(&dir.path().join("missing.idx"))
+            .expect_err("missing");
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn index_log_checkpoint_and_deltas() {
+        use std::io::Cursor;
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let index_path = dir.path().join("store.idx");
+
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        for i in 0..16u8 {
+            store.insert(&[i], b"value").expect("insert");
+        }
+        store.persist_index_log(&index_path).expect("checkpoint");
+        let checkpoint_len = std::fs::metadata(&index_path).expect("metadata").len();
+
+        store.update(&[0], b"updated").expect("update");
+        store.delete(&[1]).expect("delete");
+        store.persist_index_log(&index_path).expect("delta");
+        let delta_len = std::fs::metadata(&index_path).expect("metadata").len() - checkpoint_len;
+        assert!(delta_len < checkpoint_len / 2);
+
+        let mut reopened = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+        reopened
+            .load_index_log(&index_path)
+            .expect("load_index_log");
+        assert_eq!(reopened.index, store.index);
+
+        // records appended after the index log was persisted are loaded from the storage
+        store.insert(b"late", b"value").expect("insert");
+        let mut reopened = RiaKV::open_from_storage(
+            Cursor::new(store.as_bytes().to_vec()),
+            StoreOptions::default(),
+        )
+        .expect("open");
+        reopened
+            .load_index_log(&index_path)
+            .expect("load_index_log");
+        assert_eq!(reopened.get(b"late").expect("get").unwrap(), b"value");
+        assert_eq!(reopened.get(&[0]).expect("get").unwrap(), b"updated");
+        assert_eq!(reopened.get(&[1]).expect("get"), None);
+
+        // a torn delta is ignored, and truncated by the next append
+        let mut bytes = std::fs::read(&index_path).expect("read");
+        bytes.extend_from_slice(&[1, 0xff, 0, 0]);
+        std::fs::write(&index_path, bytes).expect("write");
+
+        let mut reopened = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+        reopened
+            .load_index_log(&index_path)
+            .expect("load_index_log");
+        reopened.insert(b"after", b"value").expect("insert");
+        reopened.persist_index_log(&index_path).expect("delta");
+
+        let mut reloaded = RiaKV::open_from_bytes(reopened.as_bytes().to_vec()).expect("open");
+        reloaded
+            .load_index_log(&index_path)
+            .expect("load_index_log");
+        assert_eq!(reloaded.index, reopened.index);
+
+        let mut other = RiaKV::open_from_in_memory_buffer(5000);
+        assert!(other.load_index_log(&index_path).is_err());
+
+        // index logs left over from before a compaction are refused, even once the compacted
+        // storage has grown past the length they were written for
+        let mut compacted = reopened
+            .compact_into(Cursor::new(Vec::new()))
+            .expect("compact");
+        let end = reopened.seek_to_end().expect("seek");
+        while compacted.seek_to_end().expect("seek") < end {
+            compacted.insert(b"grown", b"value").expect("insert");
+        }
+
+        let err = compacted.load_index_log(&index_path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn export_import_json_and_csv() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        store.insert(b"b", b"2").expect("insert");
+        store.insert(b"a", b"1").expect("insert");
+        store.insert(b"deleted", b"value").expect("insert");
+        store.delete(b"deleted").expect("delete");
+
+        let mut json = Vec::new();
+        assert_eq!(
+            store
+                .export_json(&mut json, Encoding::Utf8)
+                .expect("export"),
+            2
+        );
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"encoding\":\"utf8\",\"pairs\":[\n\
+            {\"key\":\"a\",\"value\":\"1\"},\n\
+            {\"key\":\"b\",\"value\":\"2\"}\n]}\n"
+        );
+
+        let mut csv = Vec::new();
+        store.export_csv(&mut csv, Encoding::Hex).expect("export");
+        assert_eq!(String::from_utf8(csv).unwrap(), "key,value\n61,31\n62,32\n");
+
+        store.insert(b"binary", &[0xff, 0x00]).expect("insert");
+        let err = store
+            .export_json(Vec::new(), Encoding::Utf8)
+            .expect_err("invalid utf8");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        for encoding in [Encoding::Hex, Encoding::Base64] {
+            let mut json = Vec::new();
+            store.export_json(&mut json, encoding).expect("export");
+            let mut csv = Vec::new();
+            store.export_csv(&mut csv, encoding).expect("export");
+
+            let mut from_json = RiaKV::open_from_in_memory_buffer(5000);
+            assert_eq!(from_json.import_json(&json[..]).expect("import"), 3);
+            let mut from_csv = RiaKV::open_from_in_memory_buffer(5000);
+            assert_eq!(from_csv.import_csv(&csv[..], encoding).expect("import"), 3);
+
+            for imported in [&mut from_json, &mut from_csv] {
+                assert_eq!(imported.get(b"a").expect("get").unwrap(), b"1");
+                assert_eq!(imported.get(b"binary").expect("get").unwrap(), [0xff, 0x00]);
+                assert_eq!(imported.get(b"deleted").expect("get"), None);
+            }
+        }
+
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        let err = store
+            .import_csv(&b"key,value\n61,zz\n"[..], Encoding::Hex)
+            .expect_err("invalid hex");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(store.index.is_empty());
+    }
+
+    #[test]
+    fn bulk_load() {
+        use std::io::Cursor;
+
+        let pairs: Vec<(&[u8], &[u8])> = vec![
+            (b"b", b"1"),
+            (b"a", b"1"),
+            (b"b", b"2"),
+            (b"c", b"1"),
+            (b"c", b""),
+            (b"a", b"2"),
+        ];
+
+        let mut inserted = RiaKV::open_from_in_memory_buffer(5000);
+        for (key, value) in &pairs {
+            inserted.insert(key, value).expect("insert");
+        }
+
+        let options = StoreOptions {
+            sync_marker_interval: Some(32),
+            ..StoreOptions::default()
+        };
+
+        for dedup in [Dedup::None, Dedup::All] {
+            let bulk = BulkLoadOptions {
+                dedup,
+                buffer_len: 16,
+            };
+            let mut store = RiaKV::bulk_load(Cursor::new(Vec::new()), options, bulk, pairs.clone())
+                .expect("bulk_load");
+
+            assert_eq!(store.get(b"a").expect("get").unwrap(), b"2");
+            assert_eq!(store.get(b"b").expect("get").unwrap(), b"2");
+            assert_eq!(store.get(b"c").expect("get"), None);
+
+            let report = store.verify().expect("verify");
+            assert!(report.check_index(&store.index).is_empty());
+
+            let expected_records = if dedup == Dedup::None { 6 } else { 2 };
+            assert_eq!(report.records, expected_records);
+        }
+
+        // adjacent duplicates, as in sorted input, are dropped without buffering the input
+        let mut sorted = pairs.clone();
+        sorted.sort_by_key(|(key, _)| *key);
+        let bulk = BulkLoadOptions {
+            dedup: Dedup::Adjacent,
+            ..BulkLoadOptions::default()
+        };
+        let mut store =
+            RiaKV::bulk_load(Cursor::new(Vec::new()), options, bulk, sorted).expect("bulk_load");
+        assert_eq!(store.get(b"a").expect("get").unwrap(), b"2");
+        assert_eq!(store.verify().expect("verify").records, 2);
+
+        // only fresh storage can be bulk loaded into
+        let err = RiaKV::bulk_load(
+            Cursor::new(inserted.as_bytes().to_vec()),
+            StoreOptions::default(),
+            BulkLoadOptions::default(),
+            pairs.clone(),
+        )
+        .expect_err("not empty");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let storage_path = dir.path().join("store.db");
+        let index_path = dir.path().join("store.idx");
+
+        RiaKV::bulk_load_file_at_path(
+            &storage_path,
+            &index_path,
+            StoreOptions::default(),
+            BulkLoadOptions::default(),
+            pairs.clone(),
+        )
+        .expect("bulk_load_file_at_path");
+
+        let mut reopened = RiaKV::open_from_file_at_path(&storage_path).expect("open");
+        reopened
+            .load_index_log(&index_path)
+            .expect("load_index_log");
+        inserted.load().expect("load");
+        assert_eq!(reopened.index, inserted.index);
+    }
+
+    #[test]
+    fn typed_store() {
+        use serde_derive::{Deserialize, Serialize};
+
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        struct User {
+            name: String,
+            age: u8,
+        }
+
+        let alice = User {
+            name: "alice".to_string(),
+            age: 30,
+        };
+        let bob = User {
+            name: "bob".to_string(),
+            age: 40,
+        };
 
-        f.write_u32::<LittleEndian>(checksum)?;
-        f.write_u32::<LittleEndian>(key_len as u32)?;
-        f.write_u32::<LittleEndian>(val_len as u32)?;
-        f.write_all(&tmp)?;
+        for codec in [Codec::Bincode, Codec::Json] {
+            let store = RiaKV::open_from_in_memory_buffer(5000);
+            let mut typed: TypedRiaKV<(u32, String), User, _> = TypedRiaKV::new(store, codec);
 
-        Ok(current_position)
+            typed.insert(&(1, "a".to_string()), &alice).expect("insert");
+            typed.insert(&(2, "b".to_string()), &bob).expect("insert");
+            typed.insert(&(3, "c".to_string()), &bob).expect("insert");
+            typed.delete(&(3, "c".to_string())).expect("delete");
+
+            assert_eq!(
+                typed.get(&(1, "a".to_string())).expect("get"),
+                Some(alice.clone())
+            );
+            assert_eq!(typed.get(&(3, "c".to_string())).expect("get"), None);
+
+            let pairs: Vec<((u32, String), User)> =
+                typed.iter().collect::<std::io::Result<_>>().expect("iter");
+            assert_eq!(pairs.len(), 2);
+            assert!(pairs.contains(&((2, "b".to_string()), bob.clone())));
+
+            // JSON values are readable with the untyped API, and refused when mistyped
+            let mut store = typed.into_inner();
+            if codec == Codec::Json {
+                assert_eq!(
+                    store.get(br#"[1,"a"]"#).expect("get").unwrap(),
+                    br#"{"name":"alice","age":30}"#
+                );
+
+                let mut mistyped: TypedRiaKV<(u32, String), u64, _> = TypedRiaKV::new(store, codec);
+                let err = mistyped.get(&(1, "a".to_string())).expect_err("mistyped");
+                assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+                store = mistyped.into_inner();
+            }
+
+            let mut unit: TypedRiaKV<u32, (), _> = TypedRiaKV::new(store, Codec::Bincode);
+            let err = unit.insert(&1, &()).expect_err("empty value");
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
     }
 
-    /// Inserts the given key value pair into the underlying storage and updates the index.
-    ///
-    /// # Example
-    /// ```
-    /// use libriakv::RiaKV;
-    ///
-    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
-    /// store.insert(b"key", b"value").expect("insert");
-    /// ```
-    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
-        let position = self.insert_but_ignore_index(key, value)?;
+    #[test]
+    fn keyspaces_and_compaction() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        store.insert(b"key", b"default").expect("insert");
 
-        self.index.insert(key.to_vec(), position);
-        Ok(())
+        let mut users = store.keyspace("users").expect("keyspace");
+        users.insert(b"key", b"user").expect("insert");
+        users.insert(b"gone", b"user").expect("insert");
+        users.delete(b"gone").expect("delete");
+        assert_eq!(users.keys(), vec![b"key".to_vec()]);
+
+        let mut orders = store.keyspace("orders").expect("keyspace");
+        orders.insert(b"key", b"order").expect("insert");
+        assert_ne!(orders.id(), store.keyspace("users").expect("keyspace").id());
+
+        assert_eq!(store.get(b"key").expect("get").unwrap(), b"default");
+        assert_eq!(store.index.len(), 1);
+        assert_eq!(
+            store.keyspace_names().expect("keyspace_names"),
+            vec!["orders".to_string(), "users".to_string()]
+        );
+
+        // keyspaces are loaded on first use when only the default index is loaded
+        let mut reopened = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+        let mut index = Vec::new();
+        store.persist_index(&mut index).expect("persist_index");
+        reopened.load_index(&mut &index[..]).expect("load_index");
+        let mut users = reopened.keyspace("users").expect("keyspace");
+        assert_eq!(users.get(b"key").expect("get").unwrap(), b"user");
+        assert_eq!(users.get(b"gone").expect("get"), None);
+
+        let report = store.verify().expect("verify");
+        assert!(report.check_index(&store.index).is_empty());
+
+        // dropping a keyspace is a single record, its records are removed by compaction
+        let len = store.as_bytes().len();
+        assert!(store.drop_keyspace("orders").expect("drop_keyspace"));
+        assert!(!store.drop_keyspace("orders").expect("drop_keyspace"));
+        assert!(store.as_bytes().len() - len < 64);
+
+        let mut reopened = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+        reopened.load().expect("load");
+        assert_eq!(
+            reopened.keyspace_names().expect("keyspace_names"),
+            vec!["users".to_string()]
+        );
+
+        let mut compacted = reopened
+            .compact_into(std::io::Cursor::new(Vec::new()))
+            .expect("compact_into");
+        assert!(compacted.as_bytes().len() < store.as_bytes().len());
+        assert_eq!(compacted.verify().expect("verify").records, 3);
+
+        let mut compacted = RiaKV::open_from_bytes(compacted.as_bytes().to_vec()).expect("open");
+        compacted.load().expect("load");
+        assert_eq!(compacted.get(b"key").expect("get").unwrap(), b"default");
+        let mut users = compacted.keyspace("users").expect("keyspace");
+        assert_eq!(users.get(b"key").expect("get").unwrap(), b"user");
+        assert!(compacted.keyspace("orders").expect("keyspace").is_empty());
+
+        // keyspaces are kept when copying into encrypted storage
+        let options = encrypted_options(EncryptionKey::generate(1));
+        let mut encrypted = compacted
+            .copy_into(std::io::Cursor::new(Vec::new()), options)
+            .expect("copy_into");
+        let mut users = encrypted.keyspace("users").expect("keyspace");
+        assert_eq!(users.get(b"key").expect("get").unwrap(), b"user");
     }
 
-    /// Updates the value for the given key by inserting a duplicate entry into the storage and
-    /// updating the index.
-    #[inline]
-    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
-        self.insert(key, value)
+    #[test]
+    fn stats() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        store.insert(b"a", b"1").expect("insert");
+        store.insert(b"bb", b"value").expect("insert");
+        store.update(b"bb", b"longer value").expect("update");
+        store.insert(b"gone", b"value").expect("insert");
+        store.delete(b"gone").expect("delete");
+
+        let mut users = store.keyspace("users").expect("keyspace");
+        users.insert(b"user", b"value").expect("insert");
+        let mut orders = store.keyspace("orders").expect("keyspace");
+        orders.insert(b"order", b"value").expect("insert");
+        store.drop_keyspace("orders").expect("drop_keyspace");
+
+        let stats = store.stats().expect("stats");
+        assert_eq!(stats.file_size, store.as_bytes().len() as u64);
+        assert_eq!(stats.records, 10);
+        assert_eq!(stats.tombstones, 2);
+        assert_eq!(stats.live_keys, 3);
+        assert_eq!(stats.damaged_bytes, 0);
+        assert_eq!(
+            stats.key_sizes.buckets().collect::<Vec<_>>(),
+            vec![(1, 1, 1), (2, 3, 1), (4, 7, 1)]
+        );
+        assert_eq!(
+            stats.value_sizes.buckets().collect::<Vec<_>>(),
+            vec![(1, 1, 1), (4, 7, 1), (8, 15, 1)]
+        );
+        assert_eq!(stats.value_sizes.count(), 3);
+
+        // compaction reclaims exactly the estimated bytes
+        let mut compacted = store
+            .compact_into(std::io::Cursor::new(Vec::new()))
+            .expect("compact_into");
+        assert_eq!(
+            stats.file_size - stats.reclaimable_bytes,
+            compacted.as_bytes().len() as u64
+        );
+
+        let compacted_stats = compacted.stats().expect("stats");
+        assert_eq!(compacted_stats.live_keys, 3);
+        assert_eq!(compacted_stats.live_bytes, stats.live_bytes);
+        assert_eq!(compacted_stats.stale_bytes, 0);
+        assert_eq!(compacted_stats.reclaimable_bytes, 0);
     }
 
-    /// Deletes the value for the given key by inserting a _tombstone_ entry:
-    ///
-    /// # Equivalent implementation
-    /// ```
-    /// use libriakv::RiaKV;
-    ///
-    /// let mut store = RiaKV::open_from_in_memory_buffer(5000);
-    /// store.insert(b"key", b"").expect("delete");
-    /// ```
-    #[inline]
-    pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
-        self.insert(key, b"")
+    #[test]
+    fn write_batch() {
+        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+        store.insert(b"old", b"value").expect("insert");
+        store.insert(b"kept", b"value").expect("insert");
+
+        let mut batch = WriteBatch::new();
+        batch.insert(b"new", b"value_1");
+        batch.insert(b"other", b"value_2");
+        batch.delete(b"old");
+        assert_eq!(batch.len(), 3);
+
+        let before = store.as_bytes().len();
+        store.write_batch(&batch).expect("write_batch");
+        let after = store.as_bytes().len();
+
+        assert_eq!(store.get(b"new").expect("get").unwrap(), b"value_1");
+        assert_eq!(store.get(b"old").expect("get"), None);
+
+        let mut reopened = RiaKV::open_from_bytes(store.as_bytes().to_vec()).expect("open");
+        reopened.load().expect("load");
+        assert_eq!(reopened.index.len(), 3);
+        assert_eq!(
+            reopened.index[&b"other".to_vec()],
+            store.index[&b"other".to_vec()]
+        );
+        assert_eq!(reopened.get(b"new").expect("get").unwrap(), b"value_1");
+        assert!(reopened.verify().expect("verify").is_clean());
+
+        // a batch torn anywhere, even at a record boundary, is skipped as a whole
+        for len in before + 1..after {
+            let mut torn = RiaKV::open_from_bytes(store.as_bytes()[..len].to_vec()).expect("open");
+            torn.load().expect("load");
+            assert_eq!(torn.get(b"old").expect("get").unwrap(), b"value");
+            assert_eq!(torn.get(b"new").expect("get"), None);
+            assert_eq!(torn.index.len(), 2);
+
+            let report = torn.verify().expect("verify");
+            assert_eq!(report.records, 2);
+            assert_eq!(report.damaged.len(), 1);
+            assert_eq!(report.damaged[0].start, before as u64);
+            assert_eq!(report.damaged[0].damage, Damage::Truncated);
+
+            torn.load_tolerant().expect("load_tolerant");
+            assert_eq!(torn.index.len(), 2);
+        }
     }
-}
 
-impl<F> RiaKV<F>
-where
-    F: Read + Write + Seek,
-{
-    /// Loads the index from the given object implementing the `Read` trait.
-    /// This done by deserializing the contents of the file using
-    /// `bincode::deserialize_from(reader)` into a `HashMap<ByteString, u64>`.
-    ///
-    /// The `Read` object is wrapped into a `io::BufReader` instance before
-    /// reading the contents.
-    pub fn load_index<R: Read>(
-        &mut self,
-        index_file: &mut R,
-    ) -> result::Result<(), bincode::Error> {
-        let reader = BufReader::new(index_file);
+    /// In-memory storage failing a single write once the given number of bytes is written.
+    struct FailingStorage {
+        inner: std::io::Cursor<Vec<u8>>,
+        fail_after: Option<usize>,
+    }
 
-        match bincode::deserialize_from(reader) {
-            Ok(index) => {
-                self.index = index;
-                Ok(())
+    impl std::io::Read for FailingStorage {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl std::io::Write for FailingStorage {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            match self.fail_after {
+                Some(0) => {
+                    self.fail_after = None;
+                    Err(std::io::Error::other("storage failed"))
+                }
+                Some(n) => {
+                    let n = self.inner.write(&buf[..n.min(buf.len())])?;
+                    self.fail_after = Some(self.fail_after.unwrap() - n);
+                    Ok(n)
+                }
+                None => self.inner.write(buf),
             }
-            Err(value) => Err(value),
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
     }
 
-    /// Writes the index into the given object implementing the `Write` trait,
-    /// using `bincode::serialize_into(writer, &self.index)`
-    ///
-    /// The `Write` object is wrapped into a `io::BufWriter` instance before
-    /// writing the contents.
-    pub fn persist_index<W: Write>(
-        &self,
-        index_file: &mut W,
-    ) -> result::Result<(), bincode::Error> {
-        let writer = BufWriter::new(index_file);
+    impl std::io::Seek for FailingStorage {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
 
-        bincode::serialize_into(writer, &self.index)
+    #[test]
+    fn write_batch_failing() {
+        let mut batch = WriteBatch::new();
+        batch.insert(b"new", b"value_1");
+        batch.insert(b"other", &[7; 100]);
+        batch.delete(b"old");
+
+        for fail_after in [0, 5, 20, 60, 150] {
+            let storage = FailingStorage {
+                inner: std::io::Cursor::new(Vec::new()),
+                fail_after: None,
+            };
+            let mut store =
+                RiaKV::open_from_storage(storage, StoreOptions::default()).expect("open");
+            store.insert(b"old", b"value").expect("insert");
+
+            store.f.fail_after = Some(fail_after);
+            store.write_batch(&batch).unwrap_err();
+            assert_eq!(store.get(b"old").expect("get").unwrap(), b"value");
+            assert_eq!(store.get(b"new").expect("get"), None);
+
+            // records appended after the failed batch are not corrupted by it
+            store.insert(b"after", b"value").expect("insert");
+
+            let bytes = store.f.inner.into_inner();
+            let mut reopened = RiaKV::open_from_bytes(bytes).expect("open");
+            reopened.load().expect("load");
+            assert_eq!(reopened.get(b"old").expect("get").unwrap(), b"value");
+            assert_eq!(reopened.get(b"new").expect("get"), None);
+            assert_eq!(reopened.get(b"after").expect("get").unwrap(), b"value");
+            assert!(reopened.verify().expect("verify").is_clean());
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::RiaKV;
+    #[test]
+    fn dump_restore() {
+        use std::io::Cursor;
+
+        let options = StoreOptions {
+            compression: Compression::Lz4,
+            ..StoreOptions::default()
+        };
+
+        let mut store = RiaKV::open_from_storage(Cursor::new(Vec::new()), options).expect("open");
+        store.insert(b"key_1", b"value_1").expect("insert");
+        store.insert(b"key_2", b"old").expect("insert");
+        store.update(b"key_2", b"new").expect("update");
+        store.delete(b"key_1").expect("delete");
+
+        let mut users = store.keyspace("users").expect("keyspace");
+        users.insert(b"key_1", b"user").expect("insert");
+        store.keyspace("sessions").expect("keyspace");
+        store
+            .keyspace("dropped")
+            .expect("keyspace")
+            .insert(b"key", b"value")
+            .expect("insert");
+        store.drop_keyspace("dropped").expect("drop_keyspace");
+
+        let mut dump = Vec::new();
+        let summary = store.dump(&mut dump, DumpOptions::default()).expect("dump");
+        assert_eq!(
+            (summary.keyspaces, summary.pairs, summary.tombstones),
+            (2, 2, 0)
+        );
+
+        let mut restored = RiaKV::open_from_in_memory_buffer(5000);
+        assert_eq!(restored.restore(&dump[..]).expect("restore"), summary);
+        assert_eq!(restored.get(b"key_1").expect("get"), None);
+        assert_eq!(restored.get(b"key_2").expect("get").unwrap(), b"new");
+        assert_eq!(
+            restored.keyspace_names().expect("keyspace_names"),
+            vec!["sessions", "users"]
+        );
+        let mut users = restored.keyspace("users").expect("keyspace");
+        assert_eq!(users.get(b"key_1").expect("get").unwrap(), b"user");
+
+        let mut history = Vec::new();
+        let summary = store
+            .dump(&mut history, DumpOptions { history: true })
+            .expect("dump");
+        assert_eq!((summary.pairs, summary.tombstones), (4, 1));
+
+        let mut restored = RiaKV::open_from_in_memory_buffer(5000);
+        restored.restore(&history[..]).expect("restore");
+        assert_eq!(restored.history(b"key_2").expect("history").len(), 2);
+        assert_eq!(restored.get(b"key_1").expect("get"), None);
+
+        // damaged dumps are refused, with nothing restored past the damage
+        let mut corrupt = dump.clone();
+        // last byte of the last value, just before the end entry
+        let last = corrupt.len() - 18;
+        corrupt[last] ^= 0xff;
+        let mut restored = RiaKV::open_from_in_memory_buffer(5000);
+        let err = restored.restore(&corrupt[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        for len in 0..dump.len() {
+            let mut restored = RiaKV::open_from_in_memory_buffer(5000);
+            assert!(restored.restore(&dump[..len]).is_err());
+        }
+    }
 
     #[test]
-    fn insert() {
+    fn restore_file_at_path() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("restored.db");
+
         let mut store = RiaKV::open_from_in_memory_buffer(5000);
         store.insert(b"key", b"value").expect("insert");
+        let mut dump = Vec::new();
+        store.dump(&mut dump, DumpOptions::default()).expect("dump");
+
+        let (mut restored, _) =
+            RiaKV::restore_file_at_path(&path, StoreOptions::default(), &dump[..])
+                .expect("restore_file_at_path");
+        assert_eq!(restored.get(b"key").expect("get").unwrap(), b"value");
+
+        let err =
+            RiaKV::restore_file_at_path(&path, StoreOptions::default(), &dump[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+
+        // a file created at the path while its contents are written is not replaced
+        let path = dir.path().join("created.db");
+        let err = super::create_file_atomically(&path, |f| {
+            std::fs::write(&path, b"other")?;
+            f.write_all(b"contents")
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(&path).expect("read"), b"other");
+        assert!(!dir.path().join("created.db.tmp").exists());
     }
 
     #[test]
-    fn insert_get() {
-        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    fn backup_to() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let storage_path = dir.path().join("store.db");
+        let backup_path = dir.path().join("backup.db");
+        let backup_index_path = dir.path().join("backup.idx");
 
-        let kv_pairs = [
-            (b"12345", b"1qwerrtyui"),
-            (b"asdef", b"1zxcvnnnqq"),
-            (b"1asdf", b"qwertynnii"),
-            (b"1zxcv", b"1lllllpppq"),
-            (b"qwert", b"1zxcqqqqee"),
-            (b"abjkl", b"1aassddwww"),
-            (b"nmkli", b"1qaazzssqq"),
-            (b"asdff", b"1ppppkkkkq"),
-        ];
+        let mut store = RiaKV::open_from_file_at_path(&storage_path).expect("open");
+        store.insert(b"key_1", b"value_1").expect("insert");
+        store.insert(b"key_2", b"old").expect("insert");
+        store.update(b"key_2", b"new").expect("update");
+        store.delete(b"key_1").expect("delete");
 
-        for kv in kv_pairs {
-            store.insert(kv.0, kv.1).expect("insert");
-        }
+        let end = store
+            .backup_to(&backup_path, &backup_index_path)
+            .expect("backup_to");
+        let index = store.index.clone();
 
-        for kv in kv_pairs {
-            let value = store.get(kv.0).expect("get").unwrap();
-            assert_eq!(value, kv.1.to_vec());
-        }
+        // writes after the backup are left out of it
+        store.insert(b"key_3", b"value_3").expect("insert");
+
+        assert_eq!(
+            std::fs::metadata(&backup_path).expect("metadata").len(),
+            end
+        );
+
+        let mut backup = RiaKV::open_from_file_at_path(&backup_path).expect("open");
+        backup
+            .load_index_log(&backup_index_path)
+            .expect("load_index_log");
+        assert_eq!(backup.index, index);
+        assert_eq!(backup.get(b"key_1").expect("get"), None);
+        assert_eq!(backup.get(b"key_2").expect("get").unwrap(), b"new");
+        assert_eq!(backup.get(b"key_3").expect("get"), None);
+        assert!(backup.verify().expect("verify").is_clean());
+
+        // a store without its index loaded, and with a partially written record at the end of
+        // its storage, is backed up up to the end of the last complete record
+        let complete_end = store.seek_to_end().expect("seek");
+        store.insert(b"key_4", b"value_4").expect("insert");
+        let torn_end = store.seek_to_end().expect("seek");
+        drop(store);
+
+        let storage = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&storage_path)
+            .expect("open");
+        storage.set_len(torn_end - 3).expect("set_len");
+
+        let mut store = RiaKV::open_from_file_at_path(&storage_path).expect("open");
+        assert!(store.index.is_empty());
+
+        let end = store
+            .backup_to(&backup_path, &backup_index_path)
+            .expect("backup_to");
+        assert_eq!(end, complete_end);
+        assert_eq!(
+            std::fs::metadata(&backup_path).expect("metadata").len(),
+            complete_end
+        );
+
+        let mut backup = RiaKV::open_from_file_at_path(&backup_path).expect("open");
+        backup
+            .load_index_log(&backup_index_path)
+            .expect("load_index_log");
+        assert_eq!(backup.index, store.index);
+        assert_eq!(backup.get(b"key_3").expect("get").unwrap(), b"value_3");
+        assert_eq!(backup.get(b"key_4").expect("get"), None);
+        assert!(backup.verify().expect("verify").is_clean());
     }
 
     #[test]
-    fn update_get() {
-        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    fn migrate_file_at_path() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("legacy.db");
+        std::fs::write(&path, legacy_storage()).expect("write");
 
-        let kv_pairs = [
-            (b"12345", b"1qwerrtyui_1"),
-            (b"asdef", b"1zxcvnnnqq_1"),
-            (b"1asdf", b"qwertynnii_1"),
-            (b"1zxcv", b"1lllllpppq_1"),
-            (b"qwert", b"1zxcqqqqee_1"),
-            (b"abjkl", b"1aassddwww_1"),
-            (b"nmkli", b"1qaazzssqq_1"),
-            (b"asdff", b"1ppppkkkkq_1"),
-            (b"asdef", b"1zxcvnnnqq_2"),
-            (b"1asdf", b"qwertynnii_2"),
-            (b"1zxcv", b"1lllllpppq_2"),
-            (b"abjkl", b"1aassddwww_2"),
-            (b"asdef", b"1zxcvnnnqq_3"),
-            (b"1zxcv", b"1lllllpppq_3"),
-            (b"asdff", b"1ppppkkkkq_3"),
-            (b"nmkli", b"1qaazzssqq_4"),
-            (b"asdef", b"1zxcvnnnqq_4"),
-            (b"asdef", b"1zxcvnnnqq_4"),
-        ];
+        let dry_run = MigrateOptions {
+            history: false,
+            dry_run: true,
+        };
+        let planned = RiaKV::migrate_file_at_path(&path, dry_run).expect("migrate");
+        assert_eq!(std::fs::read(&path).expect("read"), legacy_storage());
+        assert_eq!(
+            (planned.from_version, planned.to_version),
+            (0, FORMAT_VERSION)
+        );
+        assert_eq!((planned.records, planned.dropped_records), (3, 2));
+        assert_eq!(planned.source_bytes, legacy_storage().len() as u64);
 
-        let final_kv_pairs = [
-            (b"12345", b"1qwerrtyui_1"),
-            (b"asdef", b"1zxcvnnnqq_4"),
-            (b"1asdf", b"qwertynnii_2"),
-            (b"1zxcv", b"1lllllpppq_3"),
-            (b"qwert", b"1zxcqqqqee_1"),
-            (b"abjkl", b"1aassddwww_2"),
-            (b"nmkli", b"1qaazzssqq_4"),
-            (b"asdff", b"1ppppkkkkq_3"),
-        ];
+        let options = MigrateOptions::default();
+        let report = RiaKV::migrate_file_at_path(&path, options).expect("migrate");
+        assert_eq!(report, planned);
+        assert_eq!(
+            std::fs::metadata(&path).expect("metadata").len(),
+            report.migrated_bytes
+        );
 
-        for kv in kv_pairs {
-            store.update(kv.0, kv.1).expect("update");
-        }
+        let mut migrated = RiaKV::open_from_file_at_path(&path).expect("open");
+        migrated.load().expect("load");
+        assert_eq!(migrated.get(b"key").expect("get").unwrap(), b"value");
+        assert!(migrated.history(b"deleted").expect("history").is_empty());
 
-        for kv in final_kv_pairs {
-            let value = store.get(kv.0).expect("get").unwrap();
-            assert_eq!(value, kv.1.to_vec());
+        // records of format version 3 have no timestamps, which migrate to 0
+        let path = dir.path().join("v3.db");
+        let mut header = Header::new(StoreOptions::default());
+        header.version = 3;
+        let mut v3 = Vec::new();
+        header.write_to(&mut v3).expect("write_to");
+        for (key, value) in [(&b"key"[..], &b"value"[..]), (b"deleted", b"")] {
+            let (record, data) =
+                crate::record::encode_record(&header, None, 0, key, value, 1).expect("encode");
+            record.write_to(&mut v3, &header).expect("write_to");
+            for part in &data {
+                v3.extend_from_slice(part);
+            }
         }
+        std::fs::write(&path, &v3).expect("write");
+
+        assert!(RiaKV::open_from_file_at_path(&path).is_err());
+        let report = RiaKV::migrate_file_at_path(&path, options).expect("migrate");
+        assert_eq!(
+            (report.from_version, report.to_version),
+            (3, FORMAT_VERSION)
+        );
+
+        let mut migrated = RiaKV::open_from_file_at_path(&path).expect("open");
+        migrated.load().expect("load");
+        assert_eq!(migrated.get(b"key").expect("get").unwrap(), b"value");
+        assert_eq!(migrated.timestamp(b"key").expect("timestamp"), Some(0));
+        assert_eq!(migrated.timestamp(b"deleted").expect("timestamp"), None);
+
+        // keyspaces survive, dropped ones and overwritten values only with history
+        let path = dir.path().join("store.db");
+        let mut store = RiaKV::open_from_file_at_path(&path).expect("open");
+        store.insert(b"key", b"old").expect("insert");
+        store.update(b"key", b"new").expect("update");
+        let mut users = store.keyspace("users").expect("keyspace");
+        users.insert(b"key", b"user").expect("insert");
+        store.keyspace("dropped").expect("keyspace");
+        store.drop_keyspace("dropped").expect("drop_keyspace");
+        let written_at = store.timestamp(b"key").expect("timestamp");
+        drop(store);
+
+        let history = MigrateOptions {
+            history: true,
+            dry_run: false,
+        };
+        let report = RiaKV::migrate_file_at_path(&path, history).expect("migrate");
+        assert_eq!((report.records, report.dropped_records), (6, 0));
+        assert_eq!(report.source_bytes, report.migrated_bytes);
+
+        let report = RiaKV::migrate_file_at_path(&path, options).expect("migrate");
+        assert_eq!((report.records, report.dropped_records), (6, 3));
+
+        let mut migrated = RiaKV::open_from_file_at_path(&path).expect("open");
+        migrated.load().expect("load");
+        assert_eq!(migrated.history(b"key").expect("history").len(), 1);
+        assert_eq!(migrated.timestamp(b"key").expect("timestamp"), written_at);
+        assert_eq!(
+            migrated.keyspace_names().expect("keyspace_names"),
+            vec!["users"]
+        );
+        let mut users = migrated.keyspace("users").expect("keyspace");
+        assert_eq!(users.get(b"key").expect("get").unwrap(), b"user");
+        assert_eq!(migrated.stats().expect("stats").reclaimable_bytes, 0);
     }
 
     #[test]
-    fn delete_and_find() {
-        let mut store = RiaKV::open_from_in_memory_buffer(5000);
+    fn diff_merge() {
+        use std::io::Cursor;
 
-        let kv_pairs = [
-            (b"12345", b"1qwerrtyui"),
-            (b"asdef", b"1zxcvnnnqq"),
-            (b"1asdf", b"qwertynnii"),
-            (b"1zxcv", b"1lllllpppq"),
-            (b"qwert", b"1zxcqqqqee"),
-            (b"abjkl", b"1aassddwww"),
-            (b"nmkli", b"1qaazzssqq"),
-            (b"asdff", b"1ppppkkkkq"),
-        ];
+        let mut left = RiaKV::open_from_in_memory_buffer(5000);
+        left.insert(b"same", b"value").expect("insert");
+        left.insert(b"changed", b"left").expect("insert");
+        left.insert(b"left", b"value").expect("insert");
+        left.insert(b"deleted", b"value").expect("insert");
+        left.delete(b"deleted").expect("delete");
 
-        for kv in kv_pairs {
-            store.insert(kv.0, kv.1).expect("insert");
-        }
+        let options = StoreOptions {
+            compression: Compression::Zstd,
+            compression_threshold: 0,
+            ..StoreOptions::default()
+        };
+        let mut right = RiaKV::open_from_storage(Cursor::new(Vec::new()), options).expect("open");
+        right.insert(b"same", b"value").expect("insert");
+        right.insert(b"changed", b"right").expect("insert");
+        right.insert(b"right", b"value").expect("insert");
 
-        let kv_pairs_to_delete = [
-            (b"abjkl", b"1aassddwww"),
-            (b"nmkli", b"1qaazzssqq"),
-            (b"asdff", b"1ppppkkkkq"),
-        ];
+        let diff = left.diff(&mut right).expect("diff");
+        assert_eq!(diff.only_left, vec![b"left".to_vec()]);
+        assert_eq!(diff.only_right, vec![b"right".to_vec()]);
+        assert_eq!(diff.changed, vec![b"changed".to_vec()]);
+        assert_eq!(diff.unchanged, 1);
 
-        let kv_pairs_untouched = [
-            (b"12345", b"1qwerrtyui"),
-            (b"asdef", b"1zxcvnnnqq"),
-            (b"1asdf", b"qwertynnii"),
-            (b"1zxcv", b"1lllllpppq"),
-            (b"qwert", b"1zxcqqqqee"),
-        ];
+        let mut kept = RiaKV::open_from_bytes(left.as_bytes().to_vec()).expect("open");
+        kept.load().expect("load");
+        let summary = kept
+            .merge(&mut right, ConflictPolicy::LeftWins)
+            .expect("merge");
+        assert_eq!(
+            (summary.inserted, summary.overwritten, summary.kept),
+            (1, 0, 1)
+        );
+        assert_eq!(kept.get(b"changed").expect("get").unwrap(), b"left");
+        assert_eq!(kept.get(b"right").expect("get").unwrap(), b"value");
+        assert_eq!(
+            kept.timestamp(b"right").expect("timestamp"),
+            right.timestamp(b"right").expect("timestamp")
+        );
 
-        for kv in kv_pairs_to_delete {
-            store.delete(kv.0).expect("delete");
-        }
+        let summary = left
+            .merge(&mut right, ConflictPolicy::RightWins)
+            .expect("merge");
+        assert_eq!(
+            (summary.inserted, summary.overwritten, summary.kept),
+            (1, 1, 0)
+        );
 
-        for kv in kv_pairs_untouched {
-            let value = store.get(kv.0).expect("get").unwrap();
-            assert_eq!(value, kv.1.to_vec());
-        }
+        let diff = left.diff(&mut right).expect("diff");
+        assert_eq!(diff.only_left, vec![b"left".to_vec()]);
+        assert!(diff.only_right.is_empty() && diff.changed.is_empty());
+        assert_eq!(left.get(b"deleted").expect("get"), None);
+    }
 
-        for kv in kv_pairs_to_delete {
-            let value = store.get(kv.0).expect("get");
-            assert_eq!(value, None);
-        }
+    #[test]
+    fn merge_latest_timestamp() {
+        let mut left = RiaKV::open_from_in_memory_buffer(5000);
+        let mut right = RiaKV::open_from_in_memory_buffer(5000);
 
-        for kv in kv_pairs {
-            store.find(kv.0).expect("find").unwrap();
-        }
+        let pause = || std::thread::sleep(std::time::Duration::from_millis(2));
+
+        right.insert(b"left_newer", b"right").expect("insert");
+        pause();
+        left.insert(b"left_newer", b"left").expect("insert");
+        left.insert(b"right_newer", b"left").expect("insert");
+        pause();
+        right.insert(b"right_newer", b"right").expect("insert");
+        right.insert(b"right_only", b"right").expect("insert");
+        left.insert(b"same", b"value").expect("insert");
+        right.insert(b"same", b"value").expect("insert");
+
+        let mut reversed = RiaKV::open_from_bytes(right.as_bytes().to_vec()).expect("open");
+        reversed.load().expect("load");
+
+        let summary = left
+            .merge(&mut right, ConflictPolicy::LatestTimestamp)
+            .expect("merge");
+        assert_eq!(
+            (summary.inserted, summary.overwritten, summary.kept),
+            (1, 1, 1)
+        );
+        assert_eq!(left.get(b"left_newer").expect("get").unwrap(), b"left");
+        assert_eq!(left.get(b"right_newer").expect("get").unwrap(), b"right");
+        assert_eq!(left.get(b"right_only").expect("get").unwrap(), b"right");
+        assert_eq!(
+            left.timestamp(b"right_newer").expect("timestamp"),
+            right.timestamp(b"right_newer").expect("timestamp")
+        );
+
+        // merging the other way around agrees on the latest values
+        let summary = reversed
+            .merge(&mut left, ConflictPolicy::LatestTimestamp)
+            .expect("merge");
+        assert_eq!(
+            (summary.inserted, summary.overwritten, summary.kept),
+            (0, 1, 0)
+        );
+        assert_eq!(reversed.get(b"left_newer").expect("get").unwrap(), b"left");
+        assert!(reversed.diff(&mut left).expect("diff").is_empty());
     }
 }
@@ -0,0 +1,81 @@
+//! Migration of storage files between format versions.
+
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+/// Options for `RiaKV::migrate_file_at_path`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MigrateOptions {
+    /// whether to migrate every record in the order written, including overwritten values and
+    /// tombstones, instead of only the latest record of every live key
+    pub history: bool,
+
+    /// whether to only report what the migration would change, without writing anything
+    pub dry_run: bool,
+}
+
+/// Report of a migration, returned by `RiaKV::migrate_file_at_path`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// format version of the source storage
+    pub from_version: u16,
+
+    /// format version of the migrated storage
+    pub to_version: u16,
+
+    /// number of records in the source storage, excluding sync markers
+    pub records: u64,
+
+    /// number of records left out of the migrated storage: superseded records, tombstones and
+    /// records of dropped keyspaces, unless history is migrated
+    pub dropped_records: u64,
+
+    /// length of the source storage
+    pub source_bytes: u64,
+
+    /// length of the migrated storage
+    pub migrated_bytes: u64,
+}
+
+/// Storage discarding everything written to it, only keeping track of its length, used to
+/// encode a migration without writing it.
+#[derive(Debug, Default)]
+pub(crate) struct NullStorage {
+    position: u64,
+    len: u64,
+}
+
+impl Read for NullStorage {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for NullStorage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.position += buf.len() as u64;
+        self.len = self.len.max(self.position);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for NullStorage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,104 @@
+//! Memory mapped read path for file backed stores.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use memmap2::Mmap;
+
+/// Storage file with a read only memory map over its contents.
+///
+/// Reads and writes through the `Read + Write + Seek` implementations go to the file as
+/// usual. [`MappedFile::mapped_bytes`] exposes the file contents as a slice, remapping the file
+/// whenever a read extends beyond the currently mapped length (i.e. after appends). Writes
+/// within the mapped length, such as a streamed record header being patched in place, unmap
+/// the file first, so that it is remapped by the next read.
+///
+/// The map relies on the file not being truncated while it is mapped, neither by other
+/// processes nor through [`MappedFile::get_ref`]: reading mapped pages past the new end of the
+/// file raises `SIGBUS`, which aborts the process. `RiaKV` never truncates its storage file.
+#[derive(Debug)]
+pub struct MappedFile {
+    file: File,
+    map: Option<Mmap>,
+}
+
+impl MappedFile {
+    /// Creates a new `MappedFile` over the given file. The file is mapped lazily on the
+    /// first call to [`MappedFile::mapped_bytes`].
+    pub fn new(file: File) -> Self {
+        MappedFile { file, map: None }
+    }
+
+    /// Returns the currently mapped length of the file.
+    pub fn mapped_len(&self) -> u64 {
+        self.map.as_ref().map_or(0, |map| map.len() as u64)
+    }
+
+    /// Remaps the file so that the mapping covers its current length.
+    pub fn remap(&mut self) -> io::Result<()> {
+        let len = self.file.metadata()?.len();
+
+        self.map = if len == 0 {
+            None
+        } else {
+            // Safety: slices of the map only live as long as the mutable borrow taken by
+            // `mapped_bytes`, and writes within the mapped length unmap the file first, so
+            // this process never modifies bytes while they are mapped. Modifying or
+            // truncating the file otherwise while it is mapped is not supported, see the
+            // documentation of `MappedFile`.
+            Some(unsafe { Mmap::map(&self.file)? })
+        };
+
+        Ok(())
+    }
+
+    /// Returns the mapped contents of the file, ensuring that at least `end` bytes are
+    /// mapped. Fails with `UnexpectedEof` if the file is shorter than `end` bytes.
+    pub fn mapped_bytes(&mut self, end: u64) -> io::Result<&[u8]> {
+        if end > self.mapped_len() {
+            self.remap()?;
+        }
+
+        if end > self.mapped_len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "read beyond the end of the storage file",
+            ));
+        }
+
+        Ok(self.map.as_deref().unwrap_or(&[]))
+    }
+
+    /// Returns a reference to the underlying file.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+}
+
+impl Read for MappedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for MappedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.map.is_some() && self.file.stream_position()? < self.mapped_len() {
+            self.map = None;
+        }
+
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for MappedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
@@ -0,0 +1,475 @@
+//! Layout of the records stored in a storage file.
+
+use std::borrow::Cow;
+use std::io;
+use std::io::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::checksum::ChecksumHasher;
+use crate::compression::Compression;
+use crate::crypto::{Cipher, NONCE_LEN, TAG_LEN};
+use crate::header::Header;
+use crate::keyspace::DEFAULT_KEYSPACE_ID;
+use crate::{ByteStr, ByteString};
+
+/// Bits of the record flags holding the identifier of the compression applied to the value.
+pub const FLAG_COMPRESSION_MASK: u8 = 0b0000_0011;
+
+/// Record flag set for records with an encrypted key and value.
+pub const FLAG_ENCRYPTED: u8 = 0b0000_0100;
+
+/// Record flag set for sync markers, which hold no key value pair and are skipped by readers.
+pub const FLAG_SYNC_MARKER: u8 = 0b0000_1000;
+
+/// Record flag set for records of a named keyspace, whose key is prefixed with the keyspace
+/// identifier.
+pub const FLAG_KEYSPACE: u8 = 0b0001_0000;
+
+/// Record flag set for batch markers, which hold the length of the batch of records following
+/// them and are skipped by readers.
+pub const FLAG_BATCH_MARKER: u8 = 0b0010_0000;
+
+/// Length of the keyspace identifier prefixed to the key of records of a named keyspace.
+pub const KEYSPACE_ID_LEN: usize = 4;
+
+/// Key of every sync marker record.
+pub const SYNC_MARKER_KEY: &[u8; 8] = b"RIAKVSYN";
+
+/// Key of every batch marker record.
+pub const BATCH_MARKER_KEY: &[u8; 8] = b"RIAKVBAT";
+
+/// All the record flags known to this version.
+const KNOWN_FLAGS: u8 =
+    FLAG_COMPRESSION_MASK | FLAG_ENCRYPTED | FLAG_SYNC_MARKER | FLAG_KEYSPACE | FLAG_BATCH_MARKER;
+
+/// Fields preceding the key and value of every record. Since format version 4, records are
+/// stored with the following layout:
+/// ```text
+/// ┌──────────┬───────┬───────────┬────────────┬──────────────┬─────┬───────┐
+/// │ checksum │ flags │ timestamp │ key length │ value length │ key │ value │
+/// └──────────┴───────┴───────────┴────────────┴──────────────┴─────┴───────┘
+/// ```
+///
+/// The timestamp is the time the record was written at, in microseconds since the unix epoch.
+/// Records copied by compaction, migration or merges keep the timestamp of the record they
+/// were copied from. The value length is the length of the value as stored, i.e. after
+/// compression. The checksum covers the flags, the timestamp, the key and the stored value.
+/// Records of format version 3 have no timestamp field, and records of older format versions
+/// no flags field either; their checksum covers only the fields they have.
+///
+/// For encrypted records, the key followed by the stored value is replaced by a random nonce,
+/// followed by the encrypted key and stored value and an authentication tag. The flags,
+/// timestamp, key length and value length are authenticated along with the encrypted data. The
+/// checksum covers the flags, the timestamp and the encrypted data, so that it can be verified
+/// without the key.
+///
+/// Records of a named keyspace have the `FLAG_KEYSPACE` flag set, and their key starts with the
+/// keyspace identifier as a little endian `u32`, which is included in the key length and, for
+/// encrypted records, encrypted along with the key. Records without it belong to the default
+/// keyspace.
+///
+/// Sync markers are records with the `FLAG_SYNC_MARKER` flag set, `SYNC_MARKER_KEY` as key and
+/// an empty value, which are never compressed or encrypted. They are optionally written at
+/// regular intervals to provide known points for resynchronising after a damaged region.
+/// Records which `RiaKV::put_from_reader` fails to stream and batches which
+/// `RiaKV::write_batch` fails to write are overwritten with sync markers padded with zeroed
+/// keys instead, so that readers skip them.
+///
+/// Batch markers are records with the `FLAG_BATCH_MARKER` flag set, `BATCH_MARKER_KEY` as key
+/// and the length in bytes of the records of the batch following them as a little endian `u64`
+/// value, which are never compressed or encrypted. A batch extending past the end of the
+/// storage was torn while it was written, and none of its records are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader {
+    /// checksum of the record
+    pub checksum: u64,
+
+    /// flags describing how the value is stored
+    pub flags: u8,
+
+    /// time the record was written at in microseconds since the unix epoch, or `0` for records
+    /// of format versions without timestamps
+    pub timestamp: u64,
+
+    /// length of the key in bytes
+    pub key_len: u32,
+
+    /// length of the stored value in bytes
+    pub val_len: u32,
+}
+
+impl RecordHeader {
+    /// Returns a hasher for computing the checksum of a record with the given flags and
+    /// timestamp. The flags and timestamp are already written to the hasher where they are
+    /// covered by the checksum.
+    pub fn hasher(header: &Header, flags: u8, timestamp: u64) -> ChecksumHasher {
+        let mut hasher = header.checksum.hasher();
+        if header.version >= 3 {
+            hasher.write(&[flags]);
+        }
+        if header.version >= 4 {
+            hasher.write(&timestamp.to_le_bytes());
+        }
+
+        hasher
+    }
+
+    /// Reads a record header of the format described by the given file header.
+    pub fn read_from<R: Read>(f: &mut R, header: &Header) -> io::Result<Self> {
+        let checksum = header.checksum.read_checksum(f)?;
+
+        let flags = if header.version >= 3 { f.read_u8()? } else { 0 };
+        if flags & !KNOWN_FLAGS != 0
+            || Compression::from_id(flags & FLAG_COMPRESSION_MASK).is_none()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown record flags {:08b}", flags),
+            ));
+        }
+
+        let timestamp = if header.version >= 4 {
+            f.read_u64::<LittleEndian>()?
+        } else {
+            0
+        };
+
+        let key_len = f.read_u32::<LittleEndian>()?;
+        let val_len = f.read_u32::<LittleEndian>()?;
+
+        Ok(RecordHeader {
+            checksum,
+            flags,
+            timestamp,
+            key_len,
+            val_len,
+        })
+    }
+
+    /// Writes this record header in the format described by the given file header.
+    pub fn write_to<W: Write>(&self, f: &mut W, header: &Header) -> io::Result<()> {
+        header.checksum.write_checksum(f, self.checksum)?;
+        if header.version >= 3 {
+            f.write_u8(self.flags)?;
+        }
+        if header.version >= 4 {
+            f.write_u64::<LittleEndian>(self.timestamp)?;
+        }
+        f.write_u32::<LittleEndian>(self.key_len)?;
+        f.write_u32::<LittleEndian>(self.val_len)
+    }
+
+    /// Verifies that the checksum of the given data following this record header matches the
+    /// saved checksum. A mismatch results in an `InvalidData` error.
+    pub fn verify_checksum(&self, header: &Header, data: &ByteStr) -> io::Result<()> {
+        let mut hasher = RecordHeader::hasher(header, self.flags, self.timestamp);
+        hasher.write(data);
+
+        check_checksum(hasher.finish(), self.checksum)
+    }
+
+    /// Returns the compression applied to the stored value.
+    pub fn compression(&self) -> Compression {
+        Compression::from_id(self.flags & FLAG_COMPRESSION_MASK).unwrap_or_default()
+    }
+
+    /// Returns whether the key and value of this record are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    /// Returns whether this record belongs to a named keyspace.
+    pub fn has_keyspace(&self) -> bool {
+        self.flags & FLAG_KEYSPACE != 0
+    }
+
+    /// Returns whether this record is a sync marker.
+    pub fn is_sync_marker(&self) -> bool {
+        self.flags & FLAG_SYNC_MARKER != 0
+    }
+
+    /// Returns whether this record is a batch marker.
+    pub fn is_batch_marker(&self) -> bool {
+        self.flags & FLAG_BATCH_MARKER != 0
+    }
+
+    /// Returns whether this record is a _tombstone_ marking the deletion of its key.
+    pub fn is_tombstone(&self) -> bool {
+        self.val_len == 0
+    }
+
+    /// Returns the number of bytes following the record header, i.e. occupied by the key and
+    /// the stored value, or the encrypted data.
+    pub fn data_len(&self) -> u64 {
+        let len = self.key_len as u64 + self.val_len as u64;
+
+        if self.is_encrypted() {
+            len + (NONCE_LEN + TAG_LEN) as u64
+        } else {
+            len
+        }
+    }
+
+    /// Returns the data authenticated along with the encrypted data of a record of the format
+    /// described by the given file header.
+    fn associated_data(&self, header: &Header) -> ByteString {
+        let mut aad = vec![self.flags];
+        if header.version >= 4 {
+            aad.extend_from_slice(&self.timestamp.to_le_bytes());
+        }
+        aad.extend_from_slice(&self.key_len.to_le_bytes());
+        aad.extend_from_slice(&self.val_len.to_le_bytes());
+        aad
+    }
+}
+
+/// Returns the current time in microseconds since the unix epoch, as recorded in the records
+/// written now.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_micros() as u64)
+}
+
+/// Checks that the checksum computed for a record matches the saved checksum. A mismatch
+/// results in an `InvalidData` error.
+pub(crate) fn check_checksum(checksum: u64, saved_checksum: u64) -> io::Result<()> {
+    if checksum != saved_checksum {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "data corruption encountered: ({:08x}) != {:08x}",
+                checksum, saved_checksum
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Encodes a record for the given key and value of the given keyspace, written at the given
+/// timestamp: the value is compressed if the header requires it, and the key and value are
+/// encrypted if a cipher is given. Returns the record header, with its checksum computed, and
+/// the data to be written after it.
+pub(crate) fn encode_record<'a>(
+    header: &Header,
+    cipher: Option<&Cipher>,
+    keyspace: u32,
+    key: &'a ByteStr,
+    value: &'a ByteStr,
+    timestamp: u64,
+) -> io::Result<(RecordHeader, Vec<Cow<'a, ByteStr>>)> {
+    let (compression, stored_value) = match header.compression {
+        Compression::None => (Compression::None, Cow::Borrowed(value)),
+        _ if value.is_empty() || value.len() < header.compression_threshold as usize => {
+            (Compression::None, Cow::Borrowed(value))
+        }
+        compression => {
+            let compressed = compression.compress(value)?;
+
+            if compressed.len() < value.len() {
+                (compression, Cow::Owned(compressed))
+            } else {
+                (Compression::None, Cow::Borrowed(value))
+            }
+        }
+    };
+
+    let mut record = RecordHeader {
+        checksum: 0,
+        flags: compression.id(),
+        timestamp: if header.version >= 4 { timestamp } else { 0 },
+        key_len: key.len() as u32,
+        val_len: stored_value.len() as u32,
+    };
+
+    let mut key_parts = Vec::with_capacity(2);
+    if keyspace != DEFAULT_KEYSPACE_ID {
+        record.flags |= FLAG_KEYSPACE;
+        record.key_len += KEYSPACE_ID_LEN as u32;
+        key_parts.push(Cow::Owned(keyspace.to_le_bytes().to_vec()));
+    }
+    key_parts.push(Cow::Borrowed(key));
+
+    let data = match cipher {
+        None => {
+            key_parts.push(stored_value);
+            key_parts
+        }
+        Some(cipher) => {
+            record.flags |= FLAG_ENCRYPTED;
+
+            let mut plaintext =
+                ByteString::with_capacity(record.key_len as usize + stored_value.len());
+            for part in &key_parts {
+                plaintext.extend_from_slice(part);
+            }
+            plaintext.extend_from_slice(&stored_value);
+
+            vec![Cow::Owned(
+                cipher.seal(&plaintext, &record.associated_data(header))?,
+            )]
+        }
+    };
+
+    let mut hasher = RecordHeader::hasher(header, record.flags, record.timestamp);
+    for part in &data {
+        hasher.write(part);
+    }
+    record.checksum = hasher.finish();
+
+    Ok((record, data))
+}
+
+/// Returns a sync marker record header, along with the data to be written after it.
+pub(crate) fn sync_marker(header: &Header) -> (RecordHeader, &'static ByteStr) {
+    let mut hasher = RecordHeader::hasher(header, FLAG_SYNC_MARKER, 0);
+    hasher.write(SYNC_MARKER_KEY);
+
+    let record = RecordHeader {
+        checksum: hasher.finish(),
+        flags: FLAG_SYNC_MARKER,
+        timestamp: 0,
+        key_len: SYNC_MARKER_KEY.len() as u32,
+        val_len: 0,
+    };
+
+    (record, SYNC_MARKER_KEY)
+}
+
+/// Returns a sync marker record header for a zeroed key of `len` bytes, used as padding over a
+/// record or batch which failed to be written.
+pub(crate) fn padding(header: &Header, len: u32) -> RecordHeader {
+    let zeros = [0; 4096];
+
+    let mut hasher = RecordHeader::hasher(header, FLAG_SYNC_MARKER, 0);
+    let mut remaining = len as usize;
+    while remaining > 0 {
+        let n = remaining.min(zeros.len());
+        hasher.write(&zeros[..n]);
+        remaining -= n;
+    }
+
+    RecordHeader {
+        checksum: hasher.finish(),
+        flags: FLAG_SYNC_MARKER,
+        timestamp: 0,
+        key_len: len,
+        val_len: 0,
+    }
+}
+
+/// Returns a batch marker record header for a batch of `len` bytes of records, along with the
+/// data to be written after it.
+pub(crate) fn batch_marker(header: &Header, len: u64) -> (RecordHeader, ByteString) {
+    let mut data = BATCH_MARKER_KEY.to_vec();
+    data.extend_from_slice(&len.to_le_bytes());
+
+    let mut hasher = RecordHeader::hasher(header, FLAG_BATCH_MARKER, 0);
+    hasher.write(&data);
+
+    let record = RecordHeader {
+        checksum: hasher.finish(),
+        flags: FLAG_BATCH_MARKER,
+        timestamp: 0,
+        key_len: BATCH_MARKER_KEY.len() as u32,
+        val_len: 8,
+    };
+
+    (record, data)
+}
+
+/// Returns the length in bytes of the batch of records following the given batch marker. The
+/// checksum is expected to be verified.
+pub(crate) fn batch_len(record: &RecordHeader, data: &ByteStr) -> io::Result<u64> {
+    if record.key_len as usize != BATCH_MARKER_KEY.len() || record.val_len != 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid batch marker",
+        ));
+    }
+
+    Ok(LittleEndian::read_u64(&data[BATCH_MARKER_KEY.len()..]))
+}
+
+/// Decodes the data following a record header, of the format described by the given file
+/// header, into the keyspace, the key and the value of the record, decrypting and
+/// decompressing them as required. The checksum is expected to be verified.
+pub(crate) fn decode_record<'a>(
+    header: &Header,
+    record: &RecordHeader,
+    cipher: Option<&Cipher>,
+    data: &'a ByteStr,
+) -> io::Result<(u32, Cow<'a, ByteStr>, Cow<'a, ByteStr>)> {
+    let (key, stored_value) = if record.is_encrypted() {
+        let (key, stored_value) = decrypt_record(header, record, cipher, data)?;
+        (Cow::Owned(key), Cow::Owned(stored_value))
+    } else {
+        let (key, stored_value) = data.split_at(record.key_len as usize);
+        (Cow::Borrowed(key), Cow::Borrowed(stored_value))
+    };
+
+    let (keyspace, key) = split_keyspace(record, key)?;
+
+    let value = match record.compression() {
+        Compression::None => stored_value,
+        compression => Cow::Owned(compression.decompress(&stored_value)?),
+    };
+
+    Ok((keyspace, key, value))
+}
+
+/// Decrypts the encrypted data of a record, of the format described by the given file header,
+/// returning its key and stored value.
+pub(crate) fn decrypt_record(
+    header: &Header,
+    record: &RecordHeader,
+    cipher: Option<&Cipher>,
+    data: &ByteStr,
+) -> io::Result<(ByteString, ByteString)> {
+    let cipher = cipher.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "encrypted record encountered, but no encryption key was supplied",
+        )
+    })?;
+
+    let mut key = cipher.open(data, &record.associated_data(header))?;
+    if key.len() as u64 != record.key_len as u64 + record.val_len as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "decrypted record length does not match the record header",
+        ));
+    }
+
+    let stored_value = key.split_off(record.key_len as usize);
+
+    Ok((key, stored_value))
+}
+
+/// Splits the keyspace identifier from the key of a record of a named keyspace.
+fn split_keyspace<'a>(
+    record: &RecordHeader,
+    key: Cow<'a, ByteStr>,
+) -> io::Result<(u32, Cow<'a, ByteStr>)> {
+    if !record.has_keyspace() {
+        return Ok((DEFAULT_KEYSPACE_ID, key));
+    }
+
+    if key.len() < KEYSPACE_ID_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "record key too short for its keyspace identifier",
+        ));
+    }
+
+    let keyspace = LittleEndian::read_u32(&key[..KEYSPACE_ID_LEN]);
+    let key = match key {
+        Cow::Borrowed(key) => Cow::Borrowed(&key[KEYSPACE_ID_LEN..]),
+        Cow::Owned(mut key) => Cow::Owned(key.split_off(KEYSPACE_ID_LEN)),
+    };
+
+    Ok((keyspace, key))
+}
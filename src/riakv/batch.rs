@@ -0,0 +1,315 @@
+//! Scripts of puts and deletes applied to a store in a single open.
+
+use libriakv::{Encoding, RiaKV, WriteBatch};
+
+use clap::ValueEnum;
+use serde_derive::Deserialize;
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::ExitCode;
+
+use crate::shell::split_words;
+use crate::StoreArgs;
+
+/// Format of a batch script.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ScriptFormat {
+    /// one operation per line: put KEY VALUE or delete KEY, quoted and escaped as in the shell
+    Lines,
+
+    /// a JSON document: {"encoding":"utf8","operations":[{"op":"put","key":..,"value":..}]}
+    Json,
+}
+
+/// Operation of a batch script.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum JsonOperation {
+    Put { key: String, value: String },
+    Delete { key: String },
+}
+
+/// Batch script as a JSON document.
+#[derive(Debug, Deserialize)]
+struct JsonScript {
+    #[serde(default)]
+    encoding: Encoding,
+    operations: Vec<JsonOperation>,
+}
+
+/// Operation of a batch script, decoded.
+#[derive(Debug, PartialEq, Eq)]
+enum Operation {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// Reads the whole script, then applies its operations in order. Unless `atomic`, a failing
+/// operation leaves the operations before it applied.
+pub fn run(
+    args: &StoreArgs,
+    script: &Path,
+    format: ScriptFormat,
+    atomic: bool,
+) -> io::Result<ExitCode> {
+    let text = if script.as_os_str() == "-" {
+        let mut text = String::new();
+        io::stdin().lock().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(script).map_err(|err| crate::with_path(script, err))?
+    };
+
+    let operations = match format {
+        ScriptFormat::Lines => parse_lines(&text)?,
+        ScriptFormat::Json => parse_json(&text)?,
+    };
+
+    let mut store = crate::open_store_for_writing(args)?;
+
+    if atomic {
+        let mut batch = WriteBatch::new();
+        for operation in &operations {
+            match operation {
+                Operation::Put(key, value) => batch.insert(key, value),
+                Operation::Delete(key) => batch.delete(key),
+            }
+        }
+
+        store.write_batch(&batch)?;
+    } else {
+        for (i, operation) in operations.iter().enumerate() {
+            apply(&mut store, operation).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("operation {}: {}, {} applied before", i + 1, err, i),
+                )
+            })?;
+        }
+    }
+
+    crate::persist_index(&mut store, args)?;
+
+    eprintln!("applied {} operations", operations.len());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn apply(store: &mut RiaKV<fs::File>, operation: &Operation) -> io::Result<()> {
+    match operation {
+        Operation::Put(key, value) => store.insert(key, value),
+        Operation::Delete(key) => store.delete(key),
+    }
+}
+
+fn parse_lines(text: &str) -> io::Result<Vec<Operation>> {
+    let mut operations = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let operation =
+            parse_line(line).map_err(|err| invalid_data(format!("line {}: {}", i + 1, err)))?;
+        operations.push(operation);
+    }
+
+    Ok(operations)
+}
+
+fn parse_line(line: &str) -> io::Result<Operation> {
+    match &split_words(line)?[..] {
+        [op, key, value] if op == b"put" || op == b"update" => put(key.clone(), value.clone()),
+        [op, key] if op == b"delete" => Ok(Operation::Delete(key.clone())),
+        [op, ..] => Err(invalid_data(format!(
+            "invalid operation {}, expected put KEY VALUE or delete KEY",
+            op.escape_ascii()
+        ))),
+        [] => unreachable!("blank lines are skipped"),
+    }
+}
+
+fn parse_json(text: &str) -> io::Result<Vec<Operation>> {
+    let script: JsonScript = serde_json::from_str(text)?;
+    let encoding = script.encoding;
+
+    script
+        .operations
+        .into_iter()
+        .enumerate()
+        .map(|(i, operation)| {
+            decode_operation(encoding, operation)
+                .map_err(|err| invalid_data(format!("operation {}: {}", i + 1, err)))
+        })
+        .collect()
+}
+
+fn decode_operation(encoding: Encoding, operation: JsonOperation) -> io::Result<Operation> {
+    match operation {
+        JsonOperation::Put { key, value } => put(encoding.decode(&key)?, encoding.decode(&value)?),
+        JsonOperation::Delete { key } => Ok(Operation::Delete(encoding.decode(&key)?)),
+    }
+}
+
+fn put(key: Vec<u8>, value: Vec<u8>) -> io::Result<Operation> {
+    if value.is_empty() {
+        return Err(invalid_data(
+            "empty values are stored as tombstones, use delete instead",
+        ));
+    }
+
+    Ok(Operation::Put(key, value))
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Operation::{Delete, Put};
+
+    fn put_op(key: &[u8], value: &[u8]) -> Operation {
+        Put(key.to_vec(), value.to_vec())
+    }
+
+    fn delete_op(key: &[u8]) -> Operation {
+        Delete(key.to_vec())
+    }
+
+    fn error(result: io::Result<Vec<Operation>>) -> String {
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        err.to_string()
+    }
+
+    #[test]
+    fn parse_put_and_delete_lines() {
+        let script = "put a 1\n\n  # comment\nupdate b 2\ndelete a\n\t\n";
+        assert_eq!(
+            parse_lines(script).expect("parse_lines"),
+            [put_op(b"a", b"1"), put_op(b"b", b"2"), delete_op(b"a")]
+        );
+
+        assert!(parse_lines("").expect("parse_lines").is_empty());
+    }
+
+    #[test]
+    fn parse_quoted_lines() {
+        let script = "put \"some key\" \"some value\"\nput k\\x00 \"\\\"v\\\"\"\ndelete \"\"";
+        assert_eq!(
+            parse_lines(script).expect("parse_lines"),
+            [
+                put_op(b"some key", b"some value"),
+                put_op(b"k\0", b"\"v\""),
+                delete_op(b"")
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_malformed_lines() {
+        let err = error(parse_lines("put a 1\nput a"));
+        assert!(err.starts_with("line 2: invalid operation put"), "{}", err);
+
+        let err = error(parse_lines("delete a b"));
+        assert!(
+            err.starts_with("line 1: invalid operation delete"),
+            "{}",
+            err
+        );
+
+        let err = error(parse_lines("put a \"\""));
+        assert!(err.contains("use delete instead"), "{}", err);
+
+        let err = parse_lines("put \"a 1").unwrap_err();
+        assert!(err.to_string().contains("unterminated quote"));
+
+        let err = parse_lines("put a\\").unwrap_err();
+        assert!(err.to_string().contains("trailing backslash"));
+    }
+
+    #[test]
+    fn parse_unknown_ops() {
+        let err = error(parse_lines("put a 1\n\ninsert b 2"));
+        assert!(
+            err.starts_with("line 3: invalid operation insert"),
+            "{}",
+            err
+        );
+
+        let err = error(parse_json(r#"{"operations":[{"op":"insert","key":"a"}]}"#));
+        assert!(err.contains("insert"), "{}", err);
+    }
+
+    #[test]
+    fn parse_json_scripts() {
+        let script = r#"{"operations":[
+            {"op":"put","key":"a","value":"1"},
+            {"op":"delete","key":"b"}
+        ]}"#;
+        assert_eq!(
+            parse_json(script).expect("parse_json"),
+            [put_op(b"a", b"1"), delete_op(b"b")]
+        );
+
+        let script = r#"{"encoding":"hex","operations":[{"op":"put","key":"00ff","value":"41"}]}"#;
+        assert_eq!(
+            parse_json(script).expect("parse_json"),
+            [put_op(b"\x00\xff", b"A")]
+        );
+
+        let script = r#"{"encoding":"base64","operations":[{"op":"put","key":"a2V5","value":""}]}"#;
+        let err = error(parse_json(script));
+        assert!(err.starts_with("operation 1:"), "{}", err);
+        assert!(err.contains("use delete instead"), "{}", err);
+
+        let script = r#"{"encoding":"hex","operations":[{"op":"delete","key":"a"}]}"#;
+        assert!(error(parse_json(script)).starts_with("operation 1:"));
+
+        assert!(parse_json(r#"{"operations":[{"op":"put","key":"a"}]}"#).is_err());
+        assert!(parse_json("put a 1").is_err());
+    }
+
+    #[test]
+    fn bad_line_aborts_batch() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let args = StoreArgs {
+            store: dir.path().join("store.db"),
+            index_file: None,
+        };
+
+        let script = dir.path().join("script");
+        fs::write(&script, "put a 1\nput b 2\nput c\nput d 4\n").expect("write");
+
+        for atomic in [false, true] {
+            let err = run(&args, &script, ScriptFormat::Lines, atomic).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().starts_with("line 3:"), "{}", err);
+            assert!(!args.store.exists());
+        }
+
+        let mut store = crate::open_store_for_writing(&args).expect("open");
+        store.insert(b"a", b"0").expect("insert");
+        let before = fs::read(&args.store).expect("read");
+
+        for atomic in [false, true] {
+            assert!(run(&args, &script, ScriptFormat::Lines, atomic).is_err());
+            assert_eq!(fs::read(&args.store).expect("read"), before);
+        }
+
+        fs::write(&script, "put a 1\ndelete a\nput b 2\n").expect("write");
+        run(&args, &script, ScriptFormat::Lines, false).expect("run");
+
+        let store = crate::open_store(&args).expect("open");
+        assert_eq!(store.index.len(), 1);
+    }
+}
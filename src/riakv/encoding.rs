@@ -0,0 +1,285 @@
+//! Encodings of keys and values on the command line, standard input and standard output.
+
+use libriakv::Encoding;
+
+use clap::ValueEnum;
+use serde_json::json;
+
+use std::io::{self, Write};
+
+/// Encoding of keys and values read from arguments, files or standard input.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Input {
+    /// bytes as given
+    #[default]
+    Raw,
+
+    /// bytes as given, which must be valid UTF-8
+    Utf8,
+
+    /// hexadecimal digits
+    Hex,
+
+    /// standard base64 with padding
+    Base64,
+}
+
+impl Input {
+    /// Decodes a key or value given as an argument.
+    pub fn decode_arg(self, arg: &str) -> io::Result<Vec<u8>> {
+        self.decode(arg.as_bytes().to_vec())
+    }
+
+    /// Decodes a key or value read from a file or standard input. Text encodings may be
+    /// surrounded by whitespace, e.g. a trailing newline.
+    pub fn decode(self, bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+        let encoding = match self {
+            Input::Raw => return Ok(bytes),
+            Input::Utf8 => Encoding::Utf8,
+            Input::Hex => Encoding::Hex,
+            Input::Base64 => Encoding::Base64,
+        };
+
+        let text = String::from_utf8(bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        match encoding {
+            Encoding::Utf8 => Ok(text.into_bytes()),
+            encoding => encoding.decode(text.trim()),
+        }
+    }
+}
+
+/// Encoding of keys and values written to standard output.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Output {
+    /// bytes as stored, without a trailing newline for single values
+    Raw,
+
+    /// UTF-8 text, failing for values which are not valid UTF-8
+    #[default]
+    Utf8,
+
+    /// printable ASCII, with every other byte escaped
+    Escaped,
+
+    /// hexadecimal digits
+    Hex,
+
+    /// standard base64 with padding
+    Base64,
+
+    /// a JSON object per pair, with UTF-8 text if possible, base64 otherwise
+    Json,
+}
+
+impl Output {
+    /// Writes a single value.
+    pub fn write_value<W: Write>(self, w: &mut W, key: &[u8], value: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Raw => w.write_all(value),
+            Output::Json => self.write_pair(w, key, value),
+            _ => writeln!(w, "{}", self.encode(value)?),
+        }
+    }
+
+    /// Writes a key value pair as a line: the key and the value separated by a tab, or a JSON
+    /// object.
+    pub fn write_pair<W: Write>(self, w: &mut W, key: &[u8], value: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Raw => {
+                w.write_all(key)?;
+                w.write_all(b"\t")?;
+                w.write_all(value)?;
+                w.write_all(b"\n")
+            }
+            Output::Json => {
+                let encoding = match (std::str::from_utf8(key), std::str::from_utf8(value)) {
+                    (Ok(_), Ok(_)) => Encoding::Utf8,
+                    _ => Encoding::Base64,
+                };
+
+                let pair = json!({
+                    "key": encoding.encode(key)?,
+                    "value": encoding.encode(value)?,
+                    "encoding": encoding,
+                });
+                writeln!(w, "{}", pair)
+            }
+            _ => writeln!(w, "{}\t{}", self.encode(key)?, self.encode(value)?),
+        }
+    }
+
+    /// Writes a record as a line: its offset, the size of its value, whether it is live or
+    /// deleted, its key if given and its value, separated by tabs, or a JSON object.
+    pub fn write_record<W: Write>(
+        self,
+        w: &mut W,
+        offset: u64,
+        key: Option<&[u8]>,
+        value: &[u8],
+    ) -> io::Result<()> {
+        let status = if value.is_empty() { "deleted" } else { "live" };
+
+        match self {
+            Output::Raw => {
+                write!(w, "{}\t{}\t{}\t", offset, value.len(), status)?;
+                if let Some(key) = key {
+                    w.write_all(key)?;
+                    w.write_all(b"\t")?;
+                }
+                w.write_all(value)?;
+                w.write_all(b"\n")
+            }
+            Output::Json => {
+                let utf8 = key.is_none_or(|key| std::str::from_utf8(key).is_ok())
+                    && std::str::from_utf8(value).is_ok();
+                let encoding = if utf8 {
+                    Encoding::Utf8
+                } else {
+                    Encoding::Base64
+                };
+
+                let mut record = json!({
+                    "offset": offset,
+                    "size": value.len(),
+                    "deleted": value.is_empty(),
+                    "value": encoding.encode(value)?,
+                    "encoding": encoding,
+                });
+                if let Some(key) = key {
+                    record["key"] = json!(encoding.encode(key)?);
+                }
+                writeln!(w, "{}", record)
+            }
+            _ => {
+                write!(w, "{}\t{}\t{}\t", offset, value.len(), status)?;
+                if let Some(key) = key {
+                    write!(w, "{}\t", self.encode(key)?)?;
+                }
+                writeln!(w, "{}", self.encode(value)?)
+            }
+        }
+    }
+
+    /// Encodes the given bytes as text.
+    pub fn encode(self, bytes: &[u8]) -> io::Result<String> {
+        let encoding = match self {
+            Output::Escaped => return Ok(bytes.escape_ascii().to_string()),
+            Output::Hex => Encoding::Hex,
+            Output::Base64 => Encoding::Base64,
+            _ => Encoding::Utf8,
+        };
+
+        encoding.encode(bytes).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("{}, use --output hex or base64 for binary data", err),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINARY: &[u8] = &[0, 1, 0x7f, 0x80, 0xfe, 0xff];
+
+    fn encoded(output: Output, key: &[u8], value: &[u8]) -> String {
+        let mut w = Vec::new();
+        output.write_value(&mut w, key, value).expect("write_value");
+        String::from_utf8(w).expect("UTF-8 output")
+    }
+
+    #[test]
+    fn round_trips() {
+        for (input, output) in [(Input::Hex, Output::Hex), (Input::Base64, Output::Base64)] {
+            for bytes in [&b""[..], b"value", BINARY] {
+                let text = output.encode(bytes).expect("encode");
+                assert_eq!(input.decode_arg(&text).expect("decode"), bytes);
+
+                // values read from files may end with a newline
+                let line = encoded(output, b"key", bytes);
+                assert_eq!(input.decode(line.into_bytes()).expect("decode"), bytes);
+            }
+        }
+
+        assert_eq!(Input::Raw.decode(BINARY.to_vec()).expect("decode"), BINARY);
+        assert_eq!(
+            Input::Utf8.decode_arg("välue").expect("decode"),
+            "välue".as_bytes()
+        );
+        assert_eq!(encoded(Output::Utf8, b"key", "välue".as_bytes()), "välue\n");
+
+        let mut raw = Vec::new();
+        Output::Raw
+            .write_value(&mut raw, b"key", BINARY)
+            .expect("write_value");
+        assert_eq!(raw, BINARY);
+
+        assert_eq!(encoded(Output::Hex, b"key", BINARY), "00017f80feff\n");
+        assert_eq!(encoded(Output::Base64, b"key", b"value"), "dmFsdWU=\n");
+        assert_eq!(
+            encoded(Output::Escaped, b"key", BINARY),
+            "\\x00\\x01\\x7f\\x80\\xfe\\xff\n"
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let line = encoded(Output::Json, b"key", b"value");
+        let pair: serde_json::Value = serde_json::from_str(&line).expect("JSON");
+        assert_eq!(pair["key"], "key");
+        assert_eq!(pair["value"], "value");
+        assert_eq!(pair["encoding"], "utf8");
+
+        let line = encoded(Output::Json, b"key", BINARY);
+        let pair: serde_json::Value = serde_json::from_str(&line).expect("JSON");
+        assert_eq!(pair["key"], "a2V5");
+        assert_eq!(pair["encoding"], "base64");
+        assert_eq!(
+            Input::Base64
+                .decode_arg(pair["value"].as_str().expect("value"))
+                .expect("decode"),
+            BINARY
+        );
+    }
+
+    #[test]
+    fn invalid_hex() {
+        for text in ["abc", "0g", "zz"] {
+            let err = Input::Hex.decode_arg(text).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", text);
+        }
+    }
+
+    #[test]
+    fn invalid_base64() {
+        for text in ["dmFsdWU", "dm!sdWU=", "dmFs dWU="] {
+            let err = Input::Base64.decode_arg(text).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", text);
+        }
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        for input in [Input::Utf8, Input::Hex, Input::Base64] {
+            let err = input.decode(BINARY.to_vec()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let mut w = Vec::new();
+        let err = Output::Utf8
+            .write_value(&mut w, b"key", BINARY)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("use --output hex or base64"));
+        assert!(w.is_empty());
+
+        let err = Output::Utf8
+            .write_pair(&mut Vec::new(), BINARY, b"value")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,968 @@
+use libriakv::{
+    ConflictPolicy, Damage, DumpOptions, DumpSummary, Encoding, IndexMismatch, MigrateOptions,
+    RiaKV, SizeHistogram, StoreOptions,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+mod batch;
+mod encoding;
+mod shell;
+
+use batch::ScriptFormat;
+use encoding::{Input, Output};
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const EXIT_STATUS: &str = "\
+Exit status:
+    0  success
+    1  key not found, damage found by verify, or differences found by diff
+    2  usage or I/O error";
+
+/// CLI client for RiaKV key value stores.
+#[derive(Parser)]
+#[command(name = "riakv", version, after_help = EXIT_STATUS)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct StoreArgs {
+    /// Storage file of the store
+    #[arg(value_name = "STORE")]
+    store: PathBuf,
+
+    /// Index log loaded instead of scanning the whole storage file, and updated after writes
+    #[arg(long, value_name = "INDEX_FILE")]
+    index_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ValueArgs {
+    /// Value to write
+    #[arg(required_unless_present = "value_file")]
+    value: Option<String>,
+
+    /// File to read the value from instead, or - for standard input
+    #[arg(long, value_name = "FILE", conflicts_with = "value")]
+    value_file: Option<PathBuf>,
+
+    /// Encoding of the key and value
+    #[arg(long, value_enum, default_value_t)]
+    input: Input,
+}
+
+impl ValueArgs {
+    /// Reads and decodes the value.
+    fn read(&self) -> io::Result<Vec<u8>> {
+        let bytes = match (&self.value, &self.value_file) {
+            (Some(value), _) => value.as_bytes().to_vec(),
+            (None, Some(path)) if path.as_os_str() == "-" => {
+                let mut bytes = Vec::new();
+                io::stdin().lock().read_to_end(&mut bytes)?;
+                bytes
+            }
+            (None, Some(path)) => fs::read(path).map_err(|err| with_path(path, err))?,
+            (None, None) => Vec::new(),
+        };
+
+        let value = self.input.decode(bytes)?;
+
+        if value.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty values are stored as tombstones, use delete instead",
+            ));
+        }
+
+        Ok(value)
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the value for a key
+    Get {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Key to read
+        key: String,
+
+        /// Encoding of the key
+        #[arg(long, value_enum, default_value_t)]
+        input: Input,
+
+        /// Encoding of the value
+        #[arg(long, value_enum, default_value_t)]
+        output: Output,
+    },
+
+    /// Insert a key value pair, creating the store if it does not exist
+    Put {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Key to write
+        key: String,
+
+        #[command(flatten)]
+        value: ValueArgs,
+    },
+
+    /// Update the value for a key, creating the store if it does not exist
+    Update {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Key to write
+        key: String,
+
+        #[command(flatten)]
+        value: ValueArgs,
+    },
+
+    /// Delete a key
+    Delete {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Key to delete
+        key: String,
+
+        /// Encoding of the key
+        #[arg(long, value_enum, default_value_t)]
+        input: Input,
+    },
+
+    /// Print the latest record of every live key, ordered by key
+    ///
+    /// Every record is printed with its offset, the size of its value, whether it is deleted,
+    /// its key and its value.
+    Scan {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Only print keys starting with the given prefix
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// Print at most the given number of keys
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+
+        /// Print deleted keys as well, along with their latest tombstone. Scans the whole
+        /// storage file, even with an index log
+        #[arg(long)]
+        include_deleted: bool,
+
+        /// Encoding of the prefix
+        #[arg(long, value_enum, default_value_t)]
+        input: Input,
+
+        /// Encoding of the keys and values
+        #[arg(long, value_enum, default_value_t = Output::Escaped)]
+        output: Output,
+    },
+
+    /// Print every record for a key, oldest first
+    ///
+    /// Every record is printed with its offset, the size of its value, whether it is a
+    /// tombstone and its value.
+    History {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Key to read
+        key: String,
+
+        /// Encoding of the key
+        #[arg(long, value_enum, default_value_t)]
+        input: Input,
+
+        /// Encoding of the values
+        #[arg(long, value_enum, default_value_t = Output::Escaped)]
+        output: Output,
+    },
+
+    /// Print statistics about the records in the storage file, and the space compaction would
+    /// reclaim
+    Stats {
+        #[command(flatten)]
+        store: StoreArgs,
+    },
+
+    /// Run get, put, delete, scan, history and stats commands interactively on an open store
+    Shell {
+        #[command(flatten)]
+        store: StoreArgs,
+    },
+
+    /// Apply a script of puts and deletes, opening the store once
+    Batch {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Script to apply, or - for standard input
+        #[arg(value_name = "FILE")]
+        script: PathBuf,
+
+        /// Format of the script
+        #[arg(long, value_enum, default_value_t = ScriptFormat::Lines)]
+        format: ScriptFormat,
+
+        /// Write all the operations as a single batch, which is either applied as a whole or
+        /// not at all, even if the write is torn by a crash
+        #[arg(long)]
+        atomic: bool,
+    },
+
+    /// Rewrite the storage file keeping only the latest record of every live key
+    Compact {
+        #[command(flatten)]
+        store: StoreArgs,
+    },
+
+    /// Check every record of the storage file, and the index log if given
+    Verify {
+        #[command(flatten)]
+        store: StoreArgs,
+    },
+
+    /// Rewrite a storage file of an older format version in the latest format version
+    Migrate {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Keep every record in the order written, including overwritten values and
+        /// tombstones, instead of only the latest record of every live key
+        #[arg(long)]
+        history: bool,
+
+        /// Only report what the migration would change, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Write every live key value pair as JSON or CSV
+    Export {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Format of the export
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+
+        /// Encoding of keys and values: utf8, hex or base64
+        #[arg(long, default_value_t = Encoding::Utf8)]
+        encoding: Encoding,
+
+        /// File to write to, instead of standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Insert the key value pairs of a JSON or CSV export
+    Import {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Format of the export
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+
+        /// Encoding of keys and values for CSV: utf8, hex or base64. JSON exports record their
+        /// encoding
+        #[arg(long, default_value_t = Encoding::Utf8)]
+        encoding: Encoding,
+
+        /// File to read from, instead of standard input
+        input: Option<PathBuf>,
+    },
+
+    /// Compare the live key value pairs of the default keyspace of two stores, printing every
+    /// differing key
+    ///
+    /// Named keyspaces are ignored.
+    Diff {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Storage file of the store to compare with
+        #[arg(value_name = "OTHER")]
+        other: PathBuf,
+    },
+
+    /// Merge the live key value pairs of the default keyspace of another store into the store
+    ///
+    /// Named keyspaces are ignored. Merged records keep the timestamps they were written at in
+    /// the other store.
+    Merge {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Storage file of the store to merge
+        #[arg(value_name = "OTHER")]
+        other: PathBuf,
+
+        /// Value kept for keys with different values in both stores
+        #[arg(long, value_enum)]
+        policy: Policy,
+    },
+
+    /// Write a versioned, checksummed dump of every keyspace, for backups and restores
+    Dump {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Dump every record in the order written, including overwritten values and
+        /// tombstones, instead of only the live key value pairs
+        #[arg(long)]
+        history: bool,
+
+        /// File to write to, instead of standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Create a new store from a dump, only once the whole dump is validated
+    Restore {
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Dump to restore, or - for standard input (the default)
+        input: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Policy {
+    /// keep the value of the store merged into
+    Left,
+
+    /// take the value of the other store
+    Right,
+
+    /// take the value written last, keeping the value of the store merged into for equal
+    /// timestamps
+    Latest,
+}
+
+/// Opens the storage file for reading, without loading the index. A missing storage file is
+/// an error instead of being created, and so is an empty one instead of being given a header.
+fn open_storage(args: &StoreArgs) -> io::Result<RiaKV<File>> {
+    File::open(&args.store)
+        .and_then(|file| {
+            if file.metadata()?.len() == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "empty file: not a riakv storage file",
+                ));
+            }
+
+            RiaKV::open_from_storage(file, StoreOptions::default())
+        })
+        .map_err(|err| with_path(&args.store, err))
+}
+
+/// Opens the store for reading, and loads its index.
+fn open_store(args: &StoreArgs) -> io::Result<RiaKV<File>> {
+    let mut store = open_storage(args)?;
+
+    load_index(&mut store, args)?;
+    Ok(store)
+}
+
+/// Opens the store for reading and writing, creating the storage file if required.
+fn open_store_for_writing(args: &StoreArgs) -> io::Result<RiaKV<File>> {
+    let mut store =
+        RiaKV::open_from_file_at_path(&args.store).map_err(|err| with_path(&args.store, err))?;
+
+    load_index(&mut store, args)?;
+    Ok(store)
+}
+
+/// Loads the index from the index log if given, falling back to scanning the storage file.
+fn load_index(store: &mut RiaKV<File>, args: &StoreArgs) -> io::Result<()> {
+    if let Some(index_file) = &args.index_file {
+        match store.load_index_log(index_file) {
+            Ok(()) => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => eprintln!(
+                "riakv: unable to load {}, rebuilding the index from storage: {}",
+                index_file.display(),
+                err
+            ),
+        }
+    }
+
+    store.load().map_err(|err| with_path(&args.store, err))
+}
+
+/// Persists the index to the index log, if given.
+fn persist_index(store: &mut RiaKV<File>, args: &StoreArgs) -> io::Result<()> {
+    match &args.index_file {
+        Some(index_file) => store
+            .persist_index_log(index_file)
+            .map_err(|err| with_path(index_file, err)),
+        None => Ok(()),
+    }
+}
+
+fn with_path(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
+
+fn get(args: &StoreArgs, key: &str, input: Input, output: Output) -> io::Result<ExitCode> {
+    let key = input.decode_arg(key)?;
+    let mut store = open_store(args)?;
+
+    match store.get(&key)? {
+        None => {
+            eprintln!("riakv: {} not found", key.escape_ascii());
+            Ok(ExitCode::from(1))
+        }
+        Some(value) => {
+            let mut stdout = io::stdout().lock();
+            output.write_value(&mut stdout, &key, &value)?;
+            stdout.flush()?;
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+fn put(args: &StoreArgs, key: &str, value: &ValueArgs) -> io::Result<ExitCode> {
+    let key = value.input.decode_arg(key)?;
+    let value = value.read()?;
+    let mut store = open_store_for_writing(args)?;
+
+    store.insert(&key, &value)?;
+
+    persist_index(&mut store, args)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn delete(args: &StoreArgs, key: &str, input: Input) -> io::Result<ExitCode> {
+    let key = input.decode_arg(key)?;
+    let mut store = open_store_for_writing(args)?;
+
+    store.delete(&key)?;
+
+    persist_index(&mut store, args)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Filters of the scan command.
+struct ScanFilter {
+    prefix: Vec<u8>,
+    limit: Option<usize>,
+    include_deleted: bool,
+}
+
+fn scan(args: &StoreArgs, filter: &ScanFilter, output: Output) -> io::Result<ExitCode> {
+    let (mut store, latest) = if filter.include_deleted {
+        let mut store = open_storage(args)?;
+        let report = store.verify()?;
+
+        let latest: Vec<(Vec<u8>, u64)> = report.index.into_iter().chain(report.deleted).collect();
+        (store, latest)
+    } else {
+        let store = open_store(args)?;
+
+        let latest: Vec<(Vec<u8>, u64)> = store
+            .index
+            .iter()
+            .map(|(key, &position)| (key.clone(), position))
+            .collect();
+        (store, latest)
+    };
+
+    let mut latest: Vec<(Vec<u8>, u64)> = latest
+        .into_iter()
+        .filter(|(key, _)| key.starts_with(&filter.prefix))
+        .collect();
+    latest.sort_unstable();
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    let limit = filter.limit.unwrap_or(usize::MAX);
+    for (key, position) in latest.into_iter().take(limit) {
+        let kv = store.get_at(position)?;
+        output.write_record(&mut stdout, position, Some(&key), &kv.value)?;
+    }
+
+    stdout.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn history(args: &StoreArgs, key: &str, input: Input, output: Output) -> io::Result<ExitCode> {
+    let key = input.decode_arg(key)?;
+    let mut store = open_storage(args)?;
+
+    let history = store.history(&key)?;
+    if history.is_empty() {
+        eprintln!("riakv: {} not found", key.escape_ascii());
+        return Ok(ExitCode::from(1));
+    }
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    for (position, value) in history {
+        output.write_record(&mut stdout, position, None, &value)?;
+    }
+
+    stdout.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn stats(args: &StoreArgs) -> io::Result<ExitCode> {
+    let mut store = open_storage(args)?;
+
+    let mut stdout = io::stdout().lock();
+    write_stats(&mut store, &mut stdout)?;
+    stdout.flush()?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn write_stats<W: Write>(store: &mut RiaKV<File>, w: &mut W) -> io::Result<()> {
+    let stats = store.stats()?;
+
+    writeln!(w, "file size: {}", stats.file_size)?;
+    writeln!(w, "records: {}", stats.records)?;
+    writeln!(w, "tombstones: {}", stats.tombstones)?;
+    writeln!(w, "live keys: {}", stats.live_keys)?;
+    writeln!(w, "live bytes: {}", stats.live_bytes)?;
+    writeln!(w, "stale bytes: {}", stats.stale_bytes)?;
+    writeln!(w, "damaged bytes: {}", stats.damaged_bytes)?;
+    writeln!(
+        w,
+        "reclaimable by compaction: {} bytes ({:.1}%)",
+        stats.reclaimable_bytes,
+        percentage(stats.reclaimable_bytes, stats.file_size)
+    )?;
+
+    write_histogram(w, "key sizes", &stats.key_sizes)?;
+    write_histogram(w, "value sizes", &stats.value_sizes)
+}
+
+fn write_histogram<W: Write>(w: &mut W, name: &str, histogram: &SizeHistogram) -> io::Result<()> {
+    writeln!(w, "{}:", name)?;
+
+    let total = histogram.count();
+    for (smallest, largest, count) in histogram.buckets() {
+        writeln!(
+            w,
+            "  {:>24} bytes: {} ({:.1}%)",
+            format!("{}-{}", smallest, largest),
+            count,
+            percentage(count, total)
+        )?;
+    }
+
+    Ok(())
+}
+
+fn percentage(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+
+    part as f64 * 100.0 / total as f64
+}
+
+fn compact(args: &StoreArgs) -> io::Result<ExitCode> {
+    let before = std::fs::metadata(&args.store)
+        .map_err(|err| with_path(&args.store, err))?
+        .len();
+
+    let mut store = RiaKV::compact_file_at_path(&args.store, StoreOptions::default())
+        .map_err(|err| with_path(&args.store, err))?;
+
+    if let Some(index_file) = &args.index_file {
+        store
+            .checkpoint_index_log(index_file)
+            .map_err(|err| with_path(index_file, err))?;
+    }
+
+    let after = store.seek_to_end()?;
+    println!(
+        "{}: {} bytes compacted to {} bytes",
+        args.store.display(),
+        before,
+        after
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn migrate(args: &StoreArgs, options: MigrateOptions) -> io::Result<ExitCode> {
+    let report = RiaKV::migrate_file_at_path(&args.store, options)
+        .map_err(|err| with_path(&args.store, err))?;
+
+    if !options.dry_run {
+        if let Some(index_file) = &args.index_file {
+            let mut store = open_storage(args)?;
+            store.load().map_err(|err| with_path(&args.store, err))?;
+            store
+                .checkpoint_index_log(index_file)
+                .map_err(|err| with_path(index_file, err))?;
+        }
+    }
+
+    println!(
+        "{}: {} format version {} to {}, {} of {} records kept, {} bytes to {} bytes",
+        args.store.display(),
+        if options.dry_run {
+            "would migrate"
+        } else {
+            "migrated"
+        },
+        report.from_version,
+        report.to_version,
+        report.records - report.dropped_records,
+        report.records,
+        report.source_bytes,
+        report.migrated_bytes
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify(args: &StoreArgs) -> io::Result<ExitCode> {
+    let mut store = open_storage(args)?;
+
+    let report = store.verify()?;
+
+    for damaged in &report.damaged {
+        match &damaged.damage {
+            Damage::Corrupt(reason) => println!(
+                "corrupt records at offset {}: {} bytes skipped ({})",
+                damaged.start,
+                damaged.len(),
+                reason
+            ),
+            Damage::Truncated => println!(
+                "truncated record at offset {}: {} bytes at the end of the storage",
+                damaged.start,
+                damaged.len()
+            ),
+        }
+    }
+
+    let mut index_mismatches = 0;
+
+    if let Some(index_file) = &args.index_file {
+        store
+            .load_index_log(index_file)
+            .map_err(|err| with_path(index_file, err))?;
+
+        let mismatches = report.check_index(&store.index);
+        index_mismatches = mismatches.len();
+
+        for mismatch in mismatches {
+            match mismatch {
+                IndexMismatch::Missing { key, position } => println!(
+                    "index: key \"{}\" missing, latest record at offset {}",
+                    key.escape_ascii(),
+                    position
+                ),
+                IndexMismatch::Stale {
+                    key,
+                    indexed,
+                    latest,
+                } => println!(
+                    "index: key \"{}\" points to offset {}, latest record at offset {}",
+                    key.escape_ascii(),
+                    indexed,
+                    latest
+                ),
+                IndexMismatch::Dangling { key, indexed } => println!(
+                    "index: key \"{}\" points to offset {}, but is not live",
+                    key.escape_ascii(),
+                    indexed
+                ),
+            }
+        }
+    }
+
+    println!(
+        "{}: {} records ({} tombstones), {} live keys, {} damaged ranges, {} index mismatches",
+        args.store.display(),
+        report.records,
+        report.tombstones,
+        report.index.len(),
+        report.damaged.len(),
+        index_mismatches
+    );
+
+    if !report.is_clean() || index_mismatches > 0 {
+        return Ok(ExitCode::from(1));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn export(
+    args: &StoreArgs,
+    format: Format,
+    encoding: Encoding,
+    output: Option<&Path>,
+) -> io::Result<ExitCode> {
+    let mut store = open_store(args)?;
+
+    // the exports buffer their output themselves
+    let mut w: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).map_err(|err| with_path(path, err))?),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let exported = match format {
+        Format::Json => store.export_json(&mut w, encoding)?,
+        Format::Csv => store.export_csv(&mut w, encoding)?,
+    };
+    w.flush()?;
+
+    eprintln!("exported {} pairs", exported);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn import(
+    args: &StoreArgs,
+    format: Format,
+    encoding: Encoding,
+    input: Option<&Path>,
+) -> io::Result<ExitCode> {
+    let r: Box<dyn io::Read> = match input {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).map_err(|err| with_path(path, err))?,
+        )),
+        None => Box::new(io::stdin().lock()),
+    };
+
+    let mut store = open_store_for_writing(args)?;
+
+    let imported = match format {
+        Format::Json => store.import_json(r)?,
+        Format::Csv => store.import_csv(r, encoding)?,
+    };
+
+    persist_index(&mut store, args)?;
+
+    eprintln!("imported {} pairs", imported);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn diff(args: &StoreArgs, other: &Path) -> io::Result<ExitCode> {
+    let mut store = open_store(args)?;
+    let mut other_store = open_other_store(other)?;
+
+    let diff = store.diff(&mut other_store)?;
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    for (marker, keys) in [
+        ('-', &diff.only_left),
+        ('+', &diff.only_right),
+        ('~', &diff.changed),
+    ] {
+        for key in keys {
+            writeln!(stdout, "{}\t{}", marker, key.escape_ascii())?;
+        }
+    }
+    stdout.flush()?;
+
+    eprintln!(
+        "{} only in {}, {} only in {}, {} changed, {} unchanged",
+        diff.only_left.len(),
+        args.store.display(),
+        diff.only_right.len(),
+        other.display(),
+        diff.changed.len(),
+        diff.unchanged
+    );
+
+    if !diff.is_empty() {
+        return Ok(ExitCode::from(1));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn merge(args: &StoreArgs, other: &Path, policy: Policy) -> io::Result<ExitCode> {
+    let mut store = open_store_for_writing(args)?;
+    let mut other_store = open_other_store(other)?;
+
+    let policy = match policy {
+        Policy::Left => ConflictPolicy::LeftWins,
+        Policy::Right => ConflictPolicy::RightWins,
+        Policy::Latest => ConflictPolicy::LatestTimestamp,
+    };
+
+    let summary = store.merge(&mut other_store, policy)?;
+    persist_index(&mut store, args)?;
+
+    eprintln!(
+        "merged {} new keys, {} overwritten, {} kept",
+        summary.inserted, summary.overwritten, summary.kept
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Opens the other store of diff and merge for reading, scanning its storage file to load its
+/// index.
+fn open_other_store(path: &Path) -> io::Result<RiaKV<File>> {
+    open_store(&StoreArgs {
+        store: path.to_path_buf(),
+        index_file: None,
+    })
+}
+
+fn dump(args: &StoreArgs, history: bool, output: Option<&Path>) -> io::Result<ExitCode> {
+    let mut store = open_store(args)?;
+
+    let w: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).map_err(|err| with_path(path, err))?),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let summary = store.dump(w, DumpOptions { history })?;
+
+    eprintln!("dumped {}", describe_dump(&summary));
+    Ok(ExitCode::SUCCESS)
+}
+
+fn restore(args: &StoreArgs, input: Option<&Path>) -> io::Result<ExitCode> {
+    let r: Box<dyn io::Read> = match input {
+        Some(path) if path.as_os_str() != "-" => {
+            Box::new(File::open(path).map_err(|err| with_path(path, err))?)
+        }
+        _ => Box::new(io::stdin().lock()),
+    };
+
+    let (mut store, summary) = RiaKV::restore_file_at_path(&args.store, StoreOptions::default(), r)
+        .map_err(|err| with_path(&args.store, err))?;
+
+    if let Some(index_file) = &args.index_file {
+        store
+            .checkpoint_index_log(index_file)
+            .map_err(|err| with_path(index_file, err))?;
+    }
+
+    eprintln!("restored {}", describe_dump(&summary));
+    Ok(ExitCode::SUCCESS)
+}
+
+fn describe_dump(summary: &DumpSummary) -> String {
+    let mut description = format!(
+        "{} pairs in {} named keyspaces",
+        summary.pairs, summary.keyspaces
+    );
+
+    if summary.history {
+        description.push_str(&format!(
+            ", with history and {} tombstones",
+            summary.tombstones
+        ));
+    }
+
+    description
+}
+
+fn run(command: Command) -> io::Result<ExitCode> {
+    match command {
+        Command::Get {
+            store,
+            key,
+            input,
+            output,
+        } => get(&store, &key, input, output),
+        Command::Put { store, key, value } => put(&store, &key, &value),
+        Command::Update { store, key, value } => put(&store, &key, &value),
+        Command::Delete { store, key, input } => delete(&store, &key, input),
+        Command::Scan {
+            store,
+            prefix,
+            limit,
+            include_deleted,
+            input,
+            output,
+        } => {
+            let filter = ScanFilter {
+                prefix: prefix
+                    .map(|prefix| input.decode_arg(&prefix))
+                    .transpose()?
+                    .unwrap_or_default(),
+                limit,
+                include_deleted,
+            };
+            scan(&store, &filter, output)
+        }
+        Command::History {
+            store,
+            key,
+            input,
+            output,
+        } => history(&store, &key, input, output),
+        Command::Stats { store } => stats(&store),
+        Command::Shell { store } => shell::run(&store),
+        Command::Batch {
+            store,
+            script,
+            format,
+            atomic,
+        } => batch::run(&store, &script, format, atomic),
+        Command::Compact { store } => compact(&store),
+        Command::Verify { store } => verify(&store),
+        Command::Migrate {
+            store,
+            history,
+            dry_run,
+        } => migrate(&store, MigrateOptions { history, dry_run }),
+        Command::Export {
+            store,
+            format,
+            encoding,
+            output,
+        } => export(&store, format, encoding, output.as_deref()),
+        Command::Import {
+            store,
+            format,
+            encoding,
+            input,
+        } => import(&store, format, encoding, input.as_deref()),
+        Command::Diff { store, other } => diff(&store, &other),
+        Command::Merge {
+            store,
+            other,
+            policy,
+        } => merge(&store, &other, policy),
+        Command::Dump {
+            store,
+            history,
+            output,
+        } => dump(&store, history, output.as_deref()),
+        Command::Restore { store, input } => restore(&store, input.as_deref()),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli.command) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("riakv: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
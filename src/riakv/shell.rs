@@ -0,0 +1,335 @@
+//! Interactive shell over a single open store, keeping the index in memory between commands.
+
+use libriakv::RiaKV;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+
+use crate::encoding::Output;
+use crate::StoreArgs;
+
+const PROMPT: &str = "riakv> ";
+
+const COMMANDS: [&[u8]; 9] = [
+    b"get", b"put", b"delete", b"scan", b"history", b"stats", b"help", b"exit", b"quit",
+];
+
+const HELP: &str = "\
+Commands:
+    get KEY          print the value for a key
+    put KEY VALUE    insert a key value pair
+    delete KEY       delete a key
+    scan [PREFIX]    print every live key value pair, optionally only keys with the prefix
+    history KEY      print every record for a key, oldest first
+    stats            print statistics about the records in the storage file
+    help             print this help
+    exit             leave the shell
+
+Keys and values are separated by whitespace. Use double quotes for keys and values with
+whitespace, and \\\\, \\\", \\n, \\t, \\r, \\0 or \\xNN escapes for other bytes.";
+
+/// Reads and runs commands until the end of input or `exit`. Errors of single commands are
+/// printed without leaving the shell.
+pub fn run(args: &StoreArgs) -> io::Result<ExitCode> {
+    let mut store = crate::open_store_for_writing(args)?;
+    let mut editor = DefaultEditor::new().map_err(readline_to_io_error)?;
+
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(readline_to_io_error(err)),
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        editor
+            .add_history_entry(line.as_str())
+            .map_err(readline_to_io_error)?;
+
+        let words = match split_words(&line) {
+            Ok(words) => words,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                continue;
+            }
+        };
+
+        match run_command(&mut store, args, &words) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => eprintln!("error: {}", err),
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs a single command, returning whether the shell should keep going.
+fn run_command(store: &mut RiaKV<File>, args: &StoreArgs, words: &[Vec<u8>]) -> io::Result<bool> {
+    let (command, operands) = match words.split_first() {
+        Some((command, operands)) => (command.as_slice(), operands),
+        None => return Ok(true),
+    };
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    match (command, operands) {
+        (b"get", [key]) => match store.get(key)? {
+            Some(value) => writeln!(stdout, "{}", value.escape_ascii())?,
+            None => writeln!(stdout, "{} not found", key.escape_ascii())?,
+        },
+        (b"put", [key, value]) => {
+            if value.is_empty() {
+                return Err(invalid_input(
+                    "empty values are stored as tombstones, use delete instead",
+                ));
+            }
+
+            store.insert(key, value)?;
+            crate::persist_index(store, args)?;
+        }
+        (b"delete", [key]) => {
+            store.delete(key)?;
+            crate::persist_index(store, args)?;
+        }
+        (b"scan", []) => scan(store, &mut stdout, b"")?,
+        (b"scan", [prefix]) => scan(store, &mut stdout, prefix)?,
+        (b"history", [key]) => {
+            for (position, value) in store.history(key)? {
+                Output::Escaped.write_record(&mut stdout, position, None, &value)?;
+            }
+        }
+        (b"stats", []) => crate::write_stats(store, &mut stdout)?,
+        (b"help", []) => writeln!(stdout, "{}", HELP)?,
+        (b"exit", []) | (b"quit", []) => return Ok(false),
+        _ if COMMANDS.contains(&command) => {
+            return Err(invalid_input(format!(
+                "wrong number of operands for {}, try help",
+                command.escape_ascii()
+            )))
+        }
+        _ => {
+            return Err(invalid_input(format!(
+                "unknown command {}, try help",
+                command.escape_ascii()
+            )))
+        }
+    }
+
+    stdout.flush()?;
+    Ok(true)
+}
+
+fn scan<W: Write>(store: &mut RiaKV<File>, w: &mut W, prefix: &[u8]) -> io::Result<()> {
+    let mut keys: Vec<Vec<u8>> = store
+        .index
+        .keys()
+        .filter(|key| key.starts_with(prefix))
+        .cloned()
+        .collect();
+    keys.sort_unstable();
+
+    for key in keys {
+        if let Some(value) = store.get(&key)? {
+            Output::Escaped.write_pair(w, &key, &value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits a line into words separated by whitespace. Double quotes group words with
+/// whitespace, and backslash escapes stand for single bytes, both within and outside quotes.
+pub fn split_words(line: &str) -> io::Result<Vec<Vec<u8>>> {
+    let mut words = Vec::new();
+    let mut word: Option<Vec<u8>> = None;
+    let mut quoted = false;
+
+    let mut bytes = line.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(Vec::new);
+            }
+            b'\\' => {
+                let escaped = match bytes.next() {
+                    Some(b'n') => b'\n',
+                    Some(b't') => b'\t',
+                    Some(b'r') => b'\r',
+                    Some(b'0') => b'\0',
+                    Some(b'x') => {
+                        let digits = [bytes.next(), bytes.next()];
+                        match digits {
+                            [Some(high), Some(low)] => hex_byte(high, low)?,
+                            _ => return Err(invalid_input("incomplete \\x escape")),
+                        }
+                    }
+                    Some(byte) => byte,
+                    None => return Err(invalid_input("trailing backslash")),
+                };
+
+                word.get_or_insert_with(Vec::new).push(escaped);
+            }
+            byte if byte.is_ascii_whitespace() && !quoted => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            byte => word.get_or_insert_with(Vec::new).push(byte),
+        }
+    }
+
+    if quoted {
+        return Err(invalid_input("unterminated quote"));
+    }
+
+    words.extend(word);
+    Ok(words)
+}
+
+fn hex_byte(high: u8, low: u8) -> io::Result<u8> {
+    let digits = [high, low];
+    std::str::from_utf8(&digits)
+        .ok()
+        .filter(|digits| digits.bytes().all(|digit| digit.is_ascii_hexdigit()))
+        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| invalid_input("invalid \\x escape, expected two hexadecimal digits"))
+}
+
+fn invalid_input<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+fn readline_to_io_error(err: ReadlineError) -> io::Error {
+    match err {
+        ReadlineError::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<Vec<u8>> {
+        split_words(line).expect("split_words")
+    }
+
+    fn error(line: &str) -> String {
+        let err = split_words(line).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        err.to_string()
+    }
+
+    #[test]
+    fn split_words_on_whitespace() {
+        assert_eq!(words("put key value"), [&b"put"[..], b"key", b"value"]);
+        assert_eq!(words("  get \t key  "), [&b"get"[..], b"key"]);
+        assert!(words("").is_empty());
+        assert!(words(" \t ").is_empty());
+    }
+
+    #[test]
+    fn split_quoted_words() {
+        assert_eq!(
+            words("put \"some key\" \"a  value\""),
+            [&b"put"[..], b"some key", b"a  value"]
+        );
+        assert_eq!(words("put key\" \"value"), [&b"put"[..], b"key value"]);
+        assert_eq!(words("put \"\" \"\""), [&b"put"[..], b"", b""]);
+        assert_eq!(words("\"\""), [&b""[..]]);
+        assert_eq!(
+            words("get \"\\\"quoted\\\"\""),
+            [&b"get"[..], b"\"quoted\""]
+        );
+    }
+
+    #[test]
+    fn split_escaped_words() {
+        assert_eq!(
+            words("put \\x00\\xfF key\\ with\\tspace"),
+            [&b"put"[..], b"\x00\xff", b"key with\tspace"]
+        );
+        assert_eq!(
+            words("put \"\\n\\r\\0\\\\\" \\x41"),
+            [&b"put"[..], b"\n\r\0\\", b"A"]
+        );
+    }
+
+    #[test]
+    fn split_invalid_words() {
+        assert!(error("get \\x4").contains("incomplete \\x escape"));
+        assert!(error("get \\x").contains("incomplete \\x escape"));
+        assert!(error("get \\xzz").contains("invalid \\x escape"));
+        assert!(error("get \\x+f").contains("invalid \\x escape"));
+        assert!(error("get key\\").contains("trailing backslash"));
+        assert!(error("get \"key").contains("unterminated quote"));
+        assert!(error("put \"key\" \"").contains("unterminated quote"));
+    }
+
+    #[test]
+    fn hex_bytes() {
+        assert_eq!(hex_byte(b'0', b'0').expect("hex_byte"), 0);
+        assert_eq!(hex_byte(b'7', b'F').expect("hex_byte"), 0x7f);
+        assert_eq!(hex_byte(b'f', b'f').expect("hex_byte"), 0xff);
+        assert!(hex_byte(b'g', b'0').is_err());
+        assert!(hex_byte(b'-', b'1').is_err());
+        assert!(hex_byte(b' ', b'1').is_err());
+    }
+
+    #[test]
+    fn operand_counts() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let args = StoreArgs {
+            store: dir.path().join("store.db"),
+            index_file: None,
+        };
+        let mut store = crate::open_store_for_writing(&args).expect("open");
+
+        for line in [
+            "get",
+            "get key other",
+            "put key",
+            "put key value other",
+            "delete",
+            "scan prefix other",
+            "history",
+            "stats other",
+            "help me",
+            "exit now",
+        ] {
+            let err = run_command(&mut store, &args, &words(line)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", line);
+            assert!(
+                err.to_string().starts_with("wrong number of operands"),
+                "{}",
+                line
+            );
+        }
+
+        let err = run_command(&mut store, &args, &words("unknown")).unwrap_err();
+        assert!(err.to_string().starts_with("unknown command unknown"));
+
+        let err = run_command(&mut store, &args, &words("put key \"\"")).unwrap_err();
+        assert!(err.to_string().contains("use delete instead"));
+
+        assert!(run_command(&mut store, &args, &[]).expect("empty"));
+        assert!(run_command(&mut store, &args, &words("put key value")).expect("put"));
+        assert_eq!(store.get(b"key").expect("get"), Some(b"value".to_vec()));
+        assert!(run_command(&mut store, &args, &words("delete key")).expect("delete"));
+        assert_eq!(store.get(b"key").expect("get"), None);
+        assert!(!run_command(&mut store, &args, &words("quit")).expect("quit"));
+    }
+}
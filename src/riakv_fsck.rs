@@ -0,0 +1,175 @@
+use libriakv::{Damage, IndexMismatch, RiaKV, StoreOptions, INDEX_LOG_MAGIC};
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::process;
+
+#[cfg(target_os = "windows")]
+const USAGE: &str = "
+Offline integrity checker for RiaKV storage files.
+
+Usage:
+    riakv_fsck.exe STORAGE_FILE [--index INDEX_FILE] [--repair OUTPUT_FILE]
+";
+
+#[cfg(not(target_os = "windows"))]
+const USAGE: &str = "
+Offline integrity checker for RiaKV storage files.
+
+Usage:
+    riakv_fsck STORAGE_FILE [--index INDEX_FILE] [--repair OUTPUT_FILE]
+";
+
+struct Args {
+    storage_fname: String,
+    index_fname: Option<String>,
+    repair_fname: Option<String>,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut args = std::env::args().skip(1);
+
+    let storage_fname = args.next()?;
+    let mut index_fname = None;
+    let mut repair_fname = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--index" => index_fname = Some(args.next()?),
+            "--repair" => repair_fname = Some(args.next()?),
+            _ => return None,
+        }
+    }
+
+    Some(Args {
+        storage_fname,
+        index_fname,
+        repair_fname,
+    })
+}
+
+/// Writes a repaired copy of the store to the file at the given path, which must not exist.
+fn repair(store: &mut RiaKV<File>, path: &Path) -> io::Result<()> {
+    let mut output = OpenOptions::new().write(true).create_new(true).open(path)?;
+
+    store.repair_into(&mut output)?;
+    output.sync_all()
+}
+
+/// Loads the index file at the given path, which is either an index log or a full index.
+fn load_index(store: &mut RiaKV<File>, path: &Path) -> io::Result<()> {
+    let mut index_file = BufReader::new(File::open(path)?);
+
+    if index_file.fill_buf()?.starts_with(INDEX_LOG_MAGIC) {
+        return store.load_index_log(path);
+    }
+
+    store
+        .load_index(&mut index_file)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn main() {
+    let args = match parse_args() {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let storage_path = Path::new(&args.storage_fname);
+    let mut store = File::open(storage_path)
+        .and_then(|file| RiaKV::open_from_storage(file, StoreOptions::default()))
+        .unwrap_or_else(|err| {
+            eprintln!("unable to open {}: {}", args.storage_fname, err);
+            process::exit(2);
+        });
+
+    let report = store.verify().unwrap_or_else(|err| {
+        eprintln!("unable to scan {}: {}", args.storage_fname, err);
+        process::exit(2);
+    });
+
+    for damaged in &report.damaged {
+        match &damaged.damage {
+            Damage::Corrupt(reason) => println!(
+                "corrupt records at offset {}: {} bytes skipped ({})",
+                damaged.start,
+                damaged.len(),
+                reason
+            ),
+            Damage::Truncated => println!(
+                "truncated record at offset {}: {} bytes at the end of the storage",
+                damaged.start,
+                damaged.len()
+            ),
+        }
+    }
+
+    let mut index_mismatches = 0;
+
+    if let Some(index_fname) = &args.index_fname {
+        let loaded = load_index(&mut store, Path::new(index_fname));
+
+        match loaded {
+            Ok(()) => {
+                let mismatches = report.check_index(&store.index);
+                index_mismatches = mismatches.len();
+
+                for mismatch in mismatches {
+                    match mismatch {
+                        IndexMismatch::Missing { key, position } => println!(
+                            "index: key \"{}\" missing, latest record at offset {}",
+                            key.escape_ascii(),
+                            position
+                        ),
+                        IndexMismatch::Stale {
+                            key,
+                            indexed,
+                            latest,
+                        } => println!(
+                            "index: key \"{}\" points to offset {}, latest record at offset {}",
+                            key.escape_ascii(),
+                            indexed,
+                            latest
+                        ),
+                        IndexMismatch::Dangling { key, indexed } => println!(
+                            "index: key \"{}\" points to offset {}, but is not live",
+                            key.escape_ascii(),
+                            indexed
+                        ),
+                    }
+                }
+            }
+            Err(err) => {
+                println!("index: unable to load {}: {}", index_fname, err);
+                index_mismatches = 1;
+            }
+        }
+    }
+
+    println!(
+        "{}: {} records ({} tombstones), {} live keys, {} damaged ranges, {} index mismatches",
+        args.storage_fname,
+        report.records,
+        report.tombstones,
+        report.index.len(),
+        report.damaged.len(),
+        index_mismatches
+    );
+
+    if let Some(repair_fname) = &args.repair_fname {
+        if let Err(err) = repair(&mut store, Path::new(repair_fname)) {
+            eprintln!("unable to write repaired copy {}: {}", repair_fname, err);
+            process::exit(2);
+        }
+
+        println!("repaired copy written to {}", repair_fname);
+    }
+
+    if !report.is_clean() || index_mismatches > 0 {
+        process::exit(1);
+    }
+}
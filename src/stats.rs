@@ -0,0 +1,85 @@
+//! Statistics about the records stored in a storage file.
+
+/// Number of buckets in a `SizeHistogram`: one for empty sizes, and one for every bit length
+/// of a `u64` size.
+const SIZE_BUCKETS: usize = 65;
+
+/// Histogram of key or value sizes, with buckets doubling in width: sizes of 0 bytes, of 1
+/// byte, of 2 to 3 bytes, of 4 to 7 bytes, and so on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeHistogram {
+    counts: [u64; SIZE_BUCKETS],
+}
+
+impl Default for SizeHistogram {
+    fn default() -> Self {
+        SizeHistogram {
+            counts: [0; SIZE_BUCKETS],
+        }
+    }
+}
+
+impl SizeHistogram {
+    /// Counts the given size into its bucket.
+    pub fn add(&mut self, size: u64) {
+        let bucket = (u64::BITS - size.leading_zeros()) as usize;
+        self.counts[bucket] += 1;
+    }
+
+    /// Returns the total number of sizes counted.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns every bucket holding at least one size, in order, as the smallest and largest
+    /// size of the bucket along with the number of sizes counted into it.
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(bucket, &count)| match bucket {
+                0 => (0, 0, count),
+                bucket => (1 << (bucket - 1), u64::MAX >> (64 - bucket), count),
+            })
+    }
+}
+
+/// Statistics about the records stored in a storage file, returned by `RiaKV::stats`.
+///
+/// Record sizes include the record header, and the data as stored, i.e. after compression and
+/// encryption. Key and value sizes are those of the decoded keys and values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// length of the storage file
+    pub file_size: u64,
+
+    /// number of valid records, excluding sync markers
+    pub records: u64,
+
+    /// number of valid records which are tombstones
+    pub tombstones: u64,
+
+    /// number of live keys, in the default keyspace and every named keyspace
+    pub live_keys: u64,
+
+    /// bytes taken by the latest record of every live key, and by the keyspace catalog
+    pub live_bytes: u64,
+
+    /// bytes taken by valid records which are superseded, tombstones, or belong to dropped
+    /// keyspaces
+    pub stale_bytes: u64,
+
+    /// bytes taken by damaged ranges, as reported by `RiaKV::verify`
+    pub damaged_bytes: u64,
+
+    /// estimate of the bytes compaction would reclaim: everything except the header and the
+    /// live records. Sync markers written into the compacted file are not accounted for
+    pub reclaimable_bytes: u64,
+
+    /// sizes of the live keys
+    pub key_sizes: SizeHistogram,
+
+    /// sizes of the values of the live keys
+    pub value_sizes: SizeHistogram,
+}
@@ -0,0 +1,160 @@
+//! Streaming access to values stored in a `RiaKV` store.
+
+use std::io;
+use std::io::prelude::*;
+
+use crate::checksum::ChecksumHasher;
+use crate::compression::{Compression, Decoder};
+use crate::record::check_checksum;
+use crate::ByteString;
+
+/// Reader over the value of a single record, returned by `RiaKV::get_reader`. Compressed
+/// values are decompressed as they are read.
+///
+/// The checksum of the record is computed incrementally as the stored value is read and
+/// verified once the last byte of the stored value has been read. A mismatch is reported as
+/// an `io::ErrorKind::InvalidData` error from the final read.
+///
+/// Encrypted values can only be authenticated as a whole, so they are decrypted into memory
+/// before the reader is returned and only decompressed as they are read.
+pub struct ValueReader<R: Read> {
+    decoder: Decoder<ValueSource<R>>,
+    stored_len: u64,
+}
+
+impl<R: Read> ValueReader<R> {
+    /// Creates a new `ValueReader` reading a value stored in `stored_len` bytes from `inner`.
+    /// The hasher is expected to already contain everything preceding the stored value
+    /// which is covered by the checksum.
+    pub(crate) fn new(
+        inner: R,
+        stored_len: u64,
+        compression: Compression,
+        hasher: ChecksumHasher,
+        saved_checksum: u64,
+    ) -> io::Result<Self> {
+        let stored = StoredValueReader {
+            inner: inner.take(stored_len),
+            hasher,
+            saved_checksum,
+            verified: false,
+        };
+
+        Ok(ValueReader {
+            decoder: compression.decoder(ValueSource::Stored(stored))?,
+            stored_len,
+        })
+    }
+
+    /// Creates a new `ValueReader` over a stored value which has already been read, verified
+    /// and decrypted.
+    pub(crate) fn from_stored_value(
+        stored_value: ByteString,
+        compression: Compression,
+    ) -> io::Result<Self> {
+        let stored_len = stored_value.len() as u64;
+
+        Ok(ValueReader {
+            decoder: compression.decoder(ValueSource::Decrypted(io::Cursor::new(stored_value)))?,
+            stored_len,
+        })
+    }
+
+    /// Returns the length of the value as stored, i.e. after compression, in bytes.
+    pub fn stored_len(&self) -> u64 {
+        self.stored_len
+    }
+}
+
+impl<R: Read> Read for ValueReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+/// Source of the stored bytes of a value read by a `ValueReader`.
+enum ValueSource<R> {
+    Stored(StoredValueReader<R>),
+    Decrypted(io::Cursor<ByteString>),
+}
+
+impl<R: Read> Read for ValueSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ValueSource::Stored(stored) => stored.read(buf),
+            ValueSource::Decrypted(decrypted) => decrypted.read(buf),
+        }
+    }
+}
+
+/// Reader over the stored bytes of a value, verifying the checksum of the record at the end.
+struct StoredValueReader<R> {
+    inner: io::Take<R>,
+    hasher: ChecksumHasher,
+    saved_checksum: u64,
+    verified: bool,
+}
+
+impl<R: Read> StoredValueReader<R> {
+    fn verify(&mut self) -> io::Result<()> {
+        self.verified = true;
+
+        check_checksum(self.hasher.finish(), self.saved_checksum)
+    }
+}
+
+impl<R: Read> Read for StoredValueReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.write(&buf[..n]);
+
+        if self.inner.limit() == 0 {
+            if !self.verified {
+                self.verify()?;
+            }
+        } else if n == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "value truncated in storage",
+            ));
+        }
+
+        Ok(n)
+    }
+}
+
+/// Writer feeding everything written through it into a checksum hasher, while counting the
+/// number of bytes written.
+pub(crate) struct HashingWriter<'a, W> {
+    inner: W,
+    hasher: &'a mut ChecksumHasher,
+    written: u64,
+}
+
+impl<'a, W: Write> HashingWriter<'a, W> {
+    pub(crate) fn new(inner: W, hasher: &'a mut ChecksumHasher) -> Self {
+        HashingWriter {
+            inner,
+            hasher,
+            written: 0,
+        }
+    }
+
+    /// Returns the number of bytes written so far.
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl<'a, W: Write> Write for HashingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.write(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,236 @@
+//! Typed access to a `RiaKV` store, with keys and values encoded with serde.
+
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{ByteStr, ByteString, RiaKV};
+
+/// Codec used by `TypedRiaKV` for encoding keys and values into bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    /// compact binary encoding with `bincode`
+    #[default]
+    Bincode,
+
+    /// JSON encoding with `serde_json`, readable with the untyped API and exports
+    Json,
+}
+
+impl Codec {
+    /// Returns the name of this codec.
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Bincode => "bincode",
+            Codec::Json => "json",
+        }
+    }
+
+    /// Encodes the given value into bytes.
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> io::Result<ByteString> {
+        match self {
+            Codec::Bincode => {
+                bincode::serialize(value).map_err(|err| crate::bincode_to_io_error(*err))
+            }
+            Codec::Json => serde_json::to_vec(value).map_err(io::Error::from),
+        }
+    }
+
+    /// Decodes a value from the given bytes. Bytes which do not hold a value of the expected
+    /// type result in an `InvalidData` error.
+    pub fn decode<T: DeserializeOwned>(self, bytes: &ByteStr) -> io::Result<T> {
+        match self {
+            Codec::Bincode => {
+                bincode::deserialize(bytes).map_err(|err| crate::bincode_to_io_error(*err))
+            }
+            Codec::Json => serde_json::from_slice(bytes).map_err(io::Error::from),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bincode" => Ok(Codec::Bincode),
+            "json" => Ok(Codec::Json),
+            _ => Err(format!("unknown codec {}, expected bincode or json", s)),
+        }
+    }
+}
+
+/// Key value store with typed keys and values, over a `RiaKV` store. Keys and values are
+/// encoded with a `Codec` before they are stored.
+///
+/// Keys are looked up by their encoding, so key types have to encode equal keys into the same
+/// bytes, e.g. they must not contain a `HashMap`.
+#[derive(Debug)]
+pub struct TypedRiaKV<K, V, F>
+where
+    F: Read + Write + Seek,
+{
+    store: RiaKV<F>,
+    codec: Codec,
+    marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V, F> TypedRiaKV<K, V, F>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    F: Read + Write + Seek,
+{
+    /// Creates a new `TypedRiaKV` instance over the given store, encoding keys and values
+    /// with the given codec. The index of the store is expected to be loaded.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::{Codec, RiaKV, TypedRiaKV};
+    ///
+    /// let store = RiaKV::open_from_in_memory_buffer(5000);
+    /// let mut typed: TypedRiaKV<u32, String, _> = TypedRiaKV::new(store, Codec::Bincode);
+    ///
+    /// typed.insert(&7, &"seven".to_string()).expect("insert");
+    /// assert_eq!(typed.get(&7).expect("get").unwrap(), "seven");
+    /// ```
+    pub fn new(store: RiaKV<F>, codec: Codec) -> Self {
+        TypedRiaKV {
+            store,
+            codec,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the codec keys and values are encoded with.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Returns the underlying store.
+    pub fn store(&mut self) -> &mut RiaKV<F> {
+        &mut self.store
+    }
+
+    /// Consumes this instance, returning the underlying store.
+    pub fn into_inner(self) -> RiaKV<F> {
+        self.store
+    }
+
+    /// Gets the value for the given key.
+    pub fn get(&mut self, key: &K) -> io::Result<Option<V>> {
+        let key = self.codec.encode(key)?;
+
+        match self.store.get(&key)? {
+            None => Ok(None),
+            Some(value) => self.codec.decode(&value).map(Some),
+        }
+    }
+
+    /// Inserts the given key value pair. Values which encode into no bytes at all would be
+    /// stored as tombstones, so they are refused with an `InvalidInput` error.
+    pub fn insert(&mut self, key: &K, value: &V) -> io::Result<()> {
+        let key = self.codec.encode(key)?;
+        let value = self.codec.encode(value)?;
+
+        if value.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "value encodes into no bytes and cannot be told apart from a tombstone",
+            ));
+        }
+
+        self.store.insert(&key, &value)
+    }
+
+    /// Deletes the value for the given key.
+    pub fn delete(&mut self, key: &K) -> io::Result<()> {
+        let key = self.codec.encode(key)?;
+
+        self.store.delete(&key)
+    }
+
+    /// Returns an iterator over every live key value pair, ordered by the encoded keys. Pairs
+    /// are read from the storage as the iterator advances.
+    ///
+    /// # Example
+    /// ```
+    /// use libriakv::{Codec, RiaKV, TypedRiaKV};
+    ///
+    /// let store = RiaKV::open_from_in_memory_buffer(5000);
+    /// let mut typed = TypedRiaKV::new(store, Codec::Json);
+    /// typed.insert(&"key".to_string(), &vec![1u8, 2, 3]).expect("insert");
+    ///
+    /// for pair in typed.iter() {
+    ///     let (key, value): (String, Vec<u8>) = pair.expect("pair");
+    /// }
+    /// ```
+    pub fn iter(&mut self) -> TypedIter<'_, K, V, F> {
+        let mut entries: Vec<(&ByteString, &u64)> = self.store.index.iter().collect();
+        entries.sort();
+
+        let positions: Vec<u64> = entries.into_iter().map(|(_, &position)| position).collect();
+
+        TypedIter {
+            store: &mut self.store,
+            codec: self.codec,
+            positions: positions.into_iter(),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Iterator over the live key value pairs of a `TypedRiaKV` store, returned by
+/// `TypedRiaKV::iter`.
+pub struct TypedIter<'a, K, V, F>
+where
+    F: Read + Write + Seek,
+{
+    store: &'a mut RiaKV<F>,
+    codec: Codec,
+    positions: std::vec::IntoIter<u64>,
+    marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V, F> Iterator for TypedIter<'_, K, V, F>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    F: Read + Write + Seek,
+{
+    type Item = io::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for position in self.positions.by_ref() {
+            let kv = match self.store.get_at(position) {
+                Ok(kv) => kv,
+                Err(err) => return Some(Err(err)),
+            };
+
+            // deleted keys point to their tombstone until the index is reloaded
+            if kv.value.is_empty() {
+                continue;
+            }
+
+            let pair = self
+                .codec
+                .decode(&kv.key)
+                .and_then(|key| Ok((key, self.codec.decode(&kv.value)?)));
+
+            return Some(pair);
+        }
+
+        None
+    }
+}
@@ -0,0 +1,385 @@
+//! Integrity checking of the records stored in a storage file.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use crate::crypto::Cipher;
+use crate::header::Header;
+use crate::record::{self, RecordHeader};
+use crate::{ByteString, KeyValuePair};
+
+/// Number of bytes read at once while searching for the next valid record.
+const RESYNC_WINDOW_LEN: usize = 64 * 1024;
+
+/// Kind of damage found in a range of the storage file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Damage {
+    /// one or more records which cannot be read, with the reason the first one was refused
+    Corrupt(String),
+
+    /// a record cut short by the end of the storage file, e.g. by a torn write
+    Truncated,
+}
+
+/// Range of the storage file which does not hold valid records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DamagedRange {
+    /// offset of the first damaged byte
+    pub start: u64,
+
+    /// offset right after the last damaged byte, i.e. of the next valid record
+    pub end: u64,
+
+    /// kind of damage found
+    pub damage: Damage,
+}
+
+impl DamagedRange {
+    /// Returns the number of bytes in this range.
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Returns whether this range is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// Disagreement between a persisted index and the records found in the storage file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexMismatch {
+    /// a live key in the storage file is not in the index
+    Missing { key: ByteString, position: u64 },
+
+    /// the index points to an older record for a key than the latest one
+    Stale {
+        key: ByteString,
+        indexed: u64,
+        latest: u64,
+    },
+
+    /// the index holds a key which is deleted or absent in the storage file
+    Dangling { key: ByteString, indexed: u64 },
+}
+
+/// Report of a full scan over the records stored in a storage file, returned by
+/// `RiaKV::verify`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// number of valid records found
+    pub records: u64,
+
+    /// number of valid records which are tombstones
+    pub tombstones: u64,
+
+    /// ranges of the storage file without valid records, in order
+    pub damaged: Vec<DamagedRange>,
+
+    /// index built from the valid records of the default keyspace, mapping live keys to their
+    /// latest record
+    pub index: HashMap<ByteString, u64>,
+
+    /// length of the storage file
+    pub end: u64,
+
+    /// mapping from deleted keys to their latest tombstone
+    pub deleted: HashMap<ByteString, u64>,
+}
+
+impl VerifyReport {
+    /// Returns whether no damage was found.
+    pub fn is_clean(&self) -> bool {
+        self.damaged.is_empty()
+    }
+
+    /// Compares the given index, e.g. as loaded from a persisted index file, against the
+    /// index built from the valid records. Mismatches are returned ordered by key.
+    ///
+    /// Deleted keys may be absent from the given index, or point to their latest tombstone,
+    /// as they do in the index of a `RiaKV` instance after `RiaKV::delete`.
+    pub fn check_index(&self, index: &HashMap<ByteString, u64>) -> Vec<IndexMismatch> {
+        let mut mismatches = Vec::new();
+
+        for (key, &position) in &self.index {
+            match index.get(key) {
+                None => mismatches.push(IndexMismatch::Missing {
+                    key: key.clone(),
+                    position,
+                }),
+                Some(&indexed) if indexed != position => mismatches.push(IndexMismatch::Stale {
+                    key: key.clone(),
+                    indexed,
+                    latest: position,
+                }),
+                Some(_) => {}
+            }
+        }
+
+        for (key, &indexed) in index {
+            if !self.index.contains_key(key) && self.deleted.get(key) != Some(&indexed) {
+                mismatches.push(IndexMismatch::Dangling {
+                    key: key.clone(),
+                    indexed,
+                });
+            }
+        }
+
+        mismatches.sort_by(|a, b| a.key().cmp(b.key()));
+        mismatches
+    }
+}
+
+impl IndexMismatch {
+    /// Returns the key this mismatch is about.
+    pub fn key(&self) -> &ByteString {
+        match self {
+            IndexMismatch::Missing { key, .. } => key,
+            IndexMismatch::Stale { key, .. } => key,
+            IndexMismatch::Dangling { key, .. } => key,
+        }
+    }
+}
+
+/// Valid record found while scanning a storage file.
+pub(crate) struct ScannedRecord {
+    /// position of the record
+    pub(crate) position: u64,
+
+    /// header of the record
+    pub(crate) record: RecordHeader,
+
+    /// data following the record header, as stored
+    pub(crate) data: ByteString,
+
+    /// keyspace of the record
+    pub(crate) keyspace: u32,
+
+    /// decoded key and value of the record
+    pub(crate) kv: KeyValuePair,
+}
+
+/// Item found while scanning a storage file with `scan_storage`.
+pub(crate) enum ScanItem {
+    Record(ScannedRecord),
+    Damaged(DamagedRange),
+}
+
+/// Reason a record could not be read at some position.
+enum Invalid {
+    /// the record extends past the end of the storage
+    Truncated,
+
+    /// the record header or data is corrupt; the record boundaries cannot be trusted
+    Corrupt(String),
+
+    /// the record boundaries and checksum are valid, but its data cannot be decoded
+    Undecodable(String, u64),
+}
+
+/// Scans every record of the given storage, starting right after the header. Unlike
+/// `RiaKV::for_each_kv_entry_in_storage`, damaged records do not end the scan: they are
+/// reported as damaged ranges, and the scan resumes at the next position holding a record
+/// with a valid checksum, e.g. a sync marker. Sync markers and batch markers are not passed to
+/// the callback. A batch extending past the end of the storage is reported as truncated, from
+/// its marker onwards.
+/// The callback returns whether to continue the scan.
+///
+/// Only errors from the underlying storage are returned. Returns the length of the storage.
+pub(crate) fn scan_storage<R, Func>(
+    f: &mut R,
+    header: &Header,
+    cipher: Option<&Cipher>,
+    mut callback: Func,
+) -> io::Result<u64>
+where
+    R: Read + Seek,
+    Func: FnMut(ScanItem) -> bool,
+{
+    let end = f.seek(SeekFrom::End(0))?;
+    let mut position = header.len();
+    f.seek(SeekFrom::Start(position))?;
+
+    while position < end {
+        let item = match read_record(f, header, cipher, position, end)? {
+            Ok(scanned) => {
+                position += header.record_header_len() + scanned.record.data_len();
+
+                if scanned.record.is_sync_marker() {
+                    continue;
+                }
+
+                if scanned.record.is_batch_marker() {
+                    let torn = match record::batch_len(&scanned.record, &scanned.data) {
+                        Ok(len) if position + len > end => Damage::Truncated,
+                        Ok(_) => continue,
+                        Err(err) => Damage::Corrupt(err.to_string()),
+                    };
+
+                    // the records of a torn batch are not read, as if they were missing
+                    if torn == Damage::Truncated {
+                        position = end;
+                        f.seek(SeekFrom::Start(position))?;
+                    }
+
+                    ScanItem::Damaged(DamagedRange {
+                        start: scanned.position,
+                        end: position,
+                        damage: torn,
+                    })
+                } else {
+                    ScanItem::Record(scanned)
+                }
+            }
+            Err(invalid) => {
+                let start = position;
+
+                let damage = match invalid {
+                    Invalid::Undecodable(reason, next) => {
+                        position = next;
+                        Damage::Corrupt(reason)
+                    }
+                    Invalid::Truncated => {
+                        position = resync(f, header, start + 1, end)?;
+                        if position == end {
+                            Damage::Truncated
+                        } else {
+                            Damage::Corrupt("record extends past the end of storage".to_string())
+                        }
+                    }
+                    Invalid::Corrupt(reason) => {
+                        position = resync(f, header, start + 1, end)?;
+                        Damage::Corrupt(reason)
+                    }
+                };
+
+                f.seek(SeekFrom::Start(position))?;
+
+                ScanItem::Damaged(DamagedRange {
+                    start,
+                    end: position,
+                    damage,
+                })
+            }
+        };
+
+        if !callback(item) {
+            break;
+        }
+    }
+
+    Ok(end)
+}
+
+/// Reads the record at the given position, which is expected to be the current position of
+/// the storage. On success, the storage is left at the end of the record.
+fn read_record<R: Read + Seek>(
+    f: &mut R,
+    header: &Header,
+    cipher: Option<&Cipher>,
+    position: u64,
+    end: u64,
+) -> io::Result<Result<ScannedRecord, Invalid>> {
+    let (record, data) = match read_record_data(f, header, position, end)? {
+        Ok(record_data) => record_data,
+        Err(invalid) => return Ok(Err(invalid)),
+    };
+
+    let next = position + header.record_header_len() + record.data_len();
+
+    let (keyspace, kv) = match record::decode_record(header, &record, cipher, &data) {
+        Ok((keyspace, key, value)) => (
+            keyspace,
+            KeyValuePair {
+                key: key.into_owned(),
+                value: value.into_owned(),
+            },
+        ),
+        Err(err) => return Ok(Err(Invalid::Undecodable(err.to_string(), next))),
+    };
+
+    Ok(Ok(ScannedRecord {
+        position,
+        record,
+        data,
+        keyspace,
+        kv,
+    }))
+}
+
+/// Reads the header and data of the record at the given position, which is expected to be
+/// the current position of the storage, and verifies its checksum.
+fn read_record_data<R: Read>(
+    f: &mut R,
+    header: &Header,
+    position: u64,
+    end: u64,
+) -> io::Result<Result<(RecordHeader, ByteString), Invalid>> {
+    if position + header.record_header_len() > end {
+        return Ok(Err(Invalid::Truncated));
+    }
+
+    let record = match RecordHeader::read_from(f, header) {
+        Ok(record) => record,
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            return Ok(Err(Invalid::Corrupt(err.to_string())))
+        }
+        Err(err) => return Err(err),
+    };
+
+    if position + header.record_header_len() + record.data_len() > end {
+        return Ok(Err(Invalid::Truncated));
+    }
+
+    let mut data = ByteString::with_capacity(record.data_len() as usize);
+    f.take(record.data_len()).read_to_end(&mut data)?;
+
+    if data.len() as u64 != record.data_len() {
+        return Ok(Err(Invalid::Truncated));
+    }
+
+    match record.verify_checksum(header, &data) {
+        Ok(()) => Ok(Ok((record, data))),
+        Err(err) => Ok(Err(Invalid::Corrupt(err.to_string()))),
+    }
+}
+
+/// Searches for the first position at or after `from` holding a record with a valid checksum,
+/// which fits before the end of the storage. Returns the end of the storage if there is none.
+fn resync<R: Read + Seek>(f: &mut R, header: &Header, from: u64, end: u64) -> io::Result<u64> {
+    let record_header_len = header.record_header_len();
+    let mut window = vec![0; RESYNC_WINDOW_LEN];
+    let mut window_start = from;
+
+    while window_start + record_header_len <= end {
+        let window_len = RESYNC_WINDOW_LEN.min((end - window_start) as usize);
+        f.seek(SeekFrom::Start(window_start))?;
+        f.read_exact(&mut window[..window_len])?;
+
+        let candidates = window_len - record_header_len as usize + 1;
+
+        for offset in 0..candidates {
+            let position = window_start + offset as u64;
+
+            let record = match RecordHeader::read_from(&mut &window[offset..window_len], header) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+
+            if position + record_header_len + record.data_len() > end {
+                continue;
+            }
+
+            f.seek(SeekFrom::Start(position))?;
+            if read_record_data(f, header, position, end)?.is_ok() {
+                return Ok(position);
+            }
+        }
+
+        window_start += candidates as u64;
+    }
+
+    Ok(end)
+}
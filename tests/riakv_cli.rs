@@ -0,0 +1,238 @@
+//! Integration tests running the `riakv` binary.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn riakv(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_riakv"))
+        .args(args)
+        .output()
+        .expect("run riakv")
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().expect("UTF-8 path")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).expect("UTF-8 stdout")
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).expect("UTF-8 stderr")
+}
+
+#[test]
+fn put_get_delete() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let store = dir.path().join("store.db");
+    let store = path(&store);
+
+    let output = riakv(&["put", store, "key", "value"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+    let output = riakv(&["get", store, "key"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "value\n");
+
+    let output = riakv(&["get", store, "missing"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(stderr(&output).contains("missing not found"));
+
+    let output = riakv(&["update", store, "key", "updated"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&riakv(&["get", store, "key"])), "updated\n");
+
+    let output = riakv(&["delete", store, "key"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+    let output = riakv(&["get", store, "key"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("key not found"));
+}
+
+#[test]
+fn put_get_delete_with_index_file() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let store = dir.path().join("store.db");
+    let index = dir.path().join("store.idx");
+    let (store, index) = (path(&store), path(&index));
+
+    let output = riakv(&["put", store, "key", "value", "--index-file", index]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(Path::new(index).exists());
+
+    let output = riakv(&["get", store, "key", "--index-file", index]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "value\n");
+    assert!(output.stderr.is_empty());
+
+    let output = riakv(&["delete", store, "key", "--index-file", index]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+    let output = riakv(&["get", store, "key", "--index-file", index]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn errors() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let store = dir.path().join("store.db");
+    let store = path(&store);
+
+    // reading a missing store does not create it
+    let output = riakv(&["get", store, "key"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains(store));
+    assert!(!Path::new(store).exists());
+
+    let output = riakv(&["delete", store]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = riakv(&["put", store, "key", ""]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("use delete instead"));
+
+    let output = riakv(&["put", store, "key", "zz", "--input", "hex"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = riakv(&["unknown"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = riakv(&["compact", store]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains(store));
+
+    // reading an empty or headerless file is refused without writing to it
+    for contents in [&b""[..], b"not a store"] {
+        std::fs::write(store, contents).expect("write");
+
+        let output = riakv(&["get", store, "key"]);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(stderr(&output).contains("not a riakv storage file"));
+        assert_eq!(std::fs::read(store).expect("read"), contents);
+    }
+}
+
+#[test]
+fn compact() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let store = dir.path().join("store.db");
+    let index = dir.path().join("store.idx");
+    let (store, index) = (path(&store), path(&index));
+
+    for i in 0..8 {
+        let value = format!("value {}", i);
+        let output = riakv(&["put", store, "key", &value]);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    }
+    riakv(&["put", store, "other", "value"]);
+    riakv(&["delete", store, "other"]);
+
+    let before = std::fs::metadata(store).expect("metadata").len();
+
+    let output = riakv(&["compact", store, "--index-file", index]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+    let after = std::fs::metadata(store).expect("metadata").len();
+    assert!(after < before);
+    assert_eq!(
+        stdout(&output),
+        format!("{}: {} bytes compacted to {} bytes\n", store, before, after)
+    );
+
+    let output = riakv(&["get", store, "key", "--index-file", index]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "value 7\n");
+    assert!(output.stderr.is_empty());
+
+    let output = riakv(&["get", store, "other"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = riakv(&["verify", store, "--index-file", index]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
+
+#[test]
+fn scan() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let store = dir.path().join("store.db");
+    let store = path(&store);
+
+    for (key, value) in [
+        ("b1", "2"),
+        ("a", "1"),
+        ("b2", "3"),
+        ("b3", "4"),
+        ("c", "5"),
+    ] {
+        let output = riakv(&["put", store, key, value]);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    }
+    riakv(&["delete", store, "b2"]);
+    riakv(&["put", store, "a", "updated"]);
+
+    // lines hold the offset, the size of the value, the status, the key and the value
+    let scan = |args: &[&str]| {
+        let mut command = vec!["scan", store];
+        command.extend_from_slice(args);
+
+        let output = riakv(&command);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+        stdout(&output)
+            .lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                assert_eq!(fields.len(), 5, "{}", line);
+                assert!(fields[0].parse::<u64>().is_ok(), "{}", line);
+                assert_eq!(fields[1], fields[4].len().to_string(), "{}", line);
+                format!("{} {} {}", fields[2], fields[3], fields[4])
+            })
+            .collect::<Vec<String>>()
+    };
+
+    assert_eq!(
+        scan(&[]),
+        ["live a updated", "live b1 2", "live b3 4", "live c 5"]
+    );
+    assert_eq!(scan(&["--prefix", "b"]), ["live b1 2", "live b3 4"]);
+    assert_eq!(
+        scan(&["--prefix", "62", "--input", "hex"]),
+        scan(&["--prefix", "b"])
+    );
+    assert!(scan(&["--prefix", "d"]).is_empty());
+
+    assert_eq!(scan(&["--limit", "2"]), ["live a updated", "live b1 2"]);
+    assert_eq!(scan(&["--limit", "1", "--prefix", "b"]), ["live b1 2"]);
+    assert_eq!(
+        scan(&["--prefix", "b", "--limit", "5"]),
+        ["live b1 2", "live b3 4"]
+    );
+    assert!(scan(&["--limit", "0"]).is_empty());
+
+    // deleted keys only show up with --include-deleted, along with their tombstone
+    assert_eq!(
+        scan(&["--include-deleted"]),
+        [
+            "live a updated",
+            "live b1 2",
+            "deleted b2 ",
+            "live b3 4",
+            "live c 5"
+        ]
+    );
+    assert_eq!(
+        scan(&["--include-deleted", "--prefix", "b", "--limit", "2"]),
+        ["live b1 2", "deleted b2 "]
+    );
+    assert_eq!(
+        scan(&["--include-deleted", "--prefix", "b2"]),
+        ["deleted b2 "]
+    );
+    assert!(scan(&["--prefix", "b2"]).is_empty());
+
+    let output = riakv(&["scan", store, "--limit", "many"]);
+    assert_eq!(output.status.code(), Some(2));
+}